@@ -15,20 +15,14 @@ push bp
 3: # Int15 loop
     mov ecx, {extended_region_size}     # Buffer size
     mov eax, {function_code}   # Int15 function code
+    mov dword ptr [di + {regular_region_size}], 1    # Mark the entry as enabled, for BIOSes that return regular entries
     clc                        # Clear carry flag just before the interrupt
     int 0x15
-    cmp ecx, {regular_region_size} # Check if the given output is regular or extended
-    jz 4f
-    test dword ptr [di + {regular_region_size}], 1    # Check if this entry should be ignored by extended attributes.
-    jz 4f
-    jmp 5f
-4:
-    inc dword ptr [{len_address}]
     jc 6f                      # Check if an error occured in the carry flag.
     mov edx, {smap}
     cmp eax, edx               # Check the signature to verify successful call
     jnz 6f
-5:
+    inc dword ptr [{len_address}]   # The kernel ignores entries by their extended attributes
     test ebx, ebx              # Check if this is the last entry
     jz 6f
     add di, {extended_region_size}      # Move buffer address forward
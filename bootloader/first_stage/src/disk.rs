@@ -83,16 +83,17 @@ impl DiskAddressPacket {
                 "mov si, {0:x}",
                 // Put function code in `ah`
                 "mov ah, {1}",
-                // Put disk number in `dl`
-                "mov dl, {2}",
                 // Call the `disk interrupt`
-                "int {3}",
+                "int {2}",
                 // Restore si for llvm internal use.
                 "pop si",
                 in(reg) self as *const Self as u16,
                 const DiskInterrupt::ExtendedRead as u8,
-                in(reg_byte) disk_number,
                 const BiosInterrupts::Disk as u8,
+                // The disk number is passed in `dl`
+                inout("dl") disk_number => _,
+                // The status is returned in `ah`
+                out("ax") _,
             )
         }
     }
@@ -66,15 +66,20 @@ unsafe fn load_kernel_dap() {
     let disk_number =
         unsafe { core::ptr::read(DISK_NUMBER_OFFSET as *const u8) };
 
-    #[rustfmt::skip]
-    let kernel_dap = DiskAddressPacket::new(
-        128,
-        0,
-        0x1000,
-        66
-    );
+    // A packet can fill at most a single 64KiB segment, so the kernel is
+    // loaded a segment at a time, from 0x10000 up to the end of the init
+    // area at 0x40000.
+    for segment in 0..3u16 {
+        #[rustfmt::skip]
+        let kernel_dap = DiskAddressPacket::new(
+            128,
+            0,
+            0x1000 * (segment + 1),
+            66 + 128 * segment as u64
+        );
 
-    unsafe { kernel_dap.load(disk_number) };
+        unsafe { kernel_dap.load(disk_number) };
+    }
 }
 
 unsafe fn enter_vga_text() {
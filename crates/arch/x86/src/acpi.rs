@@ -0,0 +1,360 @@
+use core::{iter, mem::size_of, ptr, slice};
+
+use common::{
+    address_types::{Address, PhysicalAddress},
+    enums::MadtEntryType,
+};
+
+/// The physical address of the real mode segment of the EBDA, in the
+/// BIOS data area.
+const EBDA_SEGMENT_POINTER: usize = 0x40e;
+
+/// The BIOS area that is searched for the RSDP, after the EBDA.
+const BIOS_AREA: (usize, usize) = (0xe0000, 0x100000);
+
+/// Returns true if the bytes sum to zero, which is how ACPI structures
+/// are checked.
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// The Root System Description Pointer, which points to the root table
+/// of the ACPI tables.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    // The fields below exist only from ACPI 2.0.
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+impl Rsdp {
+    const SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+    /// The size of the part of the RSDP that exists from ACPI 1.0.
+    const V1_SIZE: usize = 20;
+
+    /// Search for the RSDP in the first KiB of the EBDA, and in the BIOS
+    /// area, where it is on a 16 byte boundary.
+    ///
+    /// The low memory must be mapped in the direct map.
+    pub fn find() -> Option<&'static Rsdp> {
+        let ebda = unsafe {
+            PhysicalAddress::from(EBDA_SEGMENT_POINTER)
+                .translate()
+                .as_non_null::<u16>()
+                .read_unaligned() as usize
+                * 16
+        };
+
+        [(ebda, ebda + 1024), BIOS_AREA]
+            .into_iter()
+            .flat_map(|(start, end)| (start..end).step_by(16))
+            .map(|address| unsafe {
+                PhysicalAddress::from(address)
+                    .translate()
+                    .as_non_null::<Rsdp>()
+                    .as_ref()
+            })
+            .find(|rsdp| {
+                rsdp.signature == *Self::SIGNATURE && rsdp.is_valid()
+            })
+    }
+
+    /// Returns true if the checksums of the RSDP are valid.
+    pub fn is_valid(&self) -> bool {
+        let bytes = |length| unsafe {
+            slice::from_raw_parts(ptr::from_ref(self).cast::<u8>(), length)
+        };
+
+        checksum(bytes(Self::V1_SIZE))
+            && (!self.is_extended()
+                || checksum(bytes(self.length as usize)))
+    }
+
+    /// Returns true if the root table is the XSDT, whose entries are 64
+    /// bit addresses, instead of the RSDT.
+    pub fn is_extended(&self) -> bool {
+        self.revision >= 2 && self.xsdt_address != 0
+    }
+
+    /// The physical address of the root table.
+    pub fn root_table_address(&self) -> PhysicalAddress {
+        if self.is_extended() {
+            PhysicalAddress::from(self.xsdt_address)
+        } else {
+            PhysicalAddress::from(self.rsdt_address as usize)
+        }
+    }
+}
+
+/// The header that every ACPI table except the RSDP starts with.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    /// The length of the whole table, including this header.
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// Returns true if the checksum of the whole table is valid.
+    ///
+    /// # Safety
+    /// The whole table must be mapped.
+    pub unsafe fn is_valid(&self) -> bool {
+        checksum(unsafe {
+            slice::from_raw_parts(
+                ptr::from_ref(self).cast::<u8>(),
+                self.length as usize,
+            )
+        })
+    }
+}
+
+/// An ACPI table that starts with an [`SdtHeader`].
+pub trait AcpiTable {
+    /// The signature in the header of the table.
+    const SIGNATURE: &'static str;
+
+    fn header(&self) -> &SdtHeader;
+}
+
+/// The RSDT or the XSDT, whose entries are the physical addresses of the
+/// other tables.
+pub struct RootTable {
+    header: &'static SdtHeader,
+    extended: bool,
+}
+
+impl RootTable {
+    /// # Safety
+    /// The header must be of the whole mapped root table that the RSDP
+    /// points to, and `extended` must be the same as
+    /// [`Rsdp::is_extended`].
+    pub const unsafe fn new(
+        header: &'static SdtHeader,
+        extended: bool,
+    ) -> Self {
+        Self { header, extended }
+    }
+
+    /// Returns the physical addresses of the tables.
+    pub fn entries(&self) -> impl Iterator<Item = PhysicalAddress> {
+        let entry_size = if self.extended { 8 } else { 4 };
+        let start = unsafe {
+            ptr::from_ref(self.header)
+                .cast::<u8>()
+                .add(size_of::<SdtHeader>())
+        };
+        let count = (self.header.length as usize - size_of::<SdtHeader>())
+            / entry_size;
+        let extended = self.extended;
+
+        (0..count).map(move |i| unsafe {
+            let entry = start.add(i * entry_size);
+            if extended {
+                PhysicalAddress::from(entry.cast::<u64>().read_unaligned())
+            } else {
+                PhysicalAddress::from(
+                    entry.cast::<u32>().read_unaligned() as usize
+                )
+            }
+        })
+    }
+}
+
+/// The polarity and trigger mode of an interrupt, as the MADT encodes
+/// them.
+///
+/// Both of them may conform to the specification of the bus, which is
+/// active high and edge triggered for the ISA bus.
+#[derive(Debug, Clone, Copy)]
+pub struct MpsIntiFlags(pub u16);
+
+impl MpsIntiFlags {
+    pub const fn is_active_low(&self) -> bool { self.0 & 0b11 == 0b11 }
+
+    pub const fn is_level_triggered(&self) -> bool {
+        (self.0 >> 2) & 0b11 == 0b11
+    }
+}
+
+/// An entry of the [`Madt`].
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    /// A processor and its local APIC.
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    /// An I/O APIC, and the first global system interrupt that it
+    /// handles.
+    IoApic {
+        id: u8,
+        address: PhysicalAddress,
+        gsi_base: u32,
+    },
+    /// A legacy ISA IRQ that is connected to a different global system
+    /// interrupt, or with a different polarity or trigger mode.
+    InterruptSourceOverride {
+        irq: u8,
+        gsi: u32,
+        flags: MpsIntiFlags,
+    },
+    /// A local interrupt pin that is connected to NMI. A processor id of
+    /// `0xff` means all of the processors.
+    LocalApicNmi {
+        processor_id: u8,
+        flags: MpsIntiFlags,
+        lint: u8,
+    },
+    /// An entry of a type that is not parsed, by its type.
+    Other(u8),
+}
+
+impl MadtEntry {
+    /// Parse the entry that starts at `entry`.
+    ///
+    /// # Safety
+    /// The whole entry must be mapped.
+    unsafe fn parse(entry: *const u8) -> Self {
+        let read_u8 = |offset| unsafe { entry.add(offset).read() };
+        let read_u16 = |offset| unsafe {
+            entry.add(offset).cast::<u16>().read_unaligned()
+        };
+        let read_u32 = |offset| unsafe {
+            entry.add(offset).cast::<u32>().read_unaligned()
+        };
+
+        let entry_type = read_u8(0);
+        match MadtEntryType::try_from(entry_type) {
+            Ok(MadtEntryType::LocalApic) => MadtEntry::LocalApic {
+                processor_id: read_u8(2),
+                apic_id: read_u8(3),
+                flags: read_u32(4),
+            },
+            Ok(MadtEntryType::IoApic) => MadtEntry::IoApic {
+                id: read_u8(2),
+                address: PhysicalAddress::from(read_u32(4) as usize),
+                gsi_base: read_u32(8),
+            },
+            Ok(MadtEntryType::InterruptSourceOverride) => {
+                MadtEntry::InterruptSourceOverride {
+                    irq: read_u8(3),
+                    gsi: read_u32(4),
+                    flags: MpsIntiFlags(read_u16(8)),
+                }
+            }
+            Ok(MadtEntryType::LocalApicNmi) => MadtEntry::LocalApicNmi {
+                processor_id: read_u8(2),
+                flags: MpsIntiFlags(read_u16(3)),
+                lint: read_u8(5),
+            },
+            _ => MadtEntry::Other(entry_type),
+        }
+    }
+}
+
+/// The Multiple APIC Description Table, which describes the interrupt
+/// controllers of the system.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Madt {
+    pub header: SdtHeader,
+    /// The physical address of the local APIC of each processor.
+    pub local_apic_address: u32,
+    pub flags: u32,
+}
+
+impl AcpiTable for Madt {
+    const SIGNATURE: &'static str = "APIC";
+
+    fn header(&self) -> &SdtHeader { &self.header }
+}
+
+impl Madt {
+    /// Returns true if the system also has the legacy 8259 PICs, which
+    /// must be masked.
+    pub const fn has_legacy_pics(&self) -> bool { self.flags & 1 != 0 }
+
+    /// Returns the entries that follow the table.
+    ///
+    /// The whole table is read, so all of it must be mapped, and not only
+    /// this struct.
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> {
+        let start = ptr::from_ref(self).cast::<u8>();
+        let length = self.header.length as usize;
+        let mut offset = size_of::<Madt>();
+
+        iter::from_fn(move || {
+            if offset + 2 > length {
+                return None;
+            }
+
+            let entry = unsafe { start.add(offset) };
+            let entry_length = unsafe { entry.add(1).read() } as usize;
+            if entry_length < 2 || offset + entry_length > length {
+                return None;
+            }
+
+            offset += entry_length;
+            Some(unsafe { MadtEntry::parse(entry) })
+        })
+    }
+}
+
+/// The Generic Address Structure, which describes where registers are.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    /// Zero for memory, and one for I/O ports.
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+/// The table that describes the registers of the HPET.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct HpetTable {
+    pub header: SdtHeader,
+    /// The same as the low half of the capabilities register of the HPET.
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub number: u8,
+    /// The minimal period of a periodic timer, in counts of the main
+    /// counter.
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl AcpiTable for HpetTable {
+    const SIGNATURE: &'static str = "HPET";
+
+    fn header(&self) -> &SdtHeader { &self.header }
+}
+
+impl HpetTable {
+    /// The physical address of the registers of the HPET.
+    pub const fn address(&self) -> PhysicalAddress {
+        PhysicalAddress::from(self.base_address.address)
+    }
+}
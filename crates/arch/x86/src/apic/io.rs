@@ -0,0 +1,286 @@
+use core::ptr::NonNull;
+
+use common::{
+    address_types::Address,
+    enums::{DeliveryMode, IoApicRegister, IsaIrq, interrupts::Interrupt},
+    error::ApicError,
+};
+use macros::bitfields;
+
+use crate::{
+    acpi::{Madt, MadtEntry, MpsIntiFlags},
+    apic::LocalApic,
+};
+
+/// The amount of I/O APICs that can be handled.
+pub const MAX_IO_APICS: usize = 8;
+
+/// The amount of legacy ISA IRQs.
+pub const ISA_IRQS: usize = 16;
+
+/// The offset of the data register from the index register.
+const DATA_REGISTER_OFFSET: usize = 0x10;
+
+/// An entry of the redirection table of an I/O APIC, which routes a
+/// global system interrupt to a local APIC.
+#[bitfields]
+pub struct RedirectionEntry {
+    pub vector: B8,
+    #[flag(flag_type = DeliveryMode)]
+    pub delivery_mode: B3,
+    pub logical_destination: B1,
+    #[flag(r)]
+    pub delivery_pending: B1,
+    pub active_low: B1,
+    #[flag(r)]
+    pub remote_irr: B1,
+    pub level_triggered: B1,
+    pub masked: B1,
+    #[flag(r)]
+    reserved: B39,
+    pub destination: B8,
+}
+
+/// A global system interrupt, with its polarity and trigger mode.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptSource {
+    pub gsi: u32,
+    pub flags: MpsIntiFlags,
+}
+
+/// A single I/O APIC, which handles the global system interrupts from
+/// `gsi_base` on.
+pub struct IoApic {
+    /// The index register, which selects the register that the data
+    /// register accesses.
+    registers: NonNull<u32>,
+    id: u8,
+    gsi_base: u32,
+    /// The amount of redirection entries.
+    entries: u32,
+}
+
+// The registers may be accessed from any cpu, but not from two at once,
+// since the index register selects what the data register accesses.
+unsafe impl Send for IoApic {}
+
+impl IoApic {
+    /// # Safety
+    /// The registers of the I/O APIC must be mapped uncached at
+    /// `registers`.
+    pub unsafe fn new(
+        registers: NonNull<u32>,
+        id: u8,
+        gsi_base: u32,
+    ) -> Self {
+        let mut apic = Self {
+            registers,
+            id,
+            gsi_base,
+            entries: 0,
+        };
+        apic.entries =
+            ((apic.read(IoApicRegister::Version as u32) >> 16) & 0xff) + 1;
+        apic
+    }
+
+    pub fn id(&self) -> u8 { self.id }
+
+    /// Returns true if this I/O APIC handles the global system interrupt.
+    pub fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    /// Returns the redirection entry of a global system interrupt that
+    /// this I/O APIC [`handles`](Self::handles).
+    pub fn redirection_entry(&self, gsi: u32) -> RedirectionEntry {
+        let index = self.entry_index(gsi);
+        let low = self.read(index) as u64;
+        let high = self.read(index + 1) as u64;
+        RedirectionEntry::from(high << 32 | low)
+    }
+
+    /// Set the redirection entry of a global system interrupt that this
+    /// I/O APIC [`handles`](Self::handles).
+    ///
+    /// # Safety
+    /// Unless the entry is masked, its vector must have a handler.
+    pub unsafe fn set_redirection_entry(
+        &self,
+        gsi: u32,
+        entry: RedirectionEntry,
+    ) {
+        let index = self.entry_index(gsi);
+        let entry = u64::from(entry);
+        // The destination is written first, since the mask is in the low
+        // register.
+        unsafe {
+            self.write(index + 1, (entry >> 32) as u32);
+            self.write(index, entry as u32);
+        }
+    }
+
+    /// Mask all of the global system interrupts of this I/O APIC.
+    pub fn mask_all(&self) {
+        for gsi in self.gsi_base..self.gsi_base + self.entries {
+            unsafe {
+                self.set_redirection_entry(
+                    gsi,
+                    RedirectionEntry::new().masked(true),
+                )
+            };
+        }
+    }
+
+    fn entry_index(&self, gsi: u32) -> u32 {
+        debug_assert!(self.handles(gsi));
+        IoApicRegister::RedirectionTable as u32 + (gsi - self.gsi_base) * 2
+    }
+
+    fn read(&self, index: u32) -> u32 {
+        unsafe {
+            self.registers.write_volatile(index);
+            self.registers
+                .byte_add(DATA_REGISTER_OFFSET)
+                .read_volatile()
+        }
+    }
+
+    unsafe fn write(&self, index: u32, value: u32) {
+        unsafe {
+            self.registers.write_volatile(index);
+            self.registers
+                .byte_add(DATA_REGISTER_OFFSET)
+                .write_volatile(value);
+        }
+    }
+}
+
+/// The I/O APICs of the system, and the global system interrupts that the
+/// legacy ISA IRQs are connected to.
+pub struct IoApics {
+    apics: [Option<IoApic>; MAX_IO_APICS],
+    isa_irqs: [InterruptSource; ISA_IRQS],
+}
+
+impl IoApics {
+    /// Returns an empty set of I/O APICs, where each ISA IRQ is connected
+    /// to the global system interrupt of the same number.
+    pub const fn new() -> Self {
+        let mut isa_irqs = [InterruptSource {
+            gsi: 0,
+            flags: MpsIntiFlags(0),
+        }; ISA_IRQS];
+
+        let mut irq = 0;
+        while irq < ISA_IRQS {
+            isa_irqs[irq].gsi = irq as u32;
+            irq += 1;
+        }
+
+        Self {
+            apics: [const { None }; MAX_IO_APICS],
+            isa_irqs,
+        }
+    }
+
+    /// Add the I/O APICs and the ISA IRQ overrides that the MADT
+    /// describes, and mask all of their interrupts.
+    ///
+    /// # Safety
+    /// The registers of the I/O APICs must be mapped uncached in the
+    /// direct map.
+    pub unsafe fn init(&mut self, madt: &Madt) -> Result<(), ApicError> {
+        for entry in madt.entries() {
+            match entry {
+                MadtEntry::IoApic {
+                    id,
+                    address,
+                    gsi_base,
+                } => {
+                    let slot = self
+                        .apics
+                        .iter_mut()
+                        .find(|a| a.is_none())
+                        .ok_or(ApicError::TooManyIoApics)?;
+
+                    let apic = unsafe {
+                        IoApic::new(
+                            address.translate().as_non_null(),
+                            id,
+                            gsi_base,
+                        )
+                    };
+                    apic.mask_all();
+                    *slot = Some(apic);
+                }
+                MadtEntry::InterruptSourceOverride { irq, gsi, flags }
+                    if (irq as usize) < ISA_IRQS =>
+                {
+                    self.isa_irqs[irq as usize] =
+                        InterruptSource { gsi, flags };
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the global system interrupt that the legacy ISA IRQ is
+    /// connected to.
+    pub fn isa_irq(&self, irq: IsaIrq) -> InterruptSource {
+        self.isa_irqs[irq as usize]
+    }
+
+    /// Route the interrupt to `vector` on the given local APIC, and
+    /// unmask it.
+    ///
+    /// # Safety
+    /// The vector must have a handler, which signals the end of the
+    /// interrupt to the local APIC.
+    pub unsafe fn route(
+        &mut self,
+        source: InterruptSource,
+        vector: Interrupt,
+        destination: &LocalApic,
+    ) -> Result<(), ApicError> {
+        let id = destination.id();
+        let destination = u8::try_from(id)
+            .map_err(|_| ApicError::UnreachableLocalApic(id))?;
+
+        let entry = RedirectionEntry::new()
+            .vector(vector as u8)
+            .delivery_mode(DeliveryMode::Fixed)
+            .active_low(source.flags.is_active_low())
+            .level_triggered(source.flags.is_level_triggered())
+            .destination(destination);
+
+        unsafe {
+            self.io_apic(source.gsi)?
+                .set_redirection_entry(source.gsi, entry)
+        };
+        Ok(())
+    }
+
+    /// Mask the global system interrupt.
+    pub fn mask(&mut self, gsi: u32) -> Result<(), ApicError> {
+        let apic = self.io_apic(gsi)?;
+        let entry = apic.redirection_entry(gsi).masked(true);
+        unsafe { apic.set_redirection_entry(gsi, entry) };
+        Ok(())
+    }
+
+    /// Returns the I/O APIC that handles the global system interrupt.
+    fn io_apic(&self, gsi: u32) -> Result<&IoApic, ApicError> {
+        self.apics
+            .iter()
+            .flatten()
+            .find(|apic| apic.handles(gsi))
+            .ok_or(ApicError::UnknownGsi(gsi))
+    }
+}
+
+impl Default for IoApics {
+    fn default() -> Self { Self::new() }
+}
@@ -0,0 +1,126 @@
+use core::ptr::NonNull;
+
+use common::{
+    address_types::{Address, PhysicalAddress},
+    enums::{ApicBaseFlag, LocalApicRegister, MSR, interrupts::Interrupt},
+    error::ApicError,
+};
+
+use crate::{
+    instructions::cpuid::CpuFeatures,
+    registers::model_specific::{rdmsr, rdmsr_at, wrmsr, wrmsr_at},
+};
+
+/// The bits of the base MSR that hold the physical address of the
+/// registers.
+const BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Enables the local APIC in the spurious interrupt vector register.
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/// The local APIC of the current cpu.
+pub struct LocalApic {
+    /// The registers in xAPIC mode, or `None` in x2APIC mode, where they
+    /// are model specific registers.
+    registers: Option<NonNull<u32>>,
+}
+
+// The registers of a local APIC are only accessed from its own cpu.
+unsafe impl Send for LocalApic {}
+unsafe impl Sync for LocalApic {}
+
+impl LocalApic {
+    /// Returns the physical address of the registers in xAPIC mode.
+    pub fn physical_base() -> PhysicalAddress {
+        PhysicalAddress::from(
+            (rdmsr(MSR::ApicBase) & BASE_ADDRESS_MASK) as usize,
+        )
+    }
+
+    /// Enable the local APIC of this cpu, in x2APIC mode if the cpu
+    /// supports it, and deliver its spurious interrupts to `spurious`.
+    ///
+    /// # Safety
+    /// Unless the cpu supports x2APIC, the registers at
+    /// [`physical_base`](Self::physical_base) must be mapped uncached in
+    /// the direct map. The spurious vector must have a handler.
+    pub unsafe fn enable(spurious: Interrupt) -> Result<Self, ApicError> {
+        let features = CpuFeatures::default();
+        if !features.has_apic() {
+            return Err(ApicError::NotSupported);
+        }
+
+        // x2APIC mode may only be entered from xAPIC mode.
+        let base =
+            rdmsr(MSR::ApicBase) | ApicBaseFlag::GlobalEnable as u64;
+        unsafe { wrmsr(MSR::ApicBase, base) };
+
+        let apic = if features.has_x2apic() {
+            unsafe {
+                wrmsr(
+                    MSR::ApicBase,
+                    base | ApicBaseFlag::X2ApicEnable as u64,
+                )
+            };
+            Self { registers: None }
+        } else {
+            Self {
+                registers: Some(
+                    Self::physical_base().translate().as_non_null(),
+                ),
+            }
+        };
+
+        unsafe {
+            apic.write(LocalApicRegister::TaskPriority, 0);
+            apic.write(
+                LocalApicRegister::SpuriousInterruptVector,
+                SOFTWARE_ENABLE | spurious as u32,
+            );
+        }
+
+        Ok(apic)
+    }
+
+    pub fn is_x2apic(&self) -> bool { self.registers.is_none() }
+
+    /// Returns the id of this local APIC.
+    pub fn id(&self) -> u32 {
+        let id = self.read(LocalApicRegister::Id);
+        if self.is_x2apic() { id } else { id >> 24 }
+    }
+
+    pub fn read(&self, register: LocalApicRegister) -> u32 {
+        match self.registers {
+            Some(registers) => unsafe {
+                registers.byte_add(register as usize).read_volatile()
+            },
+            None => rdmsr_at(Self::x2apic_msr(register)) as u32,
+        }
+    }
+
+    /// Write `value` to the given register.
+    ///
+    /// # Safety
+    /// This changes how interrupts are delivered to this cpu.
+    pub unsafe fn write(&self, register: LocalApicRegister, value: u32) {
+        match self.registers {
+            Some(registers) => unsafe {
+                registers.byte_add(register as usize).write_volatile(value)
+            },
+            None => unsafe {
+                wrmsr_at(Self::x2apic_msr(register), value as u64)
+            },
+        }
+    }
+
+    /// Signal the end of the interrupt that is being handled.
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(LocalApicRegister::EndOfInterrupt, 0) }
+    }
+
+    /// The model specific register of the register in x2APIC mode.
+    const fn x2apic_msr(register: LocalApicRegister) -> u32 {
+        MSR::X2ApicRegisters as u32 + register as u32 / 16
+    }
+}
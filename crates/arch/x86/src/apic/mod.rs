@@ -0,0 +1,7 @@
+mod io;
+mod local;
+mod timer;
+
+pub use io::{InterruptSource, IoApic, IoApics, RedirectionEntry};
+pub use local::LocalApic;
+pub use timer::ApicTimer;
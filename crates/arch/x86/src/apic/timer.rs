@@ -0,0 +1,143 @@
+use core::{
+    sync::atomic::{Ordering, fence},
+    time::Duration,
+};
+
+use common::enums::{
+    ApicTimerDivide, ApicTimerMode, LocalApicRegister, MSR,
+    interrupts::Interrupt,
+};
+
+use crate::{
+    apic::LocalApic, instructions::cpuid::CpuFeatures, pit,
+    registers::model_specific::wrmsr,
+};
+
+/// The divider of the bus clock that drives the timer.
+const DIVIDE: ApicTimerDivide = ApicTimerDivide::By16;
+
+/// Masks an entry of the local vector table.
+const LVT_MASKED: u32 = 1 << 16;
+
+/// How long the timer is measured against the PIT.
+const CALIBRATION_MS: u32 = 10;
+
+const NS_PER_MS: u128 = 1_000_000;
+
+/// The timer of a local APIC, with its measured frequency.
+pub struct ApicTimer {
+    apic: &'static LocalApic,
+    vector: u8,
+    /// Counts of the timer in a millisecond.
+    ticks_per_ms: u64,
+    tsc_deadline: bool,
+}
+
+impl ApicTimer {
+    /// Measure the frequency of the timer against the PIT. The timer is
+    /// stopped, and raises `vector` once it is started.
+    ///
+    /// Interrupts should be disabled, so nothing delays the measurement.
+    pub fn calibrate(apic: &'static LocalApic, vector: Interrupt) -> Self {
+        let vector = vector as u8;
+        unsafe {
+            apic.write(
+                LocalApicRegister::TimerDivideConfiguration,
+                DIVIDE as u32,
+            );
+            apic.write(
+                LocalApicRegister::LvtTimer,
+                LVT_MASKED | ApicTimerMode::OneShot as u32 | vector as u32,
+            );
+            apic.write(LocalApicRegister::TimerInitialCount, u32::MAX);
+        }
+
+        let ticks_per_ms = pit::calibrate(CALIBRATION_MS, || {
+            (u32::MAX - apic.read(LocalApicRegister::TimerCurrentCount))
+                as u64
+        });
+        unsafe { apic.write(LocalApicRegister::TimerInitialCount, 0) };
+
+        Self {
+            apic,
+            vector,
+            ticks_per_ms,
+            tsc_deadline: CpuFeatures::default().has_tsc_deadline(),
+        }
+    }
+
+    pub fn ticks_per_ms(&self) -> u64 { self.ticks_per_ms }
+
+    /// Returns true if the timer supports TSC-deadline mode.
+    pub fn has_tsc_deadline(&self) -> bool { self.tsc_deadline }
+
+    /// Fire every `period_us` microseconds.
+    ///
+    /// # Safety
+    /// The vector of the timer must have a handler, which signals the end
+    /// of the interrupt.
+    pub unsafe fn start_periodic(&self, period_us: u64) {
+        let count = (self.ticks_per_ms * period_us / 1000)
+            .clamp(1, u32::MAX as u64) as u32;
+
+        unsafe {
+            self.apic.write(
+                LocalApicRegister::LvtTimer,
+                ApicTimerMode::Periodic as u32 | self.vector as u32,
+            );
+            self.apic.write(LocalApicRegister::TimerInitialCount, count);
+        }
+    }
+
+    /// Fire once, after `delay` is counted down.
+    ///
+    /// A delay longer than the timer can count fires early.
+    ///
+    /// # Safety
+    /// Same as [`start_periodic`](Self::start_periodic).
+    pub unsafe fn start_one_shot(&self, delay: Duration) {
+        let count = (delay.as_nanos() * self.ticks_per_ms as u128
+            / NS_PER_MS)
+            .clamp(1, u32::MAX as u128) as u32;
+
+        unsafe {
+            self.apic.write(
+                LocalApicRegister::LvtTimer,
+                ApicTimerMode::OneShot as u32 | self.vector as u32,
+            );
+            self.apic.write(LocalApicRegister::TimerInitialCount, count);
+        }
+    }
+
+    /// Fire once, when the TSC reaches `deadline`.
+    ///
+    /// # Safety
+    /// Same as [`start_periodic`](Self::start_periodic), and the timer
+    /// must support [TSC-deadline mode](Self::has_tsc_deadline).
+    pub unsafe fn start_tsc_deadline(&self, deadline: u64) {
+        debug_assert!(self.tsc_deadline, "TSC-deadline mode is missing");
+
+        unsafe {
+            self.apic.write(
+                LocalApicRegister::LvtTimer,
+                ApicTimerMode::TscDeadline as u32 | self.vector as u32,
+            );
+            // In xAPIC mode, the write to the LVT must be seen before the
+            // deadline is armed.
+            fence(Ordering::SeqCst);
+            // A deadline of zero disarms the timer.
+            wrmsr(MSR::TscDeadline, deadline.max(1));
+        }
+    }
+
+    /// Stop the timer, in any of its modes.
+    pub fn stop(&self) {
+        unsafe {
+            self.apic.write(LocalApicRegister::LvtTimer, LVT_MASKED);
+            self.apic.write(LocalApicRegister::TimerInitialCount, 0);
+            if self.tsc_deadline {
+                wrmsr(MSR::TscDeadline, 0);
+            }
+        }
+    }
+}
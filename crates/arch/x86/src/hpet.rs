@@ -0,0 +1,313 @@
+use core::ptr::NonNull;
+
+use common::{
+    enums::{HpetRegister, HpetTimerRegister},
+    error::HpetError,
+};
+use macros::bitfields;
+
+/// Femtoseconds in a nanosecond.
+const FS_PER_NS: u128 = 1_000_000;
+
+/// The offset of the registers of the first comparator, and the size of
+/// the registers of each comparator.
+const TIMER_REGISTERS: (usize, usize) = (0x100, 0x20);
+
+/// Starts the main counter, in the configuration register.
+const ENABLE: u64 = 1 << 0;
+
+/// Routes the first two comparators to the IRQs of the PIT and the RTC, in
+/// the configuration register.
+const LEGACY_ROUTE: u64 = 1 << 1;
+
+/// The capabilities register of the HPET.
+#[bitfields]
+pub struct HpetCapabilities {
+    #[flag(r)]
+    pub revision: B8,
+    /// The index of the last comparator.
+    #[flag(r)]
+    pub last_timer: B5,
+    #[flag(r)]
+    pub wide_counter: B1,
+    #[flag(r)]
+    reserved: B1,
+    #[flag(r)]
+    pub legacy_route: B1,
+    #[flag(r)]
+    pub vendor: B16,
+    /// The period of the main counter, in femtoseconds.
+    #[flag(r)]
+    pub period: B32,
+}
+
+/// The configuration register of a comparator.
+#[bitfields]
+pub struct HpetTimerConfiguration {
+    #[flag(r)]
+    reserved0: B1,
+    pub level_triggered: B1,
+    pub interrupt_enable: B1,
+    pub periodic: B1,
+    #[flag(r)]
+    pub periodic_capable: B1,
+    #[flag(r)]
+    pub wide_comparator: B1,
+    /// Lets the next write to the comparator set the period of a periodic
+    /// comparator, after it sets its next deadline.
+    pub set_accumulator: B1,
+    #[flag(r)]
+    reserved1: B1,
+    pub force_32bit: B1,
+    /// The input of the I/O APIC that the comparator interrupts.
+    pub io_apic_route: B5,
+    pub fsb_enable: B1,
+    #[flag(r)]
+    pub fsb_capable: B1,
+    #[flag(r)]
+    reserved2: B16,
+    /// A bit for each input of the I/O APIC that the comparator can be
+    /// routed to.
+    #[flag(r)]
+    pub route_capabilities: B32,
+}
+
+/// The High Precision Event Timer, whose main counter runs at a fixed
+/// rate, and whose comparators raise an interrupt when the main counter
+/// reaches them.
+pub struct Hpet {
+    registers: NonNull<u64>,
+    capabilities: HpetCapabilities,
+}
+
+// The registers of the HPET are shared between the cpus, and each
+// comparator is only programmed from one place.
+unsafe impl Send for Hpet {}
+unsafe impl Sync for Hpet {}
+
+impl Hpet {
+    /// Restart the main counter from zero, with all of the comparators
+    /// stopped.
+    ///
+    /// # Safety
+    /// The registers of the HPET must be mapped uncached at `registers`.
+    pub unsafe fn new(registers: NonNull<u64>) -> Self {
+        let mut hpet = Self {
+            registers,
+            capabilities: HpetCapabilities::new(),
+        };
+        hpet.capabilities =
+            HpetCapabilities::from(hpet.read(HpetRegister::Capabilities));
+
+        // The main counter may only be written while it is stopped.
+        let configuration = hpet.read(HpetRegister::Configuration)
+            & !(ENABLE | LEGACY_ROUTE);
+        unsafe {
+            hpet.write(HpetRegister::Configuration, configuration);
+            hpet.write(HpetRegister::MainCounter, 0);
+        }
+        for timer in 0..hpet.timers() {
+            let _ = hpet.stop(timer);
+        }
+        unsafe {
+            hpet.write(HpetRegister::Configuration, configuration | ENABLE)
+        };
+
+        hpet
+    }
+
+    pub fn capabilities(&self) -> HpetCapabilities { self.capabilities }
+
+    /// Returns the amount of comparators.
+    pub fn timers(&self) -> u8 { self.capabilities.get_last_timer() + 1 }
+
+    /// Returns the value of the main counter.
+    ///
+    /// Without a [`wide_counter`](HpetCapabilities::is_wide_counter), it
+    /// wraps around at 32 bits.
+    pub fn counter(&self) -> u64 { self.read(HpetRegister::MainCounter) }
+
+    /// Returns the rate of the main counter, in Hz.
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.capabilities.get_period() as u64
+    }
+
+    /// Convert counts of the main counter to nanoseconds.
+    pub fn counts_to_ns(&self, counts: u64) -> u64 {
+        (counts as u128 * self.capabilities.get_period() as u128
+            / FS_PER_NS) as u64
+    }
+
+    /// Convert nanoseconds to counts of the main counter.
+    pub fn ns_to_counts(&self, ns: u64) -> u64 {
+        (ns as u128 * FS_PER_NS / self.capabilities.get_period() as u128)
+            as u64
+    }
+
+    /// Returns the configuration of a comparator.
+    pub fn timer_configuration(
+        &self,
+        timer: u8,
+    ) -> Result<HpetTimerConfiguration, HpetError> {
+        self.check_timer(timer)?;
+        Ok(HpetTimerConfiguration::from(
+            self.read_timer(timer, HpetTimerRegister::Configuration),
+        ))
+    }
+
+    /// Returns the first input of the I/O APIC that the comparator can be
+    /// routed to, which is also its global system interrupt when the
+    /// interrupts of the I/O APIC start from zero.
+    pub fn route(&self, timer: u8) -> Result<u32, HpetError> {
+        let routes =
+            self.timer_configuration(timer)?.get_route_capabilities();
+        match routes {
+            0 => Err(HpetError::Unroutable(timer)),
+            routes => Ok(routes.trailing_zeros()),
+        }
+    }
+
+    /// Raise an interrupt on the [`route`](Self::route) of the comparator
+    /// every `period_ns` nanoseconds.
+    ///
+    /// # Safety
+    /// The interrupt must be routed to a vector with a handler.
+    pub unsafe fn start_periodic(
+        &self,
+        timer: u8,
+        period_ns: u64,
+    ) -> Result<(), HpetError> {
+        let mut configuration = self.configuration_for(timer)?;
+        if !configuration.is_periodic_capable() {
+            return Err(HpetError::NotPeriodic(timer));
+        }
+        configuration.set_periodic(true);
+        configuration.set_set_accumulator(true);
+
+        let period = self.ns_to_counts(period_ns).max(1);
+        unsafe {
+            self.write_timer(
+                timer,
+                HpetTimerRegister::Configuration,
+                configuration.into(),
+            );
+            // The first write sets the next deadline, and the second one
+            // the period that is added to it each time it is reached.
+            self.write_timer(
+                timer,
+                HpetTimerRegister::Comparator,
+                self.counter() + period,
+            );
+            self.write_timer(timer, HpetTimerRegister::Comparator, period);
+        }
+        Ok(())
+    }
+
+    /// Raise an interrupt on the [`route`](Self::route) of the comparator
+    /// once the main counter reaches `deadline`.
+    ///
+    /// # Safety
+    /// Same as [`start_periodic`](Self::start_periodic).
+    pub unsafe fn start_one_shot(
+        &self,
+        timer: u8,
+        deadline: u64,
+    ) -> Result<(), HpetError> {
+        let mut configuration = self.configuration_for(timer)?;
+        configuration.set_periodic(false);
+
+        unsafe {
+            self.write_timer(
+                timer,
+                HpetTimerRegister::Configuration,
+                configuration.into(),
+            );
+            self.write_timer(
+                timer,
+                HpetTimerRegister::Comparator,
+                deadline,
+            );
+        }
+        Ok(())
+    }
+
+    /// Stop the interrupts of the comparator.
+    pub fn stop(&self, timer: u8) -> Result<(), HpetError> {
+        let mut configuration = self.timer_configuration(timer)?;
+        configuration.set_interrupt_enable(false);
+        configuration.set_periodic(false);
+        unsafe {
+            self.write_timer(
+                timer,
+                HpetTimerRegister::Configuration,
+                configuration.into(),
+            )
+        };
+        Ok(())
+    }
+
+    /// Returns the configuration of a comparator that interrupts its
+    /// [`route`](Self::route) on the edge of the interrupt.
+    fn configuration_for(
+        &self,
+        timer: u8,
+    ) -> Result<HpetTimerConfiguration, HpetError> {
+        let gsi = self.route(timer)?;
+        let mut configuration = self.timer_configuration(timer)?;
+        configuration.set_level_triggered(false);
+        configuration.set_fsb_enable(false);
+        configuration.set_io_apic_route(gsi as u8);
+        configuration.set_interrupt_enable(true);
+        Ok(configuration)
+    }
+
+    fn check_timer(&self, timer: u8) -> Result<(), HpetError> {
+        if timer < self.timers() {
+            Ok(())
+        } else {
+            Err(HpetError::UnknownTimer(timer))
+        }
+    }
+
+    fn read(&self, register: HpetRegister) -> u64 {
+        unsafe {
+            self.registers.byte_add(register as usize).read_volatile()
+        }
+    }
+
+    unsafe fn write(&self, register: HpetRegister, value: u64) {
+        unsafe {
+            self.registers
+                .byte_add(register as usize)
+                .write_volatile(value)
+        }
+    }
+
+    fn timer_register(
+        &self,
+        timer: u8,
+        register: HpetTimerRegister,
+    ) -> NonNull<u64> {
+        let (first, size) = TIMER_REGISTERS;
+        unsafe {
+            self.registers.byte_add(
+                first + size * timer as usize + register as usize,
+            )
+        }
+    }
+
+    fn read_timer(&self, timer: u8, register: HpetTimerRegister) -> u64 {
+        unsafe { self.timer_register(timer, register).read_volatile() }
+    }
+
+    unsafe fn write_timer(
+        &self,
+        timer: u8,
+        register: HpetTimerRegister,
+        value: u64,
+    ) {
+        unsafe {
+            self.timer_register(timer, register).write_volatile(value)
+        }
+    }
+}
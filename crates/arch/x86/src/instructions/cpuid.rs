@@ -1,6 +1,9 @@
 use core::arch::asm;
 
-use common::enums::{CpuFeatureEdx, CpuidQuery};
+use common::enums::{
+    CpuExtendedFeatureEdx, CpuFeatureEcx, CpuFeatureEdx,
+    CpuPowerManagementEdx, CpuStructuredFeatureEbx, CpuidQuery,
+};
 
 use crate::instructions::macros::cpu_feature;
 pub struct CpuidResult {
@@ -87,4 +90,74 @@ impl CpuFeatures {
         apic,
         ((CpuFeatureEdx::APIC as u64) << 32).trailing_zeros()
     );
+    cpu_feature!(pcid, (CpuFeatureEcx::PCID as u64).trailing_zeros());
+    cpu_feature!(x2apic, (CpuFeatureEcx::X2APIC as u64).trailing_zeros());
+    cpu_feature!(
+        tsc_deadline,
+        (CpuFeatureEcx::TSC_DEADLINE as u64).trailing_zeros()
+    );
+}
+
+/// The features of the structured extended feature leaf, in ebx.
+pub struct StructuredCpuFeatures(pub u32);
+
+impl Default for StructuredCpuFeatures {
+    fn default() -> Self {
+        let highest = cpuid(CpuidQuery::GetVendorString).eax;
+        if highest < 7 {
+            return Self(0);
+        }
+        Self(cpuid(CpuidQuery::GetStructuredFeatures).ebx)
+    }
+}
+
+impl StructuredCpuFeatures {
+    cpu_feature!(
+        invpcid,
+        (CpuStructuredFeatureEbx::INVPCID as u32).trailing_zeros()
+    );
+}
+
+/// The features of the extended feature leaf.
+///
+/// Bits 0-31 are ecx
+/// Bits 32-63 are edx
+pub struct ExtendedCpuFeatures(pub u64);
+
+impl Default for ExtendedCpuFeatures {
+    fn default() -> Self {
+        let features = cpuid(CpuidQuery::GetExtendedCpuFeatures);
+        Self(((features.edx as u64) << 32) | (features.ecx as u64))
+    }
+}
+
+impl ExtendedCpuFeatures {
+    cpu_feature!(
+        nx,
+        ((CpuExtendedFeatureEdx::NX as u64) << 32).trailing_zeros()
+    );
+    cpu_feature!(
+        page1gb,
+        ((CpuExtendedFeatureEdx::PAGE1GB as u64) << 32).trailing_zeros()
+    );
+}
+
+/// The features of the advanced power management leaf, in edx.
+pub struct PowerManagementFeatures(pub u32);
+
+impl Default for PowerManagementFeatures {
+    fn default() -> Self {
+        let highest = cpuid(CpuidQuery::GetHighestExtendedLeaf).eax;
+        if highest < 0x80000007 {
+            return Self(0);
+        }
+        Self(cpuid(CpuidQuery::GetPowerManagementFeatures).edx)
+    }
+}
+
+impl PowerManagementFeatures {
+    cpu_feature!(
+        invariant_tsc,
+        (CpuPowerManagementEdx::INVARIANT_TSC as u32).trailing_zeros()
+    );
 }
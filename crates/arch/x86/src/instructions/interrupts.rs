@@ -1,5 +1,7 @@
 use core::arch::asm;
 
+use crate::registers::rflags::Rflags;
+
 /// x86/x86_64-only.
 ///
 /// # Safety
@@ -30,3 +32,17 @@ pub unsafe fn disable() {
 pub unsafe fn hlt() {
     unsafe { asm!("hlt", options(nostack, nomem)) };
 }
+
+/// Run `f` with interrupts disabled, and enable them afterwards only if
+/// they were enabled before.
+///
+/// This keeps an interrupt handler from taking a lock that `f` holds.
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = Rflags::read().is_interrupt();
+    unsafe { disable() };
+    let result = f();
+    if enabled {
+        unsafe { enable() };
+    }
+    result
+}
@@ -10,5 +10,7 @@ pub mod port;
 pub mod tables;
 #[cfg(target_arch = "x86_64")]
 pub mod tlb;
+#[cfg(target_arch = "x86_64")]
+pub mod tsc;
 
 pub use tables::*;
@@ -85,8 +85,8 @@ pub unsafe fn sgdt() -> &'static GlobalDescriptorTableLong {
         );
 
         // Get gdt from it's register.
-        &mut *(gdt_register.assume_init().base
-            as *mut &GlobalDescriptorTableLong)
+        &*(gdt_register.assume_init().base
+            as *const GlobalDescriptorTableLong)
     }
 }
 
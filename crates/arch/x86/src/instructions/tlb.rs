@@ -1,9 +1,65 @@
 use common::address_types::{Address, VirtualAddress};
-use core::arch::asm;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use crate::registers::cr3;
+use crate::{
+    instructions::cpuid::{CpuFeatures, StructuredCpuFeatures},
+    registers::cr4,
+};
 
+/// The PCID enable bit of CR4.
+const CR4_PCIDE: u64 = 1 << 17;
+
+/// The first address of the kernel half, which every address space maps
+/// the same.
+const KERNEL_HALF_START: usize = 0xffff_8000_0000_0000;
+
+/// The largest PCID, which is 12 bits wide.
+const MAX_PCID: u16 = 0xfff;
+
+/// The number of words in the map of the PCIDs.
+const PCID_WORDS: usize = (MAX_PCID as usize + 1) / u64::BITS as usize;
+
+/// The PCIDs that are in use, one bit per PCID. PCID 0 belongs to the
+/// boot tables, and is never handed out.
+static PCIDS: [AtomicU64; PCID_WORDS] =
+    [const { AtomicU64::new(0) }; PCID_WORDS];
+
+/// The kinds of `invpcid` invalidations.
+#[repr(u64)]
+enum InvpcidType {
+    /// The entries of an address that are tagged with the given PCID.
+    IndividualAddress = 0,
+    /// Every entry that is tagged with the given PCID.
+    SingleContext = 1,
+}
+
+/// Flush the TLB entries of an address.
+///
+/// `invlpg` only flushes the entries of the active PCID, so with PCIDs an
+/// address in the kernel half is flushed from every PCID that is in use,
+/// along with the cached tables on the way to it, which may point to a
+/// freed table.
 pub fn flash_address(address: VirtualAddress) {
+    if address.as_usize() >= KERNEL_HALF_START && is_pcid_enabled() {
+        let address = address.as_usize() as u64;
+
+        unsafe { invpcid(InvpcidType::IndividualAddress, 0, address) };
+        for (i, word) in PCIDS.iter().enumerate() {
+            let mut pcids = word.load(Ordering::Acquire);
+            while pcids != 0 {
+                let pcid = (i * 64) as u16 + pcids.trailing_zeros() as u16;
+                unsafe {
+                    invpcid(InvpcidType::IndividualAddress, pcid, address)
+                };
+                pcids &= pcids - 1;
+            }
+        }
+        return;
+    }
+
     unsafe {
         asm!("invlpg [{0:r}]",
             in(reg) address.as_usize(),
@@ -12,9 +68,104 @@ pub fn flash_address(address: VirtualAddress) {
     }
 }
 
+/// Flush the TLB entries that are tagged with the given PCID, so it can
+/// be handed to another address space.
+pub fn flash_pcid(pcid: u16) {
+    if is_pcid_enabled() {
+        unsafe { invpcid(InvpcidType::SingleContext, pcid, 0) };
+    }
+}
+
+/// Take a free PCID, if there are any left.
+pub fn alloc_pcid() -> Option<u16> {
+    for (i, word) in PCIDS.iter().enumerate() {
+        let mut current = word.load(Ordering::Relaxed);
+
+        loop {
+            // PCID 0 is never handed out.
+            let taken = if i == 0 { current | 1 } else { current };
+            if taken == u64::MAX {
+                break;
+            }
+
+            let bit = taken.trailing_ones();
+            match word.compare_exchange_weak(
+                current,
+                current | (1 << bit),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some((i * 64) as u16 + bit as u16),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    None
+}
+
+/// Flush the TLB entries of a PCID, and return it to the free PCIDs.
+///
+/// The entries are flushed first, so the next address space that takes
+/// the PCID never sees the translations of the previous one.
+pub fn free_pcid(pcid: u16) {
+    flash_pcid(pcid);
+    PCIDS[pcid as usize / 64]
+        .fetch_and(!(1 << (pcid % 64)), Ordering::Release);
+}
+
+/// # Safety
+/// PCIDs must be enabled, and the cpu must support `invpcid`.
+unsafe fn invpcid(kind: InvpcidType, pcid: u16, address: u64) {
+    // The descriptor holds the PCID, followed by the address of an
+    // individual address invalidation.
+    let descriptor: [u64; 2] = [pcid as u64, address];
+
+    unsafe {
+        asm!(
+            "invpcid {0}, [{1}]",
+            in(reg) kind as u64,
+            in(reg) &descriptor,
+            options(nostack, preserves_flags)
+        )
+    }
+}
+
+/// Flush the TLB entries of the active address space, except for global
+/// pages.
 pub fn flash_all() {
-    let cr3 = cr3::read();
-    let _ = cr3::write(cr3);
+    // `cr3::write` skips writing the value that is already there, which
+    // would not flush anything.
+    unsafe {
+        asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags)
+        )
+    }
+}
+
+/// Enable process context identifiers, if the cpu supports them.
+///
+/// When enabled, the low 12 bits of CR3 tag the TLB entries of the
+/// active address space, so switching between address spaces does not
+/// flush the TLB. This must be called while the PCID in CR3 is zero.
+///
+/// PCIDs are used only if the cpu supports `invpcid`, which flushes the
+/// shared kernel half from every PCID.
+///
+/// Returns true if PCIDs are enabled.
+pub fn enable_pcid() -> bool {
+    if !CpuFeatures::default().has_pcid()
+        || !StructuredCpuFeatures::default().has_invpcid()
+    {
+        return false;
+    }
+
+    cr4::write(cr4::read() | CR4_PCIDE);
+    true
 }
 
-// TODO: Implement pcid when processes are a thing.
+/// Returns true if process context identifiers are enabled.
+pub fn is_pcid_enabled() -> bool { cr4::read() & CR4_PCIDE != 0 }
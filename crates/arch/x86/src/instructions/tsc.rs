@@ -0,0 +1,17 @@
+use core::arch::asm;
+
+/// Read the time stamp counter, which counts the cycles of the cpu since
+/// it was reset.
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!(
+            "rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    ((high as u64) << 32) | (low as u64)
+}
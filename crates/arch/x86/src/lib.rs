@@ -7,12 +7,20 @@
 #![feature(const_result_trait_fn)]
 #![feature(iter_map_windows)]
 
+#[cfg(target_arch = "x86_64")]
+pub mod acpi;
+#[cfg(target_arch = "x86_64")]
+pub mod apic;
+#[cfg(target_arch = "x86_64")]
+pub mod hpet;
 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 pub mod instructions;
 #[cfg(target_arch = "x86_64")]
 pub mod memory_map;
 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 pub mod pic8259;
+#[cfg(target_arch = "x86_64")]
+pub mod pit;
 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 pub mod registers;
 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
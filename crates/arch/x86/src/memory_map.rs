@@ -5,17 +5,50 @@ use common::{
 use core::fmt::{self, Display, Formatter};
 use sync::rwlock::{RwLock, SpinRwLock};
 
+/// The entry is valid. Entries without it should be ignored.
+pub const ATTRIBUTE_ENABLED: u32 = 1 << 0;
+/// The memory is non-volatile, and should not be used as regular memory.
+pub const ATTRIBUTE_NON_VOLATILE: u32 = 1 << 1;
+
+/// An entry of the memory map, as the BIOS returned it.
+///
+/// The type is kept as a raw number, because the BIOS may return types
+/// that are unknown to [`MemoryRegionType`].
 #[repr(C)]
 #[derive(Clone, Debug, Copy)]
 pub struct MemoryRegionExtended {
     pub base_address: u64,
     pub length: u64,
-    pub region_type: MemoryRegionType,
+    pub region_type: u32,
+    /// The ACPI 3.0 attributes of the entry. The bootloader sets them to
+    /// [`ATTRIBUTE_ENABLED`] for BIOSes that do not return them.
     pub extended_attributes: u32,
 }
 
+impl MemoryRegionExtended {
+    /// The type of the region, according to its type and attributes.
+    ///
+    /// Returns [`None`] if the entry should be ignored.
+    fn region_type(&self) -> Option<MemoryRegionType> {
+        if self.extended_attributes & ATTRIBUTE_ENABLED == 0
+            || self.length == 0
+        {
+            return None;
+        }
+
+        if self.extended_attributes & ATTRIBUTE_NON_VOLATILE != 0 {
+            return Some(MemoryRegionType::Reserved);
+        }
+
+        Some(MemoryRegionType::from_e820(self.region_type))
+    }
+
+    /// The end of the region, clamped to the end of the address space.
+    fn end(&self) -> u64 { self.base_address.saturating_add(self.length) }
+}
+
 #[repr(C)]
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub struct MemoryRegion {
     pub base_address: u64,
     pub length: u64,
@@ -33,13 +66,19 @@ impl const Default for MemoryRegion {
     }
 }
 
-impl From<&MemoryRegionExtended> for MemoryRegion {
-    fn from(value: &MemoryRegionExtended) -> Self {
-        MemoryRegion {
-            base_address: value.base_address,
-            length: value.length,
-            region_type: value.region_type,
+impl MemoryRegion {
+    pub fn end(&self) -> u64 { self.base_address + self.length }
+
+    /// Extend this region with `next`, if it is right after this region
+    /// and has the same type. Returns true if it was merged.
+    fn merge(&mut self, next: &MemoryRegion) -> bool {
+        let mergeable = self.region_type == next.region_type
+            && self.end() == next.base_address;
+
+        if mergeable {
+            self.length += next.length;
         }
+        mergeable
     }
 }
 
@@ -109,13 +148,37 @@ impl Display for MemoryMap {
 }
 
 impl MemoryMap {
+    /// Parse the memory map that the BIOS returned into `buf`.
+    ///
+    /// The BIOS map may be unsorted, and its entries may overlap. The
+    /// parsed map is sorted, memory that is covered by a few entries gets
+    /// the type with the highest [`MemoryRegionType::priority`], adjacent
+    /// regions of the same type are merged, and gaps are filled with
+    /// [`MemoryRegionType::Filler`] regions.
+    ///
+    /// The first [`INIT_AREA_SIZE_BYTES`] of the first usable region are
+    /// marked as [`MemoryRegionType::UserEnterd`].
     pub fn parse_map(
         raw: &mut [MemoryRegionExtended],
         buf: &mut [MemoryRegion],
     ) -> Result<MemoryMap, MemoryMapError> {
+        // Drop the ignored entries, and sort the rest.
+        let mut entries = 0;
+        for n in 0..raw.len() {
+            if raw[n].region_type().is_some() {
+                raw[entries] = raw[n];
+                entries += 1;
+            }
+        }
+        let raw = &mut raw[..entries];
+        raw.sort_unstable_by_key(|entry| entry.base_address);
+
         let mut position = 0;
         let mut push =
             |region: MemoryRegion| -> Result<(), MemoryMapError> {
+                if position > 0 && buf[position - 1].merge(&region) {
+                    return Ok(());
+                }
                 if position >= buf.len() {
                     return Err(MemoryMapError::Overflow);
                 }
@@ -124,37 +187,57 @@ impl MemoryMap {
                 Ok(())
             };
 
-        let first_usable = raw
-            .iter_mut()
-            .find(|f| f.region_type == MemoryRegionType::Usable)
-            .ok_or(MemoryMapError::Empty)?;
-
-        assert!(first_usable.length > INIT_AREA_SIZE_BYTES);
+        // Walk over the points where an entry starts or ends. Between two
+        // such points, the type of the memory does not change.
+        let mut start =
+            raw.first().ok_or(MemoryMapError::Empty)?.base_address;
 
-        let init_entry = MemoryRegion {
-            base_address: first_usable.base_address,
-            length: INIT_AREA_SIZE_BYTES,
-            region_type: MemoryRegionType::UserEnterd,
-        };
+        while let Some(end) = raw
+            .iter()
+            .flat_map(|entry| [entry.base_address, entry.end()])
+            .filter(|&point| point > start)
+            .min()
+        {
+            let region_type = raw
+                .iter()
+                .filter(|entry| {
+                    entry.base_address <= start && start < entry.end()
+                })
+                .filter_map(|entry| entry.region_type())
+                .max_by_key(|region_type| region_type.priority())
+                .unwrap_or(MemoryRegionType::Filler);
 
-        first_usable.base_address += INIT_AREA_SIZE_BYTES;
-        first_usable.length -= INIT_AREA_SIZE_BYTES;
+            push(MemoryRegion {
+                base_address: start,
+                length: end - start,
+                region_type,
+            })?;
 
-        push(init_entry)?;
+            start = end;
+        }
 
-        for (left, right) in raw.iter().map_windows(|[a, b]| (*a, *b)) {
-            let filler = filler_entry(left, right);
+        let first_usable = buf[..position]
+            .iter()
+            .position(|r| r.region_type == MemoryRegionType::Usable)
+            .ok_or(MemoryMapError::Empty)?;
 
-            push(left.into())?;
+        assert!(buf[first_usable].length > INIT_AREA_SIZE_BYTES);
 
-            if let Some(f) = filler {
-                push(f)?;
-            }
+        if position >= buf.len() {
+            return Err(MemoryMapError::Overflow);
         }
 
-        let last = raw.last().ok_or(MemoryMapError::Empty)?;
+        buf.copy_within(first_usable..position, first_usable + 1);
+        position += 1;
 
-        push(last.into())?;
+        let (init, rest) = buf.split_at_mut(first_usable + 1);
+        init[first_usable] = MemoryRegion {
+            base_address: rest[0].base_address,
+            length: INIT_AREA_SIZE_BYTES,
+            region_type: MemoryRegionType::UserEnterd,
+        };
+        rest[0].base_address += INIT_AREA_SIZE_BYTES;
+        rest[0].length -= INIT_AREA_SIZE_BYTES;
 
         let capacity = buf.len();
 
@@ -167,24 +250,34 @@ impl MemoryMap {
             capacity,
         })
     }
-}
 
-/// Return a filler entry if there is a gap between A and B
-#[inline]
-fn filler_entry(
-    left: &MemoryRegionExtended,
-    right: &MemoryRegionExtended,
-) -> Option<MemoryRegion> {
-    assert!(left.base_address < right.base_address);
+    /// Mark the ACPI reclaimable regions as usable, and merge them with
+    /// their neighbours.
+    ///
+    /// This should be called once the ACPI tables in them are consumed.
+    /// `release` is called with each of the regions, to hand their memory
+    /// to the allocator.
+    pub fn reclaim_acpi(&self, mut release: impl FnMut(&MemoryRegion)) {
+        let mut regions = self.regions.write();
 
-    (left.base_address + left.length < right.base_address).then(|| {
-        let filler_base = left.base_address + left.length;
-        let length = right.base_address - filler_base;
+        for region in regions
+            .iter_mut()
+            .filter(|r| r.region_type == MemoryRegionType::Reclaimable)
+        {
+            release(region);
+            region.region_type = MemoryRegionType::Usable;
+        }
 
-        MemoryRegion {
-            base_address: filler_base,
-            length,
-            region_type: MemoryRegionType::Filler,
+        let mut length = 0;
+        for n in 0..regions.len() {
+            let region = regions[n];
+            if length == 0 || !regions[length - 1].merge(&region) {
+                regions[length] = region;
+                length += 1;
+            }
         }
-    })
+
+        let all = core::mem::take(&mut *regions);
+        *regions = &mut all[..length];
+    }
 }
@@ -50,6 +50,12 @@ impl ProgrammableInterruptController {
         }
     }
 
+    fn disable(&mut self) {
+        unsafe {
+            self.data.outb(u8::MAX);
+        }
+    }
+
     fn end_of_interrupt(&mut self) {
         unsafe {
             self.command.outb(PicCommandCode::EndOfInterrupt as u8);
@@ -107,6 +113,16 @@ impl CascadedPIC {
         }
     }
 
+    /// Mask all of the interrupts of both controllers, so the APIC can
+    /// take their place.
+    ///
+    /// The controllers should be initialized first, so their spurious
+    /// interrupts do not use the vectors of exceptions.
+    pub fn disable(&mut self) {
+        self.master.disable();
+        self.slave.disable();
+    }
+
     pub fn disable_irq(&mut self, irq: CascadedPicInterruptLine) {
         unsafe {
             if irq as u16 > PicInterruptLine::Irq7 as u16 {
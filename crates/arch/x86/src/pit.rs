@@ -0,0 +1,173 @@
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use common::enums::{PitAccessMode, PitChannel, PitMode, Port};
+
+use crate::instructions::port::PortExt;
+
+/// The frequency of the clock that drives the channels of the PIT, in Hz.
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// Bits of [`Port::SystemControl`].
+const CHANNEL2_GATE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL2_OUTPUT: u8 = 1 << 5;
+
+/// Returns the count that divides [`PIT_FREQUENCY`] into `hz`, where zero
+/// stands for 65536.
+fn divisor(hz: u32) -> u16 {
+    (PIT_FREQUENCY / hz.max(1)).clamp(1, u16::MAX as u32 + 1) as u16
+}
+
+/// Write a command and the count that follows it to a channel.
+///
+/// # Safety
+/// This changes what the output of the channel is connected to does.
+unsafe fn program(channel: PitChannel, mode: PitMode, count: u16) {
+    let mut data = match channel {
+        PitChannel::Channel0 => Port::PitChannel0,
+        PitChannel::Channel2 => Port::PitChannel2,
+    };
+
+    unsafe {
+        Port::PitCommand.outb(
+            channel as u8
+                | PitAccessMode::LowByteHighByte as u8
+                | mode as u8,
+        );
+        data.outb(count as u8);
+        data.outb((count >> 8) as u8);
+    }
+}
+
+/// Channel 0 of the PIT, which raises IRQ 0 at a fixed rate, and the
+/// amount of times that it did.
+pub struct Pit {
+    divisor: u16,
+    ticks: AtomicU64,
+}
+
+impl Pit {
+    /// Program channel 0 to raise IRQ 0 about `hz` times a second. The
+    /// rate is rounded to a divisor of [`PIT_FREQUENCY`], so it is between
+    /// about 18Hz and [`PIT_FREQUENCY`].
+    ///
+    /// # Safety
+    /// IRQ 0 must have a handler, which calls [`tick`](Self::tick).
+    pub unsafe fn start(hz: u32) -> Self {
+        let divisor = divisor(hz);
+        unsafe {
+            program(PitChannel::Channel0, PitMode::RateGenerator, divisor)
+        };
+
+        Self {
+            divisor,
+            ticks: AtomicU64::new(0),
+        }
+    }
+
+    /// Count an interrupt of channel 0.
+    pub fn tick(&self) { self.ticks.fetch_add(1, Ordering::Relaxed); }
+
+    /// Returns the amount of interrupts since the channel was started.
+    pub fn ticks(&self) -> u64 { self.ticks.load(Ordering::Relaxed) }
+
+    /// Returns the actual rate of the interrupts, in Hz.
+    pub fn frequency(&self) -> u32 { PIT_FREQUENCY / self.period_counts() }
+
+    /// Returns the time since the channel was started.
+    pub fn uptime(&self) -> Duration {
+        let counts = self.ticks() as u128 * self.period_counts() as u128;
+        Duration::from_nanos(
+            (counts * 1_000_000_000 / PIT_FREQUENCY as u128) as u64,
+        )
+    }
+
+    /// Busy wait until `ms` milliseconds pass.
+    ///
+    /// The time is measured in interrupts, so they must be enabled, and
+    /// the wait is as precise as their rate.
+    pub fn sleep_ms(&self, ms: u64) {
+        let end = self.uptime() + Duration::from_millis(ms);
+        while self.uptime() < end {
+            spin_loop();
+        }
+    }
+
+    /// The counts of the clock between two interrupts.
+    fn period_counts(&self) -> u32 {
+        match self.divisor {
+            0 => u16::MAX as u32 + 1,
+            divisor => divisor as u32,
+        }
+    }
+}
+
+/// Busy wait for `ms` milliseconds on channel 2 of the PIT, which does
+/// not raise an interrupt, so it can be used to calibrate other timers
+/// before interrupts are set up.
+///
+/// This uses the same channel as the PC speaker, so it stops any
+/// [`play_sound`].
+///
+/// # Panics
+/// The count of the channel is 16 bits wide, so it can wait at most 54ms.
+pub fn wait_ms(ms: u32) {
+    let count = u16::try_from(PIT_FREQUENCY * ms / 1000)
+        .expect("The PIT can wait at most 54ms at once");
+
+    unsafe {
+        // Open the gate of the channel without sounding the speaker.
+        let control = Port::SystemControl.inb();
+        Port::SystemControl
+            .outb((control & !SPEAKER_ENABLE) | CHANNEL2_GATE);
+
+        // The output goes low here, and high once the count reaches zero.
+        program(
+            PitChannel::Channel2,
+            PitMode::InterruptOnTerminalCount,
+            count,
+        );
+
+        while Port::SystemControl.inb() & CHANNEL2_OUTPUT == 0 {
+            spin_loop();
+        }
+
+        Port::SystemControl
+            .outb(control & !(SPEAKER_ENABLE | CHANNEL2_GATE));
+    }
+}
+
+/// Measure the rate of a counter, by reading it before and after waiting
+/// `ms` milliseconds with [`wait_ms`], and returns its counts in a
+/// millisecond.
+///
+/// A counter that counts down should be read as its distance from where
+/// it started.
+pub fn calibrate(ms: u32, mut read: impl FnMut() -> u64) -> u64 {
+    let start = read();
+    wait_ms(ms);
+    let end = read();
+    (end - start) / ms as u64
+}
+
+/// Sound the PC speaker at `hz`, until [`stop_sound`] is called.
+pub fn play_sound(hz: u32) {
+    unsafe {
+        program(PitChannel::Channel2, PitMode::SquareWave, divisor(hz));
+        let control = Port::SystemControl.inb();
+        Port::SystemControl.outb(control | CHANNEL2_GATE | SPEAKER_ENABLE);
+    }
+}
+
+/// Silence the PC speaker.
+pub fn stop_sound() {
+    unsafe {
+        let control = Port::SystemControl.inb();
+        Port::SystemControl
+            .outb(control & !(CHANNEL2_GATE | SPEAKER_ENABLE));
+    }
+}
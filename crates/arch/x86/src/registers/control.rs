@@ -3,10 +3,29 @@ use crate::registers::macros::impl_reg_read_write_u32;
 #[cfg(target_arch = "x86_64")]
 use crate::registers::macros::impl_reg_read_write_u64;
 
+#[cfg(target_arch = "x86_64")]
+impl_reg_read_write_u64!(cr0);
 #[cfg(target_arch = "x86_64")]
 impl_reg_read_write_u64!(cr3);
 #[cfg(target_arch = "x86_64")]
 impl_reg_read_write_u64!(cr2);
+#[cfg(target_arch = "x86_64")]
+impl_reg_read_write_u64!(cr4);
 
 #[cfg(target_arch = "x86")]
 impl_reg_read_write_u32!(cr3);
+
+/// The write protect bit of CR0.
+#[cfg(target_arch = "x86_64")]
+const CR0_WP: u64 = 1 << 16;
+
+/// Enable write protection of read-only pages in ring 0.
+///
+/// Until this is enabled, the kernel can write to any present page, and
+/// the writable bit of page table entries is only checked in user mode.
+#[cfg(target_arch = "x86_64")]
+pub fn enable_write_protect() { cr0::write(cr0::read() | CR0_WP); }
+
+/// Returns true if ring 0 writes to read-only pages fault.
+#[cfg(target_arch = "x86_64")]
+pub fn is_write_protect_enabled() -> bool { cr0::read() & CR0_WP != 0 }
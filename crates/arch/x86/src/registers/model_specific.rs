@@ -1,14 +1,22 @@
-use common::enums::MSR;
+use common::enums::{EFERFlag, MSR};
 use core::arch::asm;
 
+use crate::instructions::cpuid::ExtendedCpuFeatures;
+
 /// Read from the given model specific register
-pub fn rdmsr(msr: MSR) -> u64 {
+pub fn rdmsr(msr: MSR) -> u64 { rdmsr_at(msr as u32) }
+
+/// Read from the model specific register at the given index.
+///
+/// This is for the ranges of registers that have a single [`MSR`]
+/// variant for their first register, such as [`MSR::X2ApicRegisters`].
+pub fn rdmsr_at(index: u32) -> u64 {
     let low: u32;
     let high: u32;
     unsafe {
         asm!(
             "rdmsr",
-            in("ecx") msr as u32,
+            in("ecx") index,
             out("eax") low,
             out("edx") high,
         );
@@ -22,15 +30,50 @@ pub fn rdmsr(msr: MSR) -> u64 {
 /// This function writes arbitrary value to the register, which could lead
 /// into undefined behavior
 pub unsafe fn wrmsr(msr: MSR, value: u64) {
+    unsafe { wrmsr_at(msr as u32, value) }
+}
+
+/// Write `value` to the model specific register at the given index.
+///
+/// # Safety
+/// Same as [`wrmsr`].
+pub unsafe fn wrmsr_at(index: u32, value: u64) {
     let low = value as u32;
     let high = (value >> 32) as u32;
     unsafe {
         asm!(
             "wrmsr",
-            in("ecx") msr as u32,
+            in("ecx") index,
             in("eax") low,
             in("edx") high,
             options(nostack, preserves_flags),
         );
     }
 }
+
+/// Enable the execute disable bit of page table entries, if the cpu
+/// supports it.
+///
+/// Until this is enabled, the bit is reserved, and a page that sets it
+/// faults on any access.
+///
+/// Returns true if the bit is enabled.
+pub fn enable_nx() -> bool {
+    if !ExtendedCpuFeatures::default().has_nx() {
+        return false;
+    }
+
+    unsafe {
+        wrmsr(
+            MSR::EFER,
+            rdmsr(MSR::EFER) | EFERFlag::ExecuteDisableBitEnable as u64,
+        )
+    };
+    true
+}
+
+/// Returns true if the execute disable bit of page table entries is
+/// enabled.
+pub fn is_nx_enabled() -> bool {
+    rdmsr(MSR::EFER) & EFERFlag::ExecuteDisableBitEnable as u64 != 0
+}
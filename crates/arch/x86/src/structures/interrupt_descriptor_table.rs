@@ -17,7 +17,8 @@ use alloc::boxed::Box;
 use sync::mutex::SpinMutex;
 
 /// Global TSS segment
-pub static TSS: TaskStateSegment = TaskStateSegment::default();
+pub static TSS: SpinMutex<TaskStateSegment> =
+    SpinMutex::new(TaskStateSegment::default());
 
 use crate::{
     instructions::{self, interrupts::hlt},
@@ -73,17 +74,20 @@ impl InterruptDescriptorTable {
                 as *mut GlobalDescriptorTableLong)
         };
 
-        if TSS.iomb() < size_of::<TaskStateSegment>() as u16 {
-            panic!(
-                "I/O maps are not supported, change TSS IOMB into number \
-                 larger then 0x68"
+        let tss = {
+            let segment = TSS.lock();
+            if segment.iomb() < size_of::<TaskStateSegment>() as u16 {
+                panic!(
+                    "I/O maps are not supported, change TSS IOMB into \
+                     number larger then 0x68"
+                )
+            }
+            SystemSegmentDescriptor64::new(
+                &*segment as *const _ as u64,
+                (size_of::<TaskStateSegment>() - 1) as u32,
+                SystemSegmentType::TaskStateSegmentAvailable,
             )
-        }
-        let tss = SystemSegmentDescriptor64::new(
-            &TSS as *const _ as u64,
-            (size_of::<TaskStateSegment>() - 1) as u32,
-            SystemSegmentType::TaskStateSegmentAvailable,
-        );
+        };
         let mut boxed = Box::<InterruptDescriptorTable>::new_uninit();
 
         gdt.load_tss(tss);
@@ -133,9 +137,36 @@ impl InterruptDescriptorTable {
         dpl: ProtectionLevel,
         handler_type: InterruptType,
     ) {
-        let entry = InterruptDescriptorTableEntry::new(
+        self.set_interrupt_handler_with_stack(
+            routine,
             handler_address,
+            dpl,
+            handler_type,
             InterruptStackTable::None,
+        );
+    }
+
+    /// Set an interrupt handler for a given interrupt that runs on the
+    /// stack of the given IST entry, which is set in the [`TSS`].
+    ///
+    /// # Parameters
+    ///
+    /// - `routine`: The interrupt handler to set
+    /// - `handler_address`: The virtual address to the handler function
+    /// - `dpl`: The protection level on the handler entry
+    /// - `handler_type`: The type of the handler (Fault / Trap)
+    /// - `ist`: The InterruptStackTable index of the stack
+    pub fn set_interrupt_handler_with_stack(
+        &mut self,
+        routine: Interrupt,
+        handler_address: VirtualAddress,
+        dpl: ProtectionLevel,
+        handler_type: InterruptType,
+        ist: InterruptStackTable,
+    ) {
+        let entry = InterruptDescriptorTableEntry::new(
+            handler_address,
+            ist,
             InterruptAttributes::new()
                 .present(true)
                 .dpl(dpl)
@@ -14,7 +14,9 @@ pub struct PageEntryFlags {
     pub dirty: B1,
     pub huge_page: B1,
     pub global: B1,
-    pub full: B1,
+    /// The page is shared copy-on-write, and is read-only until it is
+    /// copied.
+    pub copy_on_write: B1,
     pub table: B1,
     pub root_entry: B1,
 }
@@ -1,9 +1,6 @@
 use core::ptr::{self, NonNull};
 
-use crate::{
-    registers::cr3,
-    structures::paging::{PageEntryFlags, PageTableEntry},
-};
+use crate::structures::paging::{PageEntryFlags, PageTableEntry};
 use common::{
     address_types::{Address, PhysicalAddress, VirtualAddress},
     constants::{PAGE_DIRECTORY_ENTRIES, REGULAR_PAGE_ALIGNMENT},
@@ -58,10 +55,21 @@ impl PageTable {
         }
     }
 
+    /// Returns true if none of the entries of this table are present.
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|e| !e.get_flags().is_present())
+    }
+
+    /// Returns the PML4 of the active address space, through the direct
+    /// map of the physical memory.
     #[inline]
+    #[cfg(target_arch = "x86_64")]
     pub fn current_table() -> NonNull<PageTable> {
-        NonNull::new(cr3::read() as usize as *mut PageTable)
-            .expect("Page table pointer is not present in cr3, found NULL")
+        // The low bits of cr3 hold the PCID of the address space.
+        let address = crate::registers::cr3::read() as usize & !0xfff;
+        assert_ne!(address, 0, "Page table pointer in cr3 is NULL");
+
+        PhysicalAddress::from(address).translate().as_non_null()
     }
 
     #[inline]
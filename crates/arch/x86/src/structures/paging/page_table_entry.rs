@@ -77,6 +77,17 @@ impl PageTableEntry {
             Err(EntryError::NoMapping)
         }
     }
+    /// Clear the entry, and return the physical address that was mapped
+    /// by it.
+    ///
+    /// The caller is responsible for flushing the TLB.
+    #[inline]
+    pub fn unmap(&mut self) -> Result<PhysicalAddress, EntryError> {
+        let address = self.mapped()?;
+        *self = PageTableEntry::new();
+        Ok(address)
+    }
+
     /// Return the physical address mapped by this table as
     /// a reference into a page table.
     ///
@@ -97,6 +108,22 @@ impl PageTableEntry {
         }
     }
 
+    /// Returns true if code may run from the memory that this entry maps.
+    #[inline]
+    pub fn is_executable(&self) -> bool { !self.is_not_executable() }
+
+    /// Set whether code may run from the memory that this entry maps.
+    ///
+    /// The caller is responsible for flushing the TLB.
+    ///
+    /// # Safety
+    /// The execute disable bit is reserved until it is enabled in EFER,
+    /// and any access through an entry that sets it faults.
+    #[inline]
+    pub unsafe fn set_executable(&mut self, executable: bool) {
+        self.set_not_executable(!executable);
+    }
+
     pub fn table_index(&self) -> usize {
         let table_offset = self as *const _ as usize & ((1 << 12) - 1);
         table_offset / size_of::<PageTableEntry>()
@@ -1,6 +1,6 @@
 use common::{
     address_types::{Address, VirtualAddress},
-    enums::{ProtectionLevel, Sections},
+    enums::{ProtectionLevel, Sections, interrupts::InterruptStackTable},
 };
 
 use macros::bitfields;
@@ -32,8 +32,23 @@ pub struct TaskStateSegment {
 
 impl TaskStateSegment {
     /// Return the I/O map base address
-    pub const fn iomb(&self) -> u16 {
-        self.io_map_offset
+    pub const fn iomb(&self) -> u16 { self.io_map_offset }
+
+    /// Set the stack the cpu switches to on interrupts that use the
+    /// given IST entry.
+    ///
+    /// # Panics
+    /// If `ist` is [`InterruptStackTable::None`], which means the current
+    /// stack is used.
+    pub fn set_interrupt_stack(
+        &mut self,
+        ist: InterruptStackTable,
+        top: VirtualAddress,
+    ) {
+        let index = (ist as usize)
+            .checked_sub(1)
+            .expect("Interrupt stack table entry 0 is not a stack");
+        self.int_stack_table[index] = top;
     }
 
     /// Construct a default TSS
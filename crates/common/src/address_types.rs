@@ -227,4 +227,13 @@ impl PhysicalAddress {
     pub const fn translate(&self) -> VirtualAddress {
         VirtualAddress(self.0 + PHYSICAL_MEMORY_OFFSET)
     }
+
+    /// The physical address of an address in the direct map of the
+    /// physical memory, which is the inverse of
+    /// [`PhysicalAddress::translate`].
+    #[inline]
+    #[cfg(target_arch = "x86_64")]
+    pub const fn from_direct_map(address: VirtualAddress) -> Self {
+        PhysicalAddress(address.0 - PHYSICAL_MEMORY_OFFSET)
+    }
 }
@@ -14,9 +14,21 @@ pub const IDENTITY_PAGE_TABLE_L3_OFFSET: usize = 0xc000;
 pub const IDENTITY_PAGE_TABLE_L2_OFFSET: usize = 0xd000;
 pub const TOP_IDENTITY_PAGE_TABLE_L3_OFFSET: usize = 0xe000;
 pub const TOP_IDENTITY_PAGE_TABLE_L2_OFFSET: usize = 0xf000;
+/// The physical address the kernel is loaded to.
 pub const KERNEL_OFFSET: u64 = 0x10000;
 
+/// The kernel is linked at the top 2GiB of the address space, where
+/// the code model of the kernel can address every symbol.
+#[cfg(target_arch = "x86_64")]
+pub const KERNEL_VIRTUAL_BASE: usize = 0xffffffff80000000;
+
 #[cfg(target_arch = "x86_64")]
 pub const PHYSICAL_MEMORY_OFFSET: usize = 0xffff800000000000;
 #[cfg(target_arch = "x86_64")]
 pub const PAGE_ALLOCATOR_OFFSET: usize = PHYSICAL_MEMORY_OFFSET + 0x100000;
+#[cfg(target_arch = "x86_64")]
+pub const KERNEL_STACKS_OFFSET: usize = 0xffffff0000000000;
+#[cfg(target_arch = "x86_64")]
+pub const VMALLOC_OFFSET: usize = 0xfffffe0000000000;
+#[cfg(target_arch = "x86_64")]
+pub const VMALLOC_SIZE: usize = crate::constants::GiB;
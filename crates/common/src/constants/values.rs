@@ -8,6 +8,8 @@ pub const HUGE_PAGE_SIZE: usize = BIG_PAGE_SIZE * PAGE_DIRECTORY_ENTRIES;
 pub const KiB: usize = 1024;
 #[allow(non_upper_case_globals)]
 pub const MiB: usize = 1024 * 1024;
+#[allow(non_upper_case_globals)]
+pub const GiB: usize = 1024 * MiB;
 pub const REGULAR_PAGE_ALIGNMENT: Alignment =
     unsafe { Alignment::new_unchecked(REGULAR_PAGE_SIZE) };
 pub const BIG_PAGE_ALIGNMENT: Alignment =
@@ -0,0 +1,116 @@
+use crate::error::ConversionError;
+use num_enum::{ConstIntoPrimitive, ConstTryFromPrimitive};
+
+/// The registers of the local APIC, by their offset from its base in
+/// xAPIC mode.
+///
+/// In x2APIC mode, each register is the model specific register at
+/// `0x800 + offset / 16`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+pub enum LocalApicRegister {
+    Id = 0x20,
+    Version = 0x30,
+    TaskPriority = 0x80,
+    EndOfInterrupt = 0xb0,
+    SpuriousInterruptVector = 0xf0,
+    ErrorStatus = 0x280,
+    LvtTimer = 0x320,
+    LvtLint0 = 0x350,
+    LvtLint1 = 0x360,
+    LvtError = 0x370,
+    TimerInitialCount = 0x380,
+    TimerCurrentCount = 0x390,
+    TimerDivideConfiguration = 0x3e0,
+}
+
+/// The registers of an I/O APIC, which are selected through its index
+/// register.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+pub enum IoApicRegister {
+    Id = 0x0,
+    Version = 0x1,
+    /// The first of the redirection entries, each of them takes two
+    /// registers.
+    RedirectionTable = 0x10,
+}
+
+/// The flags of the [`MSR::ApicBase`](crate::enums::MSR::ApicBase)
+/// register.
+pub enum ApicBaseFlag {
+    /// This is the bootstrap processor.
+    BootstrapProcessor = 1 << 8,
+    /// The local APIC is in x2APIC mode.
+    X2ApicEnable = 1 << 10,
+    /// The local APIC is enabled.
+    GlobalEnable = 1 << 11,
+}
+
+/// How an interrupt is delivered to its destination.
+#[repr(u8)]
+#[derive(
+    Debug, Clone, Copy, ConstTryFromPrimitive, ConstIntoPrimitive,
+)]
+#[num_enum(error_type(name = ConversionError<u8>, constructor = ConversionError::CantConvertFrom))]
+pub enum DeliveryMode {
+    Fixed = 0b000,
+    LowestPriority = 0b001,
+    SystemManagement = 0b010,
+    NonMaskable = 0b100,
+    Init = 0b101,
+    ExternalInterrupt = 0b111,
+}
+
+/// The types of the entries of the MADT.
+#[repr(u8)]
+#[derive(
+    Debug, Clone, Copy, ConstTryFromPrimitive, ConstIntoPrimitive,
+)]
+#[num_enum(error_type(name = ConversionError<u8>, constructor = ConversionError::CantConvertFrom))]
+pub enum MadtEntryType {
+    LocalApic = 0,
+    IoApic = 1,
+    InterruptSourceOverride = 2,
+    NmiSource = 3,
+    LocalApicNmi = 4,
+    LocalApicAddressOverride = 5,
+    LocalX2Apic = 9,
+}
+
+/// The legacy ISA IRQs that are used, which the MADT may connect to
+/// different global system interrupts.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum IsaIrq {
+    Timer = 0,
+    Keyboard = 1,
+}
+
+/// The modes of the local APIC timer, in its LVT entry.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+pub enum ApicTimerMode {
+    /// Count down once from the initial count.
+    OneShot = 0b00 << 17,
+    /// Count down from the initial count, and reload it when it expires.
+    Periodic = 0b01 << 17,
+    /// Fire when the TSC reaches the value of
+    /// [`MSR::TscDeadline`](crate::enums::MSR::TscDeadline).
+    TscDeadline = 0b10 << 17,
+}
+
+/// The values of the divide configuration register, which divide the bus
+/// clock that drives the local APIC timer.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+pub enum ApicTimerDivide {
+    By1 = 0b1011,
+    By2 = 0b0000,
+    By4 = 0b0001,
+    By8 = 0b0010,
+    By16 = 0b0011,
+    By32 = 0b1000,
+    By64 = 0b1001,
+    By128 = 0b1010,
+}
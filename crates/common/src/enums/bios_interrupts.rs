@@ -49,6 +49,37 @@ pub enum MemoryRegionType {
     Filler = 7,
 }
 
+impl MemoryRegionType {
+    /// Convert a type from the BIOS memory map.
+    ///
+    /// Types that are unknown to the kernel, like persistent memory, are
+    /// treated as [`MemoryRegionType::Reserved`].
+    pub const fn from_e820(value: u32) -> MemoryRegionType {
+        match value {
+            1 => MemoryRegionType::Usable,
+            3 => MemoryRegionType::Reclaimable,
+            4 => MemoryRegionType::ACPINVS,
+            5 => MemoryRegionType::BadMemory,
+            _ => MemoryRegionType::Reserved,
+        }
+    }
+
+    /// The priority of the type when regions overlap. The type with the
+    /// higher priority owns the overlapping memory, so memory is never
+    /// given to the allocator if any entry says it is not usable.
+    pub const fn priority(self) -> u8 {
+        match self {
+            MemoryRegionType::Filler => 0,
+            MemoryRegionType::Usable => 1,
+            MemoryRegionType::Reclaimable => 2,
+            MemoryRegionType::ACPINVS => 3,
+            MemoryRegionType::Reserved => 4,
+            MemoryRegionType::BadMemory => 5,
+            MemoryRegionType::UserEnterd => 6,
+        }
+    }
+}
+
 #[repr(u8)]
 #[allow(non_camel_case_types)]
 /// Video modes supported by the kernel.
@@ -1,6 +1,12 @@
 use num_enum::{ConstFromPrimitive, ConstIntoPrimitive};
+use strum::VariantArray;
 use strum_macros::VariantArray;
 
+use crate::{
+    address_types::{Address, PhysicalAddress},
+    constants::{GiB, MiB},
+};
+
 #[repr(u8)]
 #[derive(
     VariantArray,
@@ -50,3 +56,58 @@ impl BuddyOrder {
         }
     }
 }
+
+/// A range of physical memory that allocations can be restricted to, for
+/// devices that cannot address all of the memory.
+#[repr(u8)]
+#[derive(
+    VariantArray, Clone, Copy, PartialEq, Debug, Eq, PartialOrd, Ord,
+)]
+pub enum Zone {
+    /// The first 16MiB, which ISA DMA can address.
+    Dma = 0,
+    /// The memory below 4GiB, which 32-bit devices can address.
+    Dma32 = 1,
+    /// The rest of the memory.
+    Normal = 2,
+}
+
+impl Zone {
+    pub const COUNT: usize = Zone::VARIANTS.len();
+
+    /// The first address of the zone.
+    pub const fn start(self) -> u64 {
+        match self {
+            Zone::Dma => 0,
+            Zone::Dma32 => Zone::Dma.end(),
+            Zone::Normal => Zone::Dma32.end(),
+        }
+    }
+
+    /// The first address above the zone.
+    pub const fn end(self) -> u64 {
+        match self {
+            Zone::Dma => 16 * MiB as u64,
+            Zone::Dma32 => 4 * GiB as u64,
+            Zone::Normal => u64::MAX,
+        }
+    }
+
+    /// Returns the zone that holds the given address.
+    pub fn of(address: PhysicalAddress) -> Zone {
+        Zone::VARIANTS
+            .iter()
+            .copied()
+            .find(|zone| (address.as_usize() as u64) < zone.end())
+            .unwrap_or(Zone::Normal)
+    }
+
+    /// The zones that an allocation from this zone may be served from, in
+    /// the order they are tried.
+    ///
+    /// Memory of a lower zone is good for any device, so an allocation
+    /// falls back to the lower zones, but never to a higher one.
+    pub fn fallbacks(self) -> impl Iterator<Item = Zone> {
+        Zone::VARIANTS[..=self as usize].iter().rev().copied()
+    }
+}
@@ -71,6 +71,31 @@ pub enum CpuFeatureEdx {
     PBE = 1 << 31,
 }
 
+/// Features of the extended feature leaf (0x80000001) in edx.
+#[repr(u32)]
+pub enum CpuExtendedFeatureEdx {
+    SYSCALL = 1 << 11,
+    NX = 1 << 20,
+    PAGE1GB = 1 << 26,
+    RDTSCP = 1 << 27,
+    LM = 1 << 29,
+}
+
+/// Features of the advanced power management leaf (0x80000007) in edx.
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+pub enum CpuPowerManagementEdx {
+    /// The TSC runs at a constant rate in all of the power states.
+    INVARIANT_TSC = 1 << 8,
+}
+
+/// Features of the structured extended feature leaf (7, subleaf 0) in
+/// ebx.
+#[repr(u32)]
+pub enum CpuStructuredFeatureEbx {
+    INVPCID = 1 << 10,
+}
+
 pub struct QueryRegisters {
     pub eax: u32,
     pub ecx: u32,
@@ -79,6 +104,10 @@ pub struct QueryRegisters {
 pub enum CpuidQuery {
     GetVendorString,
     GetCpuFeatures,
+    GetStructuredFeatures,
+    GetExtendedCpuFeatures,
+    GetHighestExtendedLeaf,
+    GetPowerManagementFeatures,
 }
 
 impl CpuidQuery {
@@ -90,6 +119,21 @@ impl CpuidQuery {
             CpuidQuery::GetCpuFeatures => {
                 QueryRegisters { eax: 1, ecx: 0 }
             }
+            CpuidQuery::GetStructuredFeatures => {
+                QueryRegisters { eax: 7, ecx: 0 }
+            }
+            CpuidQuery::GetExtendedCpuFeatures => QueryRegisters {
+                eax: 0x80000001,
+                ecx: 0,
+            },
+            CpuidQuery::GetHighestExtendedLeaf => QueryRegisters {
+                eax: 0x80000000,
+                ecx: 0,
+            },
+            CpuidQuery::GetPowerManagementFeatures => QueryRegisters {
+                eax: 0x80000007,
+                ecx: 0,
+            },
         }
     }
 }
@@ -0,0 +1,19 @@
+/// The general registers of the HPET, by their offset from its base.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+pub enum HpetRegister {
+    Capabilities = 0x0,
+    Configuration = 0x10,
+    InterruptStatus = 0x20,
+    MainCounter = 0xf0,
+}
+
+/// The registers of each comparator of the HPET, by their offset from the
+/// registers of the comparator, which are at `0x100 + 0x20 * n`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+pub enum HpetTimerRegister {
+    Configuration = 0x0,
+    Comparator = 0x8,
+    FsbRoute = 0x10,
+}
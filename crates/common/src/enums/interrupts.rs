@@ -30,6 +30,12 @@ pub enum Interrupt {
     Timer = 0x20,
     Keyboard = 0x21,
     Ahci = 0x2a,
+    /// The vector of the local APIC timer.
+    ApicTimer = 0x30,
+    /// The vector of the comparators of the HPET.
+    Hpet = 0x31,
+    /// The vector of the spurious interrupts of the local APIC.
+    Spurious = 0xff,
 }
 #[repr(u8)]
 #[derive(Clone, Debug, Copy)]
@@ -54,7 +60,7 @@ pub enum InterruptType {
     Trap = 0xf,
 }
 
-mod page_fault {
+pub mod page_fault {
 
     use crate::error::ConversionError;
     use macros::bitfields;
@@ -132,8 +138,9 @@ mod page_fault {
         RelatedToS = 1,
     }
 
+    /// The error code the cpu pushes on a page fault.
     #[bitfields]
-    struct PageFaultError {
+    pub struct PageFaultErrorCode {
         #[flag(r, flag_type = P)]
         p: B1,
         #[flag(r, flag_type = WR)]
@@ -150,7 +157,34 @@ mod page_fault {
         ss: B1,
         #[flag(r, flag_type = Hlat)]
         hlat: B1,
+        #[flag(rc(0))]
+        reserved: B7,
         #[flag(r, flag_type = Sgx)]
         sgx: B1,
     }
+
+    impl PageFaultErrorCode {
+        /// A short description of the access that caused the fault.
+        pub fn access(&self) -> &'static str {
+            match (self.get_fetch(), self.get_wr()) {
+                (Fetch::CauseByInstructionFetch, _) => {
+                    "instruction fetch from"
+                }
+                (_, WR::WriteOperation) => "write to",
+                _ => "read from",
+            }
+        }
+
+        pub fn is_write(&self) -> bool {
+            matches!(self.get_wr(), WR::WriteOperation)
+        }
+
+        /// Returns true if the page was not present, and false if the
+        /// fault is a protection violation.
+        pub fn is_not_present(&self) -> bool {
+            matches!(self.get_p(), P::NonPresentPage)
+        }
+    }
 }
+
+pub use page_fault::PageFaultErrorCode;
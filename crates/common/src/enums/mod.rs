@@ -1,30 +1,36 @@
 pub mod ahci;
+pub mod apic;
 pub mod ata;
 pub mod bios_interrupts;
 pub mod buddy;
 pub mod cpuid;
 pub mod general;
 pub mod global_descriptor_table;
+pub mod hpet;
 pub mod interrupts;
 pub mod keyboard;
 pub mod model_specific;
 pub mod paging;
 pub mod pci;
 pub mod pic8259;
+pub mod pit;
 pub mod ports;
 pub mod vga;
 
 pub use ahci::*;
+pub use apic::*;
 pub use ata::*;
 pub use bios_interrupts::*;
 pub use buddy::*;
 pub use cpuid::*;
 pub use general::*;
 pub use global_descriptor_table::*;
+pub use hpet::*;
 pub use keyboard::*;
 pub use model_specific::*;
 pub use paging::*;
 pub use pci::*;
 pub use pic8259::*;
+pub use pit::*;
 pub use ports::*;
 pub use vga::*;
@@ -1,6 +1,12 @@
 #[derive(PartialEq, Eq)]
 #[repr(u32)]
 pub enum MSR {
+    ApicBase = 0x1b,
+    /// The TSC value at which the local APIC timer fires in TSC-deadline
+    /// mode.
+    TscDeadline = 0x6e0,
+    /// The first of the registers of the local APIC in x2APIC mode.
+    X2ApicRegisters = 0x800,
     EFER = 0xc0000080,
 }
 
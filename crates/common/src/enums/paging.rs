@@ -125,6 +125,17 @@ impl PageSize {
         }
     }
 
+    /// The size of the page in bytes.
+    pub const fn size(&self) -> usize {
+        match self {
+            PageSize::Regular => REGULAR_PAGE_SIZE,
+
+            PageSize::Big => BIG_PAGE_SIZE,
+
+            PageSize::Huge => HUGE_PAGE_SIZE,
+        }
+    }
+
     pub const fn size_in_regular_pages(&self) -> usize {
         match self {
             PageSize::Regular => 1,
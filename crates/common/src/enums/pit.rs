@@ -0,0 +1,31 @@
+/// The channels of the PIT, in the select bits of a command.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum PitChannel {
+    /// Connected to IRQ 0.
+    Channel0 = 0b00 << 6,
+    /// Connected to the PC speaker, and gated through
+    /// [`Port::SystemControl`](crate::enums::Port::SystemControl).
+    Channel2 = 0b10 << 6,
+}
+
+/// How the count of a channel is accessed, in a command.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum PitAccessMode {
+    /// Latch the current count, so it can be read as low and high bytes.
+    LatchCount = 0b00 << 4,
+    LowByteHighByte = 0b11 << 4,
+}
+
+/// The operating modes of a channel, in a command.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum PitMode {
+    /// The output goes high when the count reaches zero.
+    InterruptOnTerminalCount = 0b000 << 1,
+    /// The output pulses low each time the count reaches zero.
+    RateGenerator = 0b010 << 1,
+    /// The output is a square wave at the frequency of the count.
+    SquareWave = 0b011 << 1,
+}
@@ -8,6 +8,12 @@ pub enum Port {
     SlavePicCmd = 0xA0,
     SlavePicData = 0xA1,
     IOWait = 0x80,
+    PitChannel0 = 0x40,
+    PitChannel2 = 0x42,
+    PitCommand = 0x43,
+    /// Gates channel 2 of the PIT and the PC speaker, and reads the
+    /// output of channel 2.
+    SystemControl = 0x61,
     VgaControl = 0x3D4,
     VgaData = 0x3D5,
     PciConfigAddress = 0xCF8,
@@ -0,0 +1,27 @@
+use thiserror::Error;
+
+use crate::error::MappingError;
+
+#[derive(Error, Debug)]
+pub enum AcpiError {
+    #[error("Could not find the RSDP")]
+    RsdpNotFound,
+    #[error("The checksum of the `{0}` table is invalid")]
+    InvalidChecksum(&'static str),
+    #[error("There is no `{0}` table")]
+    TableNotFound(&'static str),
+    #[error("Could not map the table: {0}")]
+    Mapping(#[from] MappingError),
+}
+
+#[derive(Error, Debug)]
+pub enum ApicError {
+    #[error("The cpu has no local APIC")]
+    NotSupported,
+    #[error("There is no room for more I/O APICs")]
+    TooManyIoApics,
+    #[error("No I/O APIC handles the global system interrupt {0}")]
+    UnknownGsi(u32),
+    #[error("The local APIC {0} cannot be reached from an I/O APIC")]
+    UnreachableLocalApic(u32),
+}
@@ -1,7 +1,11 @@
+pub mod acpi;
 pub mod ahci;
 pub mod general;
 pub mod paging;
+pub mod timer;
 
+pub use acpi::*;
 pub use ahci::*;
 pub use general::*;
 pub use paging::*;
+pub use timer::*;
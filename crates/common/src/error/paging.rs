@@ -26,4 +26,48 @@ pub enum MappingError {
     AlreadyMapped,
     #[error("The table the address belongs to does not exist")]
     TableDoesNotExist,
+    #[error("The given address is not mapped")]
+    NotMapped,
+    #[error("The address is not aligned to the size of its page")]
+    Unaligned,
+    #[error("Could not allocate a page table")]
+    TableAllocation,
+}
+
+#[derive(Error, Debug)]
+pub enum RegionError {
+    #[error("The region is not page aligned")]
+    Unaligned,
+    #[error("The region overlaps the region `{0}`")]
+    Overlaps(&'static str),
+    #[error("There is no room for more regions")]
+    Full,
+    #[error("Could not allocate a page to back the region")]
+    OutOfMemory,
+    #[error("Could not map the page: {0}")]
+    Mapping(#[from] MappingError),
+}
+
+#[derive(Error, Debug)]
+pub enum CowError {
+    #[error("The page is not shared copy-on-write")]
+    NotCopyOnWrite,
+    #[error("Only regular pages can be shared copy-on-write")]
+    NotRegular,
+    #[error("Could not allocate a page for the copy")]
+    OutOfMemory,
+    #[error("Could not map the page: {0}")]
+    Mapping(#[from] MappingError),
+}
+
+#[derive(Error, Debug)]
+pub enum StackError {
+    #[error("A kernel stack of {0} bytes does not fit in a stack slot")]
+    TooLarge(usize),
+    #[error("There is no room for more kernel stacks")]
+    Full,
+    #[error("Could not allocate a page for the stack")]
+    OutOfMemory,
+    #[error("Could not map the stack: {0}")]
+    Mapping(#[from] MappingError),
 }
@@ -0,0 +1,25 @@
+use core::time::Duration;
+
+use thiserror::Error;
+
+use crate::error::ApicError;
+
+#[derive(Error, Debug)]
+pub enum TimerError {
+    #[error("There is no room for more pending timers")]
+    QueueFull,
+    #[error("Timed out after {0:?}")]
+    TimedOut(Duration),
+}
+
+#[derive(Error, Debug)]
+pub enum HpetError {
+    #[error("The HPET has no timer {0}")]
+    UnknownTimer(u8),
+    #[error("The timer {0} of the HPET cannot be periodic")]
+    NotPeriodic(u8),
+    #[error("The timer {0} of the HPET cannot interrupt the I/O APICs")]
+    Unroutable(u8),
+    #[error("Could not route the interrupt of the timer: {0}")]
+    Apic(#[from] ApicError),
+}
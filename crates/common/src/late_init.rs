@@ -1,21 +1,29 @@
 use core::{
+    cell::UnsafeCell,
     mem::MaybeUninit,
     ops::{Deref, DerefMut},
 };
 
-pub struct LateInit<T>(MaybeUninit<T>);
+/// A value that is written once after the static that holds it is
+/// created.
+///
+/// The cell keeps the static writable, because statics without interior
+/// mutability may be placed in the read-only sections.
+pub struct LateInit<T>(UnsafeCell<MaybeUninit<T>>);
+
+unsafe impl<T: Sync> Sync for LateInit<T> {}
 
 impl<T> LateInit<T> {
     pub const fn uninit() -> LateInit<T> {
-        LateInit::<T>(MaybeUninit::uninit())
+        LateInit::<T>(UnsafeCell::new(MaybeUninit::uninit()))
     }
 
     pub const fn new(val: T) -> LateInit<T> {
-        LateInit::<T>(MaybeUninit::new(val))
+        LateInit::<T>(UnsafeCell::new(MaybeUninit::new(val)))
     }
 
     pub fn init(&self, val: T) -> &mut T {
-        let ptr = self.0.as_ptr() as *mut T;
+        let ptr = self.0.get() as *mut T;
         unsafe {
             ptr.write_volatile(val);
             &mut *ptr
@@ -23,7 +31,7 @@ impl<T> LateInit<T> {
     }
 
     pub const fn init_const(&self, val: T) -> &mut T {
-        let ptr = self.0.as_ptr() as *mut T;
+        let ptr = self.0.get() as *mut T;
         unsafe {
             ptr.write(val);
             &mut *ptr
@@ -31,17 +39,17 @@ impl<T> LateInit<T> {
     }
 
     pub const fn assume_init_ref(&self) -> &T {
-        unsafe { self.0.assume_init_ref() }
+        unsafe { (*self.0.get()).assume_init_ref() }
     }
 
     pub const fn assume_init_mut(&mut self) -> &mut T {
-        unsafe { self.0.assume_init_mut() }
+        unsafe { self.0.get_mut().assume_init_mut() }
     }
 }
 
 impl<T: Clone + Copy> LateInit<T> {
     pub const fn assume_init(&self) -> T {
-        unsafe { self.0.assume_init() }
+        unsafe { (*self.0.get()).assume_init() }
     }
 }
 
@@ -50,13 +58,13 @@ impl<T> const Deref for LateInit<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { self.0.assume_init_ref() }
+        unsafe { (*self.0.get()).assume_init_ref() }
     }
 }
 
 #[rustfmt::skip]
 impl<T> const DerefMut for LateInit<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { self.0.assume_init_mut() }
+        unsafe { self.0.get_mut().assume_init_mut() }
     }
 }
@@ -6,11 +6,14 @@
 
 use core::cell::OnceCell;
 
-use common::enums::{CascadedPicInterruptLine, PS2ScanCode, Port};
+use common::{
+    enums::{PS2ScanCode, Port},
+    late_init::LateInit,
+};
 use sync::mutex::SpinMutex;
 use x86::{
+    apic::LocalApic,
     instructions::{interrupts, port::PortExt},
-    pic8259::CascadedPIC,
     structures::interrupt_descriptor_table::InterruptStackFrame,
 };
 
@@ -20,7 +23,7 @@ pub mod ps2_keyboard;
 
 unsafe extern "Rust" {
     unsafe static KEYBOARD: SpinMutex<OnceCell<Keyboard>>;
-    unsafe static PIC: SpinMutex<CascadedPIC>;
+    unsafe static LOCAL_APIC: LateInit<LocalApic>;
 }
 
 pub extern "x86-interrupt" fn keyboard_handler(
@@ -66,8 +69,7 @@ pub extern "x86-interrupt" fn keyboard_handler(
             }
             _ => {}
         }
-        PIC.lock()
-            .end_of_interrupt(CascadedPicInterruptLine::Keyboard);
+        LOCAL_APIC.end_of_interrupt();
         interrupts::enable();
     }
 }
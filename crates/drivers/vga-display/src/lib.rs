@@ -13,6 +13,7 @@ pub mod writer;
 
 use color_code::ColorCode;
 use common::{
+    address_types::{Address, PhysicalAddress},
     constants::VGA_BUFFER_PTR,
     enums::{Port, VgaCommand},
     late_init::LateInit,
@@ -139,7 +140,10 @@ unsafe extern "Rust" {
 pub fn vga_init() {
     let screen = unsafe {
         core::slice::from_raw_parts_mut(
-            VGA_BUFFER_PTR as *mut ScreenChar,
+            PhysicalAddress::from(VGA_BUFFER_PTR as usize)
+                .translate()
+                .as_non_null::<ScreenChar>()
+                .as_ptr(),
             80 * 25,
         )
     };
@@ -0,0 +1,130 @@
+extern crate alloc;
+
+use core::ptr::NonNull;
+
+use alloc::alloc::AllocError;
+
+use common::{
+    address_types::{Address, PhysicalAddress, VirtualAddress},
+    constants::PAGE_DIRECTORY_ENTRIES,
+    enums::PageTableLevel,
+};
+use x86::{
+    instructions::tlb, registers::cr3, structures::paging::PageTable,
+};
+
+use crate::alloc::{delete_table, free_table, new_table};
+
+/// The first PML4 entry of the kernel half of the address space.
+const KERNEL_HALF: usize = PAGE_DIRECTORY_ENTRIES / 2;
+
+/// Don't flush the TLB entries of the new PCID when writing CR3.
+const CR3_NO_FLUSH: u64 = 1 << 63;
+
+/// A set of page tables, with its own lower half and a kernel half that
+/// is shared with every other address space.
+pub struct AddressSpace {
+    pml4: NonNull<PageTable>,
+    /// The PCID that tags the TLB entries of this address space, if PCIDs
+    /// are enabled and there are any left. It is freed with the address
+    /// space.
+    pcid: Option<u16>,
+}
+
+impl AddressSpace {
+    /// Create a new address space that shares the kernel half of the
+    /// active address space.
+    pub fn new() -> Result<AddressSpace, AllocError> {
+        let mut pml4 = new_table()?;
+
+        let current = unsafe { PageTable::current_table().as_ref() };
+        let entries = unsafe { &mut pml4.as_mut().entries };
+
+        entries[KERNEL_HALF..]
+            .copy_from_slice(&current.entries[KERNEL_HALF..]);
+
+        let pcid = tlb::is_pcid_enabled().then(tlb::alloc_pcid).flatten();
+
+        Ok(AddressSpace { pml4, pcid })
+    }
+
+    /// The physical address of the PML4 of this address space.
+    pub fn pml4(&self) -> PhysicalAddress {
+        PhysicalAddress::from_direct_map(VirtualAddress::from(self.pml4))
+    }
+
+    pub fn pcid(&self) -> Option<u16> { self.pcid }
+
+    /// Returns true if this is the address space the cpu is using.
+    pub fn is_active(&self) -> bool {
+        PageTable::current_table() == self.pml4
+    }
+
+    /// Switch the cpu to this address space.
+    ///
+    /// With a PCID, the TLB entries of the previous address space are
+    /// kept, and the TLB entries of this address space are reused.
+    /// Without one, the whole TLB is flushed, except for global pages.
+    ///
+    /// # Safety
+    /// The kernel must be mapped the same in this address space, and the
+    /// address space must not be dropped while it is active.
+    pub unsafe fn activate(&self) {
+        let value = match self.pcid {
+            Some(pcid) => {
+                self.pml4().as_usize() as u64 | pcid as u64 | CR3_NO_FLUSH
+            }
+            None => self.pml4().as_usize() as u64,
+        };
+
+        cr3::write(value);
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Free the tables of the lower half. The pages they map are owned by
+    /// whoever mapped them, and the kernel half is shared.
+    fn drop(&mut self) {
+        debug_assert!(
+            !self.is_active(),
+            "Dropped the active address space"
+        );
+
+        let entries = unsafe { &mut self.pml4.as_mut().entries };
+
+        for entry in entries[..KERNEL_HALF].iter_mut() {
+            if let Ok(table) = entry.mapped_table() {
+                unsafe { free_tables(table, PageTableLevel::PDPT) };
+                unsafe { free_table(entry) }.expect("Entry is not mapped");
+            }
+        }
+
+        unsafe { delete_table(self.pml4) };
+
+        if let Some(pcid) = self.pcid {
+            tlb::free_pcid(pcid);
+        }
+    }
+}
+
+/// Free every table below a table of the given level.
+///
+/// # Safety
+/// The tables must be allocated with
+/// [`alloc_table`](crate::alloc::alloc_table), and not used by anything
+/// else.
+unsafe fn free_tables(
+    mut table: NonNull<PageTable>,
+    level: PageTableLevel,
+) {
+    let Some(next) = level.next() else {
+        return;
+    };
+
+    for entry in unsafe { table.as_mut().entries.iter_mut() } {
+        if let Ok(child) = entry.mapped_table() {
+            unsafe { free_tables(child, next) };
+            unsafe { free_table(entry) }.expect("Entry is not mapped");
+        }
+    }
+}
@@ -1,23 +1,40 @@
 extern crate alloc;
 
+#[cfg(feature = "alloc-tracking")]
+pub mod tracking;
+
 use core::{alloc::Layout, ptr::NonNull};
 
-use alloc::alloc::{AllocError, GlobalAlloc, alloc};
+#[cfg(feature = "alloc-tracking")]
+use core::panic::Location;
+
+use alloc::alloc::{AllocError, GlobalAlloc, alloc_zeroed, dealloc};
 
 use common::{
     address_types::{Address, PhysicalAddress, VirtualAddress},
+    constants::{
+        IDENTITY_PAGE_TABLE_L4_OFFSET, TOP_IDENTITY_PAGE_TABLE_L2_OFFSET,
+    },
     enums::{PageSize, PageTableLevel},
     error::{EntryError, MappingError},
     late_init::LateInit,
 };
-use x86::structures::paging::{PageEntryFlags, PageTable, PageTableEntry};
-
-use crate::println;
+use sync::mutex::SpinMutex;
+#[cfg(feature = "alloc-tracking")]
+use tracking::{AllocationSummary, AllocationTracker, CallSite};
+use x86::{
+    instructions::tlb,
+    structures::paging::{
+        PageEntryFlags, PageTable, PageTableEntry, VirtualAddressExt,
+    },
+};
 
 pub type Allocator = dyn GlobalAlloc + Send + Sync;
 
 pub struct GlobalAllocator<'a> {
     allocator: LateInit<&'a Allocator>,
+    #[cfg(feature = "alloc-tracking")]
+    tracker: SpinMutex<AllocationTracker>,
 }
 
 impl<'a> GlobalAllocator<'a> {
@@ -28,81 +45,342 @@ impl<'a> GlobalAllocator<'a> {
     pub const fn uninit() -> Self {
         Self {
             allocator: LateInit::uninit(),
+            #[cfg(feature = "alloc-tracking")]
+            tracker: SpinMutex::new(AllocationTracker::new()),
+        }
+    }
+
+    /// Allocate memory for `layout`.
+    ///
+    /// With `alloc-tracking`, the allocation is counted for the caller,
+    /// unlike allocations through the `alloc` crate.
+    #[cfg_attr(feature = "alloc-tracking", track_caller)]
+    pub fn kmalloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { GlobalAlloc::alloc(self, layout) })
+    }
+
+    /// Allocate zeroed memory for `layout`, see
+    /// [`GlobalAllocator::kmalloc`].
+    #[cfg_attr(feature = "alloc-tracking", track_caller)]
+    pub fn kzalloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { GlobalAlloc::alloc_zeroed(self, layout) })
+    }
+
+    /// Free memory that was allocated with [`GlobalAllocator::kmalloc`]
+    /// or [`GlobalAllocator::kzalloc`].
+    ///
+    /// # Safety
+    /// The memory must be allocated with the same `layout`, and not used
+    /// anymore.
+    pub unsafe fn kfree(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { GlobalAlloc::dealloc(self, ptr.as_ptr(), layout) }
+    }
+}
+
+#[cfg(feature = "alloc-tracking")]
+impl<'a> GlobalAllocator<'a> {
+    /// Returns a snapshot of the allocations that are held.
+    pub fn allocation_summary(&self) -> AllocationSummary {
+        self.tracker.lock().summary()
+    }
+
+    /// Returns a copy of the counters of every call site.
+    pub fn call_sites(
+        &self,
+    ) -> [Option<CallSite>; tracking::MAX_CALL_SITES] {
+        let tracker = self.tracker.lock();
+        let mut sites = [None; tracking::MAX_CALL_SITES];
+        for (slot, site) in sites.iter_mut().zip(tracker.sites()) {
+            *slot = Some(*site);
+        }
+        sites
+    }
+
+    /// Print the call sites that hold allocations, the ones that hold the
+    /// most bytes first, followed by the summary.
+    ///
+    /// Allocations that are made through the `alloc` crate, such as of a
+    /// `Box` or a `Vec`, are reported at the `#[global_allocator]` item,
+    /// since the location is not tracked through it. Allocate with
+    /// [`GlobalAllocator::kmalloc`] to count them for their call site.
+    pub fn dump_leaks(&self) {
+        // The counters are copied out, so nothing holds the lock while
+        // printing.
+        let mut sites = self.call_sites();
+        let summary = self.allocation_summary();
+
+        sites.sort_unstable_by_key(|s| {
+            core::cmp::Reverse(s.map_or(0, |s| s.live_bytes))
+        });
+
+        for site in sites.iter().flatten() {
+            if site.live_allocations() == 0 {
+                continue;
+            }
+            crate::println!(
+                "{}: {} live, {} bytes ({} allocated, {} freed)",
+                site.caller,
+                site.live_allocations(),
+                site.live_bytes,
+                site.allocations,
+                site.frees
+            );
+        }
+
+        crate::println!("{}", summary);
+    }
+
+    /// Record the allocation, unless it failed.
+    fn track(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        caller: &'static Location<'static>,
+    ) {
+        if !ptr.is_null() {
+            self.tracker.lock().track(ptr.addr(), layout, caller);
         }
     }
 }
 
 unsafe impl<'a> GlobalAlloc for GlobalAllocator<'a> {
+    #[cfg_attr(feature = "alloc-tracking", track_caller)]
     unsafe fn alloc(&self, layout: alloc::alloc::Layout) -> *mut u8 {
-        unsafe { self.allocator.alloc(layout) }
+        let ptr = unsafe { self.allocator.alloc(layout) };
+        #[cfg(feature = "alloc-tracking")]
+        self.track(ptr, layout, Location::caller());
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::alloc::Layout) {
+        #[cfg(feature = "alloc-tracking")]
+        self.tracker.lock().untrack(ptr.addr(), layout);
         unsafe { self.allocator.dealloc(ptr, layout) }
     }
 
+    #[cfg_attr(feature = "alloc-tracking", track_caller)]
     unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
-        unsafe { self.allocator.alloc_zeroed(layout) }
+        let ptr = unsafe { self.allocator.alloc_zeroed(layout) };
+        #[cfg(feature = "alloc-tracking")]
+        self.track(ptr, layout, Location::caller());
+        ptr
     }
 
+    #[cfg_attr(feature = "alloc-tracking", track_caller)]
     unsafe fn realloc(
         &self,
         ptr: *mut u8,
         layout: core::alloc::Layout,
         new_size: usize,
     ) -> *mut u8 {
-        unsafe { self.allocator.realloc(ptr, layout, new_size) }
+        let new_ptr =
+            unsafe { self.allocator.realloc(ptr, layout, new_size) };
+        #[cfg(feature = "alloc-tracking")]
+        if !new_ptr.is_null() {
+            let new_layout = unsafe {
+                Layout::from_size_align_unchecked(new_size, layout.align())
+            };
+            let mut tracker = self.tracker.lock();
+            tracker.untrack(ptr.addr(), layout);
+            tracker.track(new_ptr.addr(), new_layout, Location::caller());
+        }
+        new_ptr
     }
 }
 
-/// Allocate a new table, point to it from a given [`PageTableEntry`]
+/// Allocates and frees page tables, see [`set_table_allocator`].
+#[derive(Clone, Copy)]
+pub struct TableAllocator {
+    /// Returns an empty table, which is accessed through the direct map.
+    pub alloc: fn() -> Option<NonNull<PageTable>>,
+    /// Frees a table that was returned by `alloc`, which may still have
+    /// entries.
+    pub free: unsafe fn(NonNull<PageTable>),
+}
+
+static TABLE_ALLOCATOR: SpinMutex<Option<TableAllocator>> =
+    SpinMutex::new(None);
+
+/// Take the page tables from the given allocator, such as a slab cache,
+/// instead of the heap.
+///
+/// The tables that were allocated from the heap before this is called
+/// must never be freed.
+pub fn set_table_allocator(allocator: TableAllocator) {
+    *TABLE_ALLOCATOR.lock() = Some(allocator);
+}
+
+/// Allocate an empty table from the table allocator, or from the heap
+/// until it is set.
+pub(crate) fn new_table() -> Result<NonNull<PageTable>, AllocError> {
+    let allocator = *TABLE_ALLOCATOR.lock();
+
+    let table = match allocator {
+        Some(allocator) => (allocator.alloc)(),
+        None => NonNull::new(unsafe {
+            alloc_zeroed(Layout::new::<PageTable>())
+        })
+        .map(NonNull::cast),
+    };
+
+    table.ok_or(AllocError)
+}
+
+/// Free a table that was allocated with [`new_table`].
+///
+/// # Safety
+/// The table must not be used anymore.
+pub(crate) unsafe fn delete_table(table: NonNull<PageTable>) {
+    let allocator = *TABLE_ALLOCATOR.lock();
+
+    match allocator {
+        Some(allocator) => unsafe { (allocator.free)(table) },
+        None => unsafe {
+            dealloc(table.as_ptr().cast(), Layout::new::<PageTable>())
+        },
+    }
+}
+
+/// Allocate a new empty table, point to it from a given
+/// [`PageTableEntry`]
+///
+/// The table is accessed through the direct map of the physical memory.
 pub fn alloc_table(
     entry: &mut PageTableEntry,
 ) -> Result<NonNull<PageTable>, AllocError> {
-    let ptr = unsafe { alloc(Layout::new::<PageTable>()) };
-    let table = NonNull::new(ptr).ok_or(AllocError)?.cast::<PageTable>();
+    let table = new_table()?;
     unsafe {
         entry.map(
-            PhysicalAddress::new_unchecked(ptr as usize),
+            PhysicalAddress::from_direct_map(VirtualAddress::from(table)),
             PageEntryFlags::table_flags(),
         );
     }
     Ok(table)
 }
 
+/// Free the table that a [`PageTableEntry`] points to, and clear the
+/// entry.
+///
+/// The tables that were set up by the bootloader are static, so the entry
+/// is cleared but they are never freed.
+///
+/// # Safety
+/// The table must not be used anymore, and must be allocated with
+/// [`alloc_table`] unless it is one of the static tables.
+pub unsafe fn free_table(
+    entry: &mut PageTableEntry,
+) -> Result<(), EntryError> {
+    let address = entry.unmap()?;
+    unsafe { release_table(address) };
+    Ok(())
+}
+
+/// Free the table at a physical address, unless it is one of the static
+/// tables of the bootloader.
+///
+/// # Safety
+/// Same as [`free_table`], and no entry may point to the table anymore.
+unsafe fn release_table(address: PhysicalAddress) {
+    if !(IDENTITY_PAGE_TABLE_L4_OFFSET..=TOP_IDENTITY_PAGE_TABLE_L2_OFFSET)
+        .contains(&address.as_usize())
+    {
+        unsafe { delete_table(address.translate().as_non_null()) };
+    }
+}
+
 #[extend::ext(name = VirtualAddressMapping)]
 pub impl VirtualAddress {
+    /// Returns the entry that should map this address with a page of the
+    /// given size, allocating the missing tables on the way.
     #[cfg(target_arch = "x86_64")]
-    fn walk_map(&self) -> impl Iterator<Item = NonNull<PageTableEntry>> {
+    fn walk_map(
+        &self,
+        page_size: PageSize,
+    ) -> Result<NonNull<PageTableEntry>, MappingError> {
         let mut table = PageTable::current_table();
-        let mut level = Some(PageTableLevel::PML4);
-        let mut prev_entry: Option<NonNull<PageTableEntry>> = None;
-        ::core::iter::from_fn(move || {
-            if let Some(mut prev_entry) = prev_entry {
-                table = match unsafe { prev_entry.as_ref().mapped_table() }
-                {
-                    Ok(t) => t,
-                    Err(EntryError::NoMapping) => {
-                        println!("ALLOCATED NEW TABLE");
-                        alloc_table(unsafe { prev_entry.as_mut() })
-                            .expect("Cannot allocate table")
-                    }
-                    Err(EntryError::NotATable) => {
-                        todo!("Mapping to this entry already exists")
-                    }
-                };
-            }
+        let mut level = PageTableLevel::PML4;
 
-            let current_level = level?;
-            let entry = unsafe {
-                NonNull::from_ref(
-                    &table.as_mut().entries[self.index_of(current_level)],
+        loop {
+            let mut entry = unsafe {
+                NonNull::from_mut(
+                    &mut table.as_mut().entries[self.index_of(level)],
                 )
             };
-            level = current_level.next();
-            prev_entry = Some(entry);
 
-            Some(entry)
+            if level == page_size.mapping_table() {
+                return Ok(entry);
+            }
+
+            table = match unsafe { entry.as_ref().mapped_table() } {
+                Ok(t) => t,
+                Err(EntryError::NoMapping) => {
+                    alloc_table(unsafe { entry.as_mut() })
+                        .map_err(|_| MappingError::TableAllocation)?
+                }
+                // A larger page already maps this address.
+                Err(EntryError::NotATable) => {
+                    return Err(MappingError::AlreadyMapped);
+                }
+            };
+
+            level = level.next().expect("Page size below the last table");
+        }
+    }
+
+    /// Returns the size of the page that maps this address, and the
+    /// entries on the way to it, indexed by their [`PageTableLevel`].
+    ///
+    /// The last entry on the path is the entry of the page itself. This
+    /// never allocates.
+    #[cfg(target_arch = "x86_64")]
+    fn mapping_path(
+        &self,
+    ) -> Result<
+        (PageSize, [Option<NonNull<PageTableEntry>>; 4]),
+        MappingError,
+    > {
+        let mut path = [None; 4];
+
+        for (level, entry) in self.walk() {
+            path[level as usize] = Some(entry);
+
+            let flags = unsafe { entry.as_ref().get_flags() };
+            if !flags.is_present() {
+                break;
+            }
+
+            let page_size = match level {
+                PageTableLevel::PDPT if flags.is_huge_page() => {
+                    PageSize::Huge
+                }
+                PageTableLevel::PD if flags.is_huge_page() => {
+                    PageSize::Big
+                }
+                PageTableLevel::PT => PageSize::Regular,
+                _ => continue,
+            };
+
+            return Ok((page_size, path));
+        }
+
+        Err(MappingError::NotMapped)
+    }
+
+    /// Returns the physical address this address is mapped to.
+    ///
+    /// Unlike the mapping functions, this never allocates tables.
+    #[cfg(target_arch = "x86_64")]
+    fn translate(&self) -> Result<PhysicalAddress, MappingError> {
+        let (page_size, path) = self.mapping_path()?;
+        let entry = path[page_size.mapping_table() as usize]
+            .expect("Mapping path is missing its page");
+
+        let offset = self.as_usize() & (page_size.size() - 1);
+
+        Ok(unsafe {
+            PhysicalAddress::new_unchecked(
+                entry.as_ref().get_address().as_usize() + offset,
+            )
         })
     }
 
@@ -113,24 +391,231 @@ pub impl VirtualAddress {
         flags: Option<PageEntryFlags>,
         page_size: PageSize,
     ) -> Result<(), MappingError> {
-        let mut entry = self
-            .walk_map()
-            .nth(page_size.mapping_table() as usize)
-            .ok_or(MappingError::TableDoesNotExist)?;
-
-        unsafe {
-            entry.as_mut().map(
-                address,
-                flags.unwrap_or_else(|| match page_size {
-                    PageSize::Regular => {
-                        PageEntryFlags::regular_page_flags()
-                    }
-                    PageSize::Big | PageSize::Huge => {
-                        PageEntryFlags::huge_page_flags()
-                    }
-                }),
-            )
+        if !self.is_aligned(page_size.alignment())
+            || !address.is_aligned(page_size.alignment())
+        {
+            return Err(MappingError::Unaligned);
         }
+
+        let mut entry = self.walk_map(page_size)?;
+
+        if unsafe { entry.as_ref().get_flags().is_present() } {
+            return Err(MappingError::AlreadyMapped);
+        }
+
+        let flags = match page_size {
+            PageSize::Regular => {
+                flags.unwrap_or(PageEntryFlags::regular_page_flags())
+            }
+            PageSize::Big | PageSize::Huge => flags
+                .unwrap_or(PageEntryFlags::huge_page_flags())
+                .huge_page(true),
+        };
+
+        unsafe { entry.as_mut().map_unchecked(address, flags) }
+        Ok(())
+    }
+
+    /// Map `length` bytes starting at this address to the physical memory
+    /// starting at `address`.
+    ///
+    /// The largest page that fits the alignment of both addresses and the
+    /// remaining length is used for each part of the range. If a part
+    /// cannot be mapped, the parts that were mapped are unmapped, and the
+    /// error of that part is returned.
+    #[cfg(target_arch = "x86_64")]
+    fn map_range(
+        &self,
+        address: PhysicalAddress,
+        length: usize,
+        flags: Option<PageEntryFlags>,
+    ) -> Result<(), MappingError> {
+        let mut mapped = 0;
+
+        while mapped < length {
+            let virt = unsafe {
+                VirtualAddress::new_unchecked(self.as_usize() + mapped)
+            };
+            let phys = unsafe {
+                PhysicalAddress::new_unchecked(address.as_usize() + mapped)
+            };
+
+            let page = [PageSize::Huge, PageSize::Big, PageSize::Regular]
+                .into_iter()
+                .find(|size| {
+                    virt.is_aligned(size.alignment())
+                        && phys.is_aligned(size.alignment())
+                        && length - mapped >= size.size()
+                })
+                .ok_or(MappingError::Unaligned)
+                .and_then(|size| {
+                    virt.map(phys, flags, size).map(|_| size)
+                });
+
+            match page {
+                Ok(page_size) => mapped += page_size.size(),
+                Err(e) => {
+                    // The pages were mapped by this call, so unmapping
+                    // them cannot fail, and the error that stopped the
+                    // mapping is the one that is reported.
+                    let _ = self.unmap_range(mapped);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unmap the page that maps this address, and free the tables that
+    /// became empty.
+    ///
+    /// Returns the physical address and the size of the unmapped page.
+    #[cfg(target_arch = "x86_64")]
+    fn unmap(&self) -> Result<(PhysicalAddress, PageSize), MappingError> {
+        let (page_size, path) = self.mapping_path()?;
+
+        if !self.is_aligned(page_size.alignment()) {
+            return Err(MappingError::Unaligned);
+        }
+
+        let level = page_size.mapping_table() as usize;
+        let mut entry =
+            path[level].expect("Mapping path is missing its page");
+
+        let address = unsafe { entry.as_mut().unmap() }
+            .map_err(|_| MappingError::NotMapped)?;
+
+        // Unlink the tables that are left empty, from the bottom up. The
+        // tables that the PML4 points to are kept, so its entries stay
+        // valid.
+        let mut unlinked = [None::<PhysicalAddress>; 4];
+        for (parent, slot) in
+            path[1..=level].iter().rev().skip(1).zip(&mut unlinked)
+        {
+            let mut parent = parent.expect("Mapping path has a hole");
+
+            // The path was walked through these entries, so they are
+            // mapped.
+            let table = unsafe { parent.as_ref().mapped_table() }
+                .expect("Mapping path has a hole");
+
+            if !unsafe { table.as_ref().is_empty() } {
+                break;
+            }
+
+            *slot = unsafe { parent.as_mut().unmap() }.ok();
+        }
+
+        // The flush also drops the cached entries that point to the
+        // unlinked tables, so they are only freed after it.
+        tlb::flash_address(*self);
+
+        for table in unlinked.into_iter().flatten() {
+            unsafe { release_table(table) };
+        }
+
+        Ok((address, page_size))
+    }
+
+    /// Unmap every page in the `length` bytes starting at this address.
+    #[cfg(target_arch = "x86_64")]
+    fn unmap_range(&self, length: usize) -> Result<(), MappingError> {
+        let mut unmapped = 0;
+
+        while unmapped < length {
+            let virt = unsafe {
+                VirtualAddress::new_unchecked(self.as_usize() + unmapped)
+            };
+            let (_, page_size) = virt.unmap()?;
+
+            unmapped += page_size.size();
+        }
+
+        Ok(())
+    }
+
+    /// Change the flags of the page that maps this address.
+    #[cfg(target_arch = "x86_64")]
+    fn protect(&self, flags: PageEntryFlags) -> Result<(), MappingError> {
+        let (page_size, path) = self.mapping_path()?;
+        let mut entry = path[page_size.mapping_table() as usize]
+            .expect("Mapping path is missing its page");
+
+        let flags = flags
+            .present(true)
+            .huge_page(page_size != PageSize::Regular);
+
+        unsafe { entry.as_mut().set_flags(flags) };
+
+        tlb::flash_address(*self);
+
         Ok(())
     }
+
+    /// Set whether code may run from the page that maps this address.
+    ///
+    /// # Safety
+    /// The execute disable bit must be enabled in EFER.
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn set_executable(
+        &self,
+        executable: bool,
+    ) -> Result<(), MappingError> {
+        let (page_size, path) = self.mapping_path()?;
+        let mut entry = path[page_size.mapping_table() as usize]
+            .expect("Mapping path is missing its page");
+
+        unsafe { entry.as_mut().set_executable(executable) };
+
+        tlb::flash_address(*self);
+
+        Ok(())
+    }
+
+    /// Split the big or huge page that maps this address into a table of
+    /// pages of the next smaller size, which keep its flags.
+    ///
+    /// Returns the size of the page that maps this address afterwards.
+    #[cfg(target_arch = "x86_64")]
+    fn split(&self) -> Result<PageSize, MappingError> {
+        let (page_size, path) = self.mapping_path()?;
+
+        let smaller = match page_size {
+            PageSize::Regular => return Ok(PageSize::Regular),
+            PageSize::Big => PageSize::Regular,
+            PageSize::Huge => PageSize::Big,
+        };
+
+        let mut entry = path[page_size.mapping_table() as usize]
+            .expect("Mapping path is missing its page");
+        let page = unsafe { *entry.as_ref() };
+
+        let mut parent = PageTableEntry::new();
+        let mut table = alloc_table(&mut parent)
+            .map_err(|_| MappingError::TableAllocation)?;
+
+        let flags =
+            page.get_flags().huge_page(smaller != PageSize::Regular);
+
+        for (i, child) in
+            unsafe { table.as_mut().entries.iter_mut().enumerate() }
+        {
+            *child = page;
+            child.set_flags(flags);
+            child.set_address(unsafe {
+                PhysicalAddress::new_unchecked(
+                    page.get_address().as_usize() + i * smaller.size(),
+                )
+            });
+        }
+
+        // The table replaces the page in a single write, so the memory
+        // stays mapped the whole time.
+        unsafe { *entry.as_mut() = parent };
+
+        tlb::flash_address(*self);
+
+        Ok(smaller)
+    }
 }
@@ -0,0 +1,210 @@
+use core::{alloc::Layout, fmt::Display, panic::Location};
+
+/// The amount of live allocations that can be tracked at once.
+pub const MAX_TRACKED_ALLOCATIONS: usize = 512;
+
+/// The amount of call sites that can have their own counters.
+pub const MAX_CALL_SITES: usize = 64;
+
+/// An allocation that was not freed yet.
+#[derive(Debug, Clone, Copy)]
+pub struct Allocation {
+    /// The address of the allocated memory.
+    pub address: usize,
+    /// The layout that the memory was allocated with.
+    pub layout: Layout,
+    /// The location that requested the allocation.
+    pub caller: &'static Location<'static>,
+}
+
+/// The counters of the tracked allocations of a single call site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallSite {
+    /// The location that requested the allocations.
+    pub caller: &'static Location<'static>,
+    /// The amount of allocations that were made from here.
+    pub allocations: usize,
+    /// The amount of those allocations that were freed.
+    pub frees: usize,
+    /// The amount of bytes that are still allocated from here.
+    pub live_bytes: usize,
+}
+
+impl CallSite {
+    /// The amount of allocations from here that were not freed yet.
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.frees
+    }
+}
+
+/// A snapshot of the allocations that the kernel is holding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationSummary {
+    /// The amount of allocations that were not freed yet.
+    pub live_allocations: usize,
+    /// The amount of bytes that were not freed yet.
+    pub live_bytes: usize,
+    /// The largest amount of bytes that were allocated at once.
+    pub peak_bytes: usize,
+    /// The amount of allocations that were ever made.
+    pub total_allocations: usize,
+    /// The amount of allocations that were ever freed.
+    pub total_frees: usize,
+    /// The amount of live allocations that are not in the table, because
+    /// it was full when they were made.
+    pub untracked_allocations: usize,
+    /// The amount of live allocations that are not counted for their call
+    /// site, because there were too many call sites.
+    pub uncounted_allocations: usize,
+}
+
+impl Display for AllocationSummary {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "Allocations: {} live, {} allocated, {} freed",
+            self.live_allocations,
+            self.total_allocations,
+            self.total_frees
+        )?;
+        write!(
+            f,
+            "Bytes: {} live, {} peak",
+            self.live_bytes, self.peak_bytes
+        )?;
+
+        if self.untracked_allocations != 0 {
+            write!(f, ", {} untracked", self.untracked_allocations)?;
+        }
+
+        if self.uncounted_allocations != 0 {
+            write!(f, ", {} uncounted", self.uncounted_allocations)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A fixed size table of the live allocations, with counters for each call
+/// site.
+///
+/// Nothing here allocates, so it can be used from inside the allocator.
+pub struct AllocationTracker {
+    allocations: [Option<Allocation>; MAX_TRACKED_ALLOCATIONS],
+    sites: [Option<CallSite>; MAX_CALL_SITES],
+    summary: AllocationSummary,
+}
+
+impl AllocationTracker {
+    pub const fn new() -> Self {
+        Self {
+            allocations: [None; MAX_TRACKED_ALLOCATIONS],
+            sites: [None; MAX_CALL_SITES],
+            summary: AllocationSummary {
+                live_allocations: 0,
+                live_bytes: 0,
+                peak_bytes: 0,
+                total_allocations: 0,
+                total_frees: 0,
+                untracked_allocations: 0,
+                uncounted_allocations: 0,
+            },
+        }
+    }
+
+    /// Record a new allocation of `layout` at `address`.
+    pub fn track(
+        &mut self,
+        address: usize,
+        layout: Layout,
+        caller: &'static Location<'static>,
+    ) {
+        let summary = &mut self.summary;
+        summary.live_allocations += 1;
+        summary.total_allocations += 1;
+        summary.live_bytes += layout.size();
+        summary.peak_bytes = summary.peak_bytes.max(summary.live_bytes);
+
+        let Some(slot) = self.allocations.iter_mut().find(|a| a.is_none())
+        else {
+            summary.untracked_allocations += 1;
+            return;
+        };
+
+        *slot = Some(Allocation {
+            address,
+            layout,
+            caller,
+        });
+
+        match self.site(caller) {
+            Some(site) => {
+                site.allocations += 1;
+                site.live_bytes += layout.size();
+            }
+            None => self.summary.uncounted_allocations += 1,
+        }
+    }
+
+    /// Record that the allocation of `layout` at `address` was freed.
+    pub fn untrack(&mut self, address: usize, layout: Layout) {
+        let summary = &mut self.summary;
+        summary.live_allocations -= 1;
+        summary.total_frees += 1;
+        summary.live_bytes -= layout.size();
+
+        let Some(allocation) = self
+            .allocations
+            .iter_mut()
+            .find(|a| a.is_some_and(|a| a.address == address))
+            .and_then(Option::take)
+        else {
+            summary.untracked_allocations =
+                summary.untracked_allocations.saturating_sub(1);
+            return;
+        };
+
+        match self.site(allocation.caller) {
+            Some(site) => {
+                site.frees += 1;
+                site.live_bytes -= allocation.layout.size();
+            }
+            None => self.summary.uncounted_allocations -= 1,
+        }
+    }
+
+    /// Returns the counters of every call site.
+    pub fn sites(&self) -> impl Iterator<Item = &CallSite> {
+        self.sites.iter().flatten()
+    }
+
+    /// Returns every tracked allocation that was not freed yet.
+    pub fn allocations(&self) -> impl Iterator<Item = &Allocation> {
+        self.allocations.iter().flatten()
+    }
+
+    pub fn summary(&self) -> AllocationSummary { self.summary }
+
+    /// Returns the counters of the call site, and starts counting it if
+    /// it is new and there is room for it.
+    fn site(
+        &mut self,
+        caller: &'static Location<'static>,
+    ) -> Option<&mut CallSite> {
+        let index = self
+            .sites
+            .iter()
+            .position(|s| s.is_none_or(|s| s.caller == caller))?;
+
+        Some(self.sites[index].get_or_insert(CallSite {
+            caller,
+            allocations: 0,
+            frees: 0,
+            live_bytes: 0,
+        }))
+    }
+}
+
+impl Default for AllocationTracker {
+    fn default() -> Self { Self::new() }
+}
@@ -1,5 +1,7 @@
 #![no_std]
 #![feature(allocator_api)]
 
+pub mod address_space;
 pub mod alloc;
 pub mod fmt;
+pub mod time;
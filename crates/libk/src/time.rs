@@ -0,0 +1,193 @@
+use core::{
+    fmt::{self, Display},
+    hint::spin_loop,
+    ops::{Add, Sub},
+};
+
+pub use core::time::Duration;
+
+use common::{error::TimerError, late_init::LateInit};
+use x86::{
+    hpet::Hpet,
+    instructions::{cpuid::PowerManagementFeatures, tsc::rdtsc},
+    pit::{self, Pit},
+};
+
+/// How long the TSC is measured against the reference timer.
+const CALIBRATION_MS: u64 = 10;
+
+const NS_PER_MS: u64 = 1_000_000;
+
+/// The monotonic clock, which [`Instant::now`] reads.
+pub static CLOCK: LateInit<Clock> = LateInit::uninit();
+
+/// Where the monotonic clock reads the time from.
+pub enum ClockSource {
+    /// The TSC, when it runs at a constant rate in all of the power
+    /// states of the cpu.
+    Tsc { cycles_per_ms: u64 },
+    /// The main counter of the HPET, when it is 64 bits wide.
+    Hpet(&'static Hpet),
+    /// The interrupts of the PIT.
+    Pit(&'static Pit),
+}
+
+impl ClockSource {
+    /// Pick the invariant TSC if the cpu has one, else the HPET, else the
+    /// interrupts of the PIT, which must be counting already.
+    pub fn select(hpet: Option<&'static Hpet>, pit: &'static Pit) -> Self {
+        if PowerManagementFeatures::default().has_invariant_tsc() {
+            ClockSource::Tsc {
+                cycles_per_ms: calibrate_tsc(hpet),
+            }
+        } else if let Some(hpet) =
+            hpet.filter(|hpet| hpet.capabilities().is_wide_counter())
+        {
+            ClockSource::Hpet(hpet)
+        } else {
+            ClockSource::Pit(pit)
+        }
+    }
+}
+
+impl Display for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClockSource::Tsc { cycles_per_ms } => {
+                write!(
+                    f,
+                    "the invariant TSC at {}MHz",
+                    cycles_per_ms / 1000
+                )
+            }
+            ClockSource::Hpet(hpet) => {
+                write!(
+                    f,
+                    "the HPET at {}MHz",
+                    hpet.frequency() / 1_000_000
+                )
+            }
+            ClockSource::Pit(pit) => {
+                write!(f, "the PIT interrupts at {}Hz", pit.frequency())
+            }
+        }
+    }
+}
+
+/// Measure the cycles of the TSC in a millisecond, against the main
+/// counter of the HPET if there is one, or else against channel 2 of the
+/// PIT.
+pub fn calibrate_tsc(hpet: Option<&Hpet>) -> u64 {
+    let Some(hpet) = hpet else {
+        return pit::calibrate(CALIBRATION_MS as u32, rdtsc);
+    };
+
+    let counts = hpet.ns_to_counts(CALIBRATION_MS * NS_PER_MS);
+    let start = hpet.counter();
+    let tsc_start = rdtsc();
+    while hpet.counter().wrapping_sub(start) < counts {
+        spin_loop();
+    }
+    (rdtsc() - tsc_start) / CALIBRATION_MS
+}
+
+/// A monotonic clock, which counts nanoseconds from an arbitrary point.
+pub struct Clock {
+    source: ClockSource,
+}
+
+impl Clock {
+    pub const fn new(source: ClockSource) -> Self { Self { source } }
+
+    pub fn source(&self) -> &ClockSource { &self.source }
+
+    /// Returns the nanoseconds since the source started counting.
+    pub fn now_ns(&self) -> u64 {
+        match self.source {
+            ClockSource::Tsc { cycles_per_ms } => {
+                (rdtsc() as u128 * NS_PER_MS as u128
+                    / cycles_per_ms as u128) as u64
+            }
+            ClockSource::Hpet(hpet) => hpet.counts_to_ns(hpet.counter()),
+            ClockSource::Pit(pit) => pit.uptime().as_nanos() as u64,
+        }
+    }
+
+    /// Returns the first value of the TSC at which the clock reads
+    /// `instant`, if the clock counts the cycles of the TSC.
+    pub fn tsc_at(&self, instant: Instant) -> Option<u64> {
+        match self.source {
+            ClockSource::Tsc { cycles_per_ms } => Some(
+                (instant.as_nanos() as u128 * cycles_per_ms as u128)
+                    .div_ceil(NS_PER_MS as u128) as u64,
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A point in time of the monotonic [`CLOCK`], which only means something
+/// compared to other instants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    /// Returns the current time of the [`CLOCK`].
+    pub fn now() -> Self { Self::from_nanos(CLOCK.now_ns()) }
+
+    pub const fn from_nanos(nanos: u64) -> Self { Self { nanos } }
+
+    pub const fn as_nanos(&self) -> u64 { self.nanos }
+
+    /// Returns the time from `earlier` to this instant, or zero if
+    /// `earlier` is later.
+    pub const fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    /// Returns the time since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.nanos.checked_add(nanos))
+            .map(Instant::from_nanos)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("Overflow when adding a duration to an instant")
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Spin until `condition` is true, or fail once `timeout` passes.
+pub fn wait_until(
+    timeout: Duration,
+    mut condition: impl FnMut() -> bool,
+) -> Result<(), TimerError> {
+    let start = Instant::now();
+    while !condition() {
+        if start.elapsed() >= timeout {
+            return Err(TimerError::TimedOut(timeout));
+        }
+        spin_loop();
+    }
+    Ok(())
+}
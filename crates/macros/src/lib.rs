@@ -29,11 +29,18 @@ pub fn generate_generics(input: TokenStream) -> TokenStream {
         // minimum size of 8 bytes (usize on 64 bit).
         let array_size = generic_size / 8;
 
+        // Objects of a generic size are aligned to the largest power of
+        // two that divides it, up to a page.
+        let align = proc_macro2::Literal::usize_unsuffixed(
+            1 << generic_size.trailing_zeros().min(12),
+        );
+
         let start = last_size;
         let end = generic_size;
 
         let struct_def = quote! {
             #[derive(Debug, Clone, Copy)]
+            #[repr(C, align(#align))]
             pub struct #generic_name(pub [usize; #array_size]);
 
             impl Generic for #generic_name {
@@ -0,0 +1,29 @@
+//! Poisoning of free memory, enabled with the `memory-debug` feature.
+
+use core::ptr::NonNull;
+
+/// The byte that fills memory while it is free.
+pub const POISON_FREE: u8 = 0x6b;
+
+/// Fill `len` bytes at `ptr` with `byte`.
+///
+/// # Safety
+/// The memory must be valid for writes, and must not be in use.
+pub unsafe fn fill(ptr: NonNull<u8>, len: usize, byte: u8) {
+    unsafe { ptr.write_bytes(byte, len) }
+}
+
+/// Returns the offset of the first of the `len` bytes at `ptr` that is
+/// not `byte`.
+///
+/// # Safety
+/// The memory must be valid for reads.
+pub unsafe fn find_mismatch(
+    ptr: NonNull<u8>,
+    len: usize,
+    byte: u8,
+) -> Option<usize> {
+    unsafe { core::slice::from_raw_parts(ptr.as_ptr(), len) }
+        .iter()
+        .position(|&b| b != byte)
+}
@@ -7,25 +7,49 @@
 #![feature(min_specialization)]
 #![feature(ptr_alignment_type)]
 
+#[cfg(feature = "memory-debug")]
+pub mod debug;
 pub mod meta;
+pub mod stats;
 
-use core::{alloc::GlobalAlloc, marker::PhantomData, ptr::NonNull};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    marker::PhantomData,
+    ptr::NonNull,
+};
+
+#[cfg(feature = "memory-debug")]
+use core::panic::Location;
 
 use libk::println;
+use strum::VariantArray;
 use sync::mutex::SpinMutex;
-use x86::{memory_map::MemoryMap, structures::paging::VirtualAddressExt};
+use x86::memory_map::MemoryMap;
 
 use common::{
     address_types::{Address, PhysicalAddress},
     alloc::BumpAllocations,
     constants::{REGULAR_PAGE_ALIGNMENT, REGULAR_PAGE_SIZE},
-    enums::{BuddyOrder, MemoryRegionType},
-    iter,
+    enums::{BuddyOrder, MemoryRegionType, Zone},
     volatile::Volatile,
 };
 
-use crate::meta::{
-    BuddyArena, BuddyBlock, BuddyError, BuddyMeta, Head, Regular,
+use crate::{
+    meta::{BuddyArena, BuddyBlock, BuddyError, BuddyMeta, Head, Regular},
+    stats::{BuddyStats, ZoneStats},
+};
+
+/// The freelist of each order in a zone.
+type FreeLists = [BuddyMeta<Head>; BuddyOrder::MAX as usize + 1];
+
+// A block never crosses into another zone, so blocks and their buddies
+// always belong to the same zone.
+const _: () = {
+    let block = (REGULAR_PAGE_SIZE << BuddyOrder::MAX as usize) as u64;
+    assert!(
+        Zone::Dma.end().is_multiple_of(block)
+            && Zone::Dma32.end().is_multiple_of(block)
+    );
 };
 
 pub struct BuddyAllocator<Arena, Block>
@@ -34,7 +58,9 @@ where
     Arena: BuddyArena<Block>,
 {
     arena: SpinMutex<Arena>,
-    freelist: SpinMutex<[BuddyMeta<Head>; BuddyOrder::MAX as usize + 1]>,
+    freelist: SpinMutex<[FreeLists; Zone::COUNT]>,
+    oom_hook: SpinMutex<Option<fn(Layout)>>,
+    pressure_hook: SpinMutex<Option<fn() -> usize>>,
     // Wrap in a mutex to automatically implement Sync and Send.
     _block: PhantomData<SpinMutex<Block>>,
 }
@@ -46,12 +72,15 @@ where
 {
     pub fn new(memory_map: &MemoryMap) -> BuddyAllocator<Arena, Block> {
         let freelist = SpinMutex::new(
-            [BuddyMeta::<Head>::default(); BuddyOrder::MAX as usize + 1],
+            [[BuddyMeta::<Head>::default(); BuddyOrder::MAX as usize + 1];
+                Zone::COUNT],
         );
 
         let mut lock = freelist.lock();
 
-        let head = &mut lock[0];
+        // All of the pages start on a single freelist, and are moved to
+        // the freelist of their zone on initialization.
+        let head = &mut lock[Zone::Dma as usize][0];
 
         let arena = SpinMutex::new(Arena::new(memory_map, head));
 
@@ -60,6 +89,8 @@ where
         BuddyAllocator {
             arena,
             freelist,
+            oom_hook: SpinMutex::new(None),
+            pressure_hook: SpinMutex::new(None),
             _block: PhantomData,
         }
     }
@@ -74,7 +105,7 @@ where
         let mut first = self.arena.lock().at(0).unwrap();
 
         let lock = self.freelist.lock();
-        let head = &lock[0];
+        let head = &lock[Zone::Dma as usize][0];
         unsafe {
             first.as_mut().meta_mut().prev =
                 Volatile::new(NonNull::from_ref(head));
@@ -82,100 +113,91 @@ where
 
         drop(lock);
 
-        // Allocate all the non-usable regions in the memory map
-        if let Some(last_usable_idx) = mmap
+        // Allocate all the non-usable regions in the memory map. Regions
+        // that are past the end of the arena are simply ignored.
+        for region in mmap
             .regions
             .read()
             .iter()
-            .rposition(|r| r.region_type == MemoryRegionType::Usable)
+            .filter(|r| r.region_type != MemoryRegionType::Usable)
         {
-            for region in mmap
-                .regions
-                .read()
-                .iter()
-                .take(last_usable_idx + 1)
-                .filter(|r| r.region_type != MemoryRegionType::Usable)
-            {
-                let mut block = self
-                    .arena
-                    .lock()
-                    .page_with_address(unsafe {
-                        PhysicalAddress::new_unchecked(
-                            region.base_address as usize,
-                        )
-                        .align_down(REGULAR_PAGE_ALIGNMENT)
-                    })
-                    .unwrap();
-                println!("First Block: {:?}", block);
-
-                let mut length = (region.length as usize)
-                    .next_multiple_of(REGULAR_PAGE_SIZE);
-
-                while length > 0 {
-                    length -= REGULAR_PAGE_SIZE;
-                    unsafe {
-                        self.allocate_block(block);
-                    }
-                    block = unsafe { block.add(1) };
-                }
-                println!(
-                    "Allocated Range {:x}..{:x}",
-                    PhysicalAddress::new_unchecked(
-                        region.base_address as usize,
-                    )
-                    .align_down(REGULAR_PAGE_ALIGNMENT)
-                    .as_usize(),
-                    region.base_address + region.length
-                )
-            }
-        }
-
-        // Note: Because this is a hobby OS, I do not hanlde all cases on
-        // the ranges in the memory map. And I rely on the observation that
-        // the first first matching free range in the arena is a power of
-        // 2, which is good for this allocator.
-        if allocations.index > 1 {
-            todo!(
-                "Currently does not implement passing allocations that \
-                 are not the arena of this allocator"
-            )
+            self.allocate_range(
+                PhysicalAddress::from(region.base_address),
+                region.length as usize,
+            );
         }
 
-        let allocation = allocations.iter().nth(0).unwrap();
-
-        println!("Allocation Base: {:x?}", allocation.base.as_usize());
+        // Allocate every allocation that was made before the handoff, no
+        // matter where it sits on the arena.
+        for allocation in allocations.iter() {
+            let base = self
+                .arena
+                .lock()
+                .physical_address_of(allocation.base)
+                .expect("Bump allocation is not mapped");
 
-        let allocation_address = allocation.base.translate().unwrap();
-
-        println!(
-            "Initial Allocation Address: {}",
-            allocation_address.as_usize()
-        );
+            self.allocate_range(base, allocation.layout.size());
+        }
 
-        let mut block = self
-            .arena
-            .lock()
-            .page_with_address(allocation_address)
-            .unwrap();
+        // The free memory is poisoned while all of the blocks are still
+        // single pages, before any of them is merged.
+        #[cfg(feature = "memory-debug")]
+        for n in 0..len {
+            let page = self.arena.lock().at(n).unwrap();
 
-        println!("Block: {:?}", block);
+            if unsafe { !page.as_ref().meta().flags.is_allocated() } {
+                self.poison(page);
+            }
+        }
 
-        let mut length =
-            allocation.layout.size().next_multiple_of(REGULAR_PAGE_SIZE);
+        // Move every free block to the freelist of its zone, and merge it
+        // as much as possible. Pages that were already merged into a
+        // bigger block have no order and are skipped.
+        for n in 0..len {
+            let mut page = self.arena.lock().at(n).unwrap();
+            let flags = unsafe { &page.as_ref().meta().flags };
 
-        while length > 0 {
-            length -= REGULAR_PAGE_SIZE;
-            unsafe {
-                self.allocate_block(block);
+            if !flags.is_allocated()
+                && flags.get_order() != BuddyOrder::None
+            {
+                unsafe { page.as_mut().meta_mut().detach() };
+                self.attach_block(self.zone_of(page), page);
+                self.merge_recursive(page);
             }
-            block = unsafe { block.add(1) };
         }
+    }
+
+    /// Mark every page that holds a part of the given physical range as
+    /// allocated.
+    ///
+    /// Pages that are already allocated, or that are outside of the arena
+    /// are skipped, so overlapping ranges can be passed safely.
+    ///
+    /// **Note**: This function should only be called before any merge
+    /// happened, when all the blocks on the arena are of
+    /// [`BuddyOrder::MIN`].
+    fn allocate_range(&self, base: PhysicalAddress, length: usize) {
+        let start = base.align_down(REGULAR_PAGE_ALIGNMENT);
+        let end = unsafe {
+            PhysicalAddress::new_unchecked(base.as_usize() + length)
+                .align_up(REGULAR_PAGE_ALIGNMENT)
+        };
 
-        for (n, _power) in
-            iter::power_chunk_firsts(0..len, BuddyOrder::MAX as usize)
+        for address in
+            (start.as_usize()..end.as_usize()).step_by(REGULAR_PAGE_SIZE)
         {
-            let page = self.arena.lock().at(n).unwrap();
-            self.merge_recursive(page);
+            let Ok(block) = self
+                .arena
+                .lock()
+                .page_with_address(PhysicalAddress::from(address))
+            else {
+                // The rest of the range is outside of the arena.
+                return;
+            };
+
+            if unsafe { !block.as_ref().meta().flags.is_allocated() } {
+                unsafe { self.allocate_block(block) };
+            }
         }
     }
 
@@ -208,54 +230,479 @@ where
         }
     }
 
+    /// Allocate a block of the given order from any zone.
+    ///
+    /// This is the same as [`BuddyAllocator::try_alloc_pages_in`] with
+    /// [`Zone::Normal`].
+    #[track_caller]
+    pub fn try_alloc_pages(
+        &self,
+        order: BuddyOrder,
+    ) -> Result<NonNull<Block>, BuddyError> {
+        self.try_alloc_pages_in(order, Zone::Normal)
+    }
+
+    /// Allocate a block of the given order from `zone`, or from a lower
+    /// zone if `zone` has no free memory.
+    ///
+    /// If a zone has no free block of this order, a larger block of the
+    /// zone is split. [`BuddyError::OutOfMemory`] is returned when none of
+    /// the zones has a free block of this order or any larger order.
+    #[track_caller]
+    pub fn try_alloc_pages_in(
+        &self,
+        order: BuddyOrder,
+        zone: Zone,
+    ) -> Result<NonNull<Block>, BuddyError> {
+        if order == BuddyOrder::None {
+            return Err(BuddyError::InvalidOrder);
+        }
+
+        // The search is retried as long as the pressure hook releases
+        // memory.
+        let block = loop {
+            let found = zone.fallbacks().find_map(|zone| {
+                match self.free_block_in(zone, order as usize) {
+                    Err(BuddyError::OutOfMemory) => None,
+                    result => Some(result),
+                }
+            });
+
+            match found {
+                Some(result) => break result?,
+                None if self.relieve_pressure() => continue,
+                None => return Err(BuddyError::OutOfMemory),
+            }
+        };
+
+        unsafe { self.allocate_block(block) };
+
+        #[cfg(feature = "memory-debug")]
+        self.check_poison(block, Location::caller());
+
+        Ok(block)
+    }
+
+    /// Returns a free block of the given order in `zone`, splitting a
+    /// larger block of the zone if there is none.
+    fn free_block_in(
+        &self,
+        zone: Zone,
+        order: usize,
+    ) -> Result<NonNull<Block>, BuddyError> {
+        let free = self.freelist.lock()[zone as usize][order].next.read();
+
+        match free {
+            Some(meta) => Ok(Block::from_meta(meta)),
+            None => self.split_until(zone, order),
+        }
+    }
+
+    /// Allocate a block of the given order that ends at or below `limit`.
+    ///
+    /// This is slower than [`BuddyAllocator::try_alloc_pages`] because
+    /// the freelists are searched for a block in range, and is meant for
+    /// devices that cannot address all of the physical memory.
+    #[track_caller]
+    pub fn try_alloc_pages_below(
+        &self,
+        order: BuddyOrder,
+        limit: PhysicalAddress,
+    ) -> Result<NonNull<Block>, BuddyError> {
+        if order == BuddyOrder::None {
+            return Err(BuddyError::InvalidOrder);
+        }
+
+        // The search is retried as long as the pressure hook releases
+        // memory.
+        let block = loop {
+            // Higher zones are searched first, to keep the lower zones for
+            // the devices that need them.
+            let found = Zone::VARIANTS
+                .iter()
+                .rev()
+                .filter(|zone| zone.start() < limit.as_usize() as u64)
+                .find_map(|&zone| {
+                    (order as usize..=BuddyOrder::MAX as usize).find_map(
+                        |i| {
+                            Some((
+                                zone,
+                                i,
+                                self.find_free_below(zone, i, limit)?,
+                            ))
+                        },
+                    )
+                });
+
+            match found {
+                Some((_, found_order, block))
+                    if found_order == order as usize =>
+                {
+                    break block;
+                }
+                Some((zone, found_order, block)) => {
+                    let block = self.split_recursive(
+                        zone,
+                        block,
+                        found_order,
+                        order as usize,
+                    )?;
+                    self.freelist.lock()[zone as usize][order as usize]
+                        .attach_block(block);
+                    break block;
+                }
+                None if self.relieve_pressure() => continue,
+                None => return Err(BuddyError::OutOfMemory),
+            }
+        };
+
+        unsafe { self.allocate_block(block) };
+
+        #[cfg(feature = "memory-debug")]
+        self.check_poison(block, Location::caller());
+
+        Ok(block)
+    }
+
+    /// Returns the first free block of the given order in `zone` that
+    /// ends at or below `limit`.
+    fn find_free_below(
+        &self,
+        zone: Zone,
+        order: usize,
+        limit: PhysicalAddress,
+    ) -> Option<NonNull<Block>> {
+        let arena = self.arena.lock();
+        let mut next =
+            self.freelist.lock()[zone as usize][order].next.read();
+
+        while let Some(meta) = next {
+            let block = Block::from_meta(meta);
+            let end = arena.address_of(block).as_usize()
+                + (REGULAR_PAGE_SIZE << order);
+
+            if end <= limit.as_usize() {
+                return Some(block);
+            }
+            next = unsafe { meta.as_ref().next.read() };
+        }
+
+        None
+    }
+
+    /// Free a block that was allocated with
+    /// [`BuddyAllocator::try_alloc_pages`] and merge it with it's buddies.
+    ///
+    /// # Safety
+    ///
+    /// The block must be allocated by this allocator, and must not be used
+    /// after this call.
+    #[track_caller]
+    pub unsafe fn free_pages(&self, mut block: NonNull<Block>) {
+        #[cfg(feature = "memory-debug")]
+        if unsafe { !block.as_ref().meta().flags.is_allocated() } {
+            panic!(
+                "Double free of the block at {:?} at {}",
+                self.address_of(block),
+                Location::caller()
+            );
+        }
+
+        debug_assert!(
+            unsafe { block.as_ref().meta().flags.is_allocated() },
+            "Double free of {:?}",
+            block
+        );
+
+        unsafe { block.as_mut().meta_mut().flags.set_allocated(false) };
+
+        #[cfg(feature = "memory-debug")]
+        self.poison(block);
+
+        self.attach_block(self.zone_of(block), block);
+        self.merge_recursive(block);
+    }
+
+    /// Free the ACPI reclaimable memory of the memory map, and return the
+    /// amount of pages that were freed.
+    ///
+    /// # Safety
+    ///
+    /// The ACPI tables must not be used after this call.
+    pub unsafe fn release_acpi_memory(&self, mmap: &MemoryMap) -> usize {
+        let mut released = 0;
+
+        mmap.reclaim_acpi(|region| {
+            released += self.free_range(
+                PhysicalAddress::from(region.base_address),
+                region.length as usize,
+            );
+        });
+
+        released
+    }
+
+    /// Free every page that is entirely inside of the given physical
+    /// range, and was allocated by [`BuddyAllocator::initialize`].
+    /// Returns the amount of pages that were freed.
+    fn free_range(&self, base: PhysicalAddress, length: usize) -> usize {
+        let start = base.align_up(REGULAR_PAGE_ALIGNMENT);
+        let end = unsafe {
+            PhysicalAddress::new_unchecked(base.as_usize() + length)
+                .align_down(REGULAR_PAGE_ALIGNMENT)
+        };
+
+        let mut released = 0;
+
+        for address in
+            (start.as_usize()..end.as_usize()).step_by(REGULAR_PAGE_SIZE)
+        {
+            let Ok(block) = self
+                .arena
+                .lock()
+                .page_with_address(PhysicalAddress::from(address))
+            else {
+                break;
+            };
+
+            let flags = unsafe { &block.as_ref().meta().flags };
+            if flags.is_allocated()
+                && flags.get_order() == BuddyOrder::Order0
+            {
+                unsafe { self.free_pages(block) };
+                released += 1;
+            }
+        }
+
+        released
+    }
+
+    /// Register a function that is called when a heap allocation fails
+    /// because there is no free memory left, with the layout that was
+    /// requested.
+    ///
+    /// The page allocation functions return their errors to the caller,
+    /// which may handle them, so they don't call the hook. It is called
+    /// from [`GlobalAlloc`], and by the allocators above this one through
+    /// [`BuddyAllocator::report_out_of_memory`], right before they return
+    /// a null pointer.
+    ///
+    /// The hook is called without any of the allocator locks held, so it
+    /// is safe to query the allocator from it.
+    pub fn set_oom_hook(&self, hook: fn(Layout)) {
+        *self.oom_hook.lock() = Some(hook);
+    }
+
+    /// Call the OOM hook for a heap allocation of the given layout that
+    /// failed.
+    pub fn report_out_of_memory(&self, layout: Layout) {
+        let hook = *self.oom_hook.lock();
+        if let Some(hook) = hook {
+            hook(layout);
+        }
+    }
+
+    /// Returns a pointer to the memory of an allocated block.
+    pub fn pointer_of(&self, block: NonNull<Block>) -> NonNull<u8> {
+        self.arena.lock().pointer_of(block)
+    }
+
+    /// Returns the physical address of a block.
+    pub fn address_of(&self, block: NonNull<Block>) -> PhysicalAddress {
+        self.arena.lock().address_of(block)
+    }
+
+    /// Returns the zone that holds a block.
+    pub fn zone_of(&self, block: NonNull<Block>) -> Zone {
+        Zone::of(self.arena.lock().address_of(block))
+    }
+
+    /// Returns the block that holds the memory pointed by `ptr`.
+    pub fn block_of(
+        &self,
+        ptr: NonNull<u8>,
+    ) -> Result<NonNull<Block>, BuddyError> {
+        self.arena.lock().page_with_pointer(ptr)
+    }
+
+    /// Returns the block that holds the given physical address.
+    pub fn block_at(
+        &self,
+        address: PhysicalAddress,
+    ) -> Result<NonNull<Block>, BuddyError> {
+        self.arena.lock().page_with_address(address)
+    }
+
+    /// Returns a snapshot of the allocator state.
+    pub fn stats(&self) -> BuddyStats {
+        let total_pages = self.arena.lock().iter().len();
+
+        let mut free_blocks = [0; BuddyOrder::MAX as usize + 1];
+        let mut zones = [ZoneStats::default(); Zone::COUNT];
+
+        let end = (total_pages * REGULAR_PAGE_SIZE) as u64;
+
+        let freelist = self.freelist.lock();
+        for (zone, stats) in Zone::VARIANTS.iter().zip(zones.iter_mut()) {
+            stats.total_pages =
+                ((zone.end().min(end) - zone.start().min(end))
+                    / REGULAR_PAGE_SIZE as u64) as usize;
+
+            for (order, (head, count)) in freelist[*zone as usize]
+                .iter()
+                .zip(free_blocks.iter_mut())
+                .enumerate()
+            {
+                let mut next = head.next.read();
+                while let Some(meta) = next {
+                    *count += 1;
+                    stats.free_pages += 1 << order;
+                    next = unsafe { meta.as_ref().next.read() };
+                }
+            }
+        }
+        drop(freelist);
+
+        let free_pages = free_blocks
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum();
+
+        let largest_free_block = free_blocks
+            .iter()
+            .rposition(|&count| count != 0)
+            .map(|order| BuddyOrder::from(order as u8));
+
+        BuddyStats {
+            free_blocks,
+            total_pages,
+            free_pages,
+            used_pages: total_pages - free_pages,
+            largest_free_block,
+            zones,
+        }
+    }
+
+    /// Register a function that is called when there is no free block
+    /// for an allocation, before the allocation fails.
+    ///
+    /// The hook should release memory back to this allocator, and return
+    /// the amount of pages it released. The hook is called without any of
+    /// the allocator locks held.
+    pub fn set_pressure_hook(&self, hook: fn() -> usize) {
+        *self.pressure_hook.lock() = Some(hook);
+    }
+
+    /// Ask the pressure hook to release memory, returns true if any
+    /// memory was released.
+    fn relieve_pressure(&self) -> bool {
+        let hook = *self.pressure_hook.lock();
+        hook.is_some_and(|hook| hook() > 0)
+    }
+
+    /// Fill the memory of a free block with
+    /// [`POISON_FREE`](debug::POISON_FREE).
+    #[cfg(feature = "memory-debug")]
+    fn poison(&self, block: NonNull<Block>) {
+        let order = unsafe { block.as_ref().meta().flags.get_order() };
+
+        unsafe {
+            debug::fill(
+                self.pointer_of(block),
+                REGULAR_PAGE_SIZE << order as usize,
+                debug::POISON_FREE,
+            )
+        };
+    }
+
+    /// Panic if the memory of a block that was just allocated was written
+    /// while it was free.
+    #[cfg(feature = "memory-debug")]
+    fn check_poison(&self, block: NonNull<Block>, caller: &Location) {
+        let order = unsafe { block.as_ref().meta().flags.get_order() };
+
+        if let Some(offset) = unsafe {
+            debug::find_mismatch(
+                self.pointer_of(block),
+                REGULAR_PAGE_SIZE << order as usize,
+                debug::POISON_FREE,
+            )
+        } {
+            panic!(
+                "Use after free: the block at {:?} was written at offset \
+                 {:#x} while it was free, found when allocated at {}",
+                self.address_of(block),
+                offset,
+                caller
+            );
+        }
+    }
+
+    /// Split the smallest free block of `zone` that is larger than
+    /// `wanted_order` until there is a free block of `wanted_order`.
+    ///
+    /// The resulting block is attached to the freelist of `wanted_order`
+    /// in `zone`.
     pub fn split_until(
         &self,
+        zone: Zone,
         wanted_order: usize,
-    ) -> Option<NonNull<Block>> {
+    ) -> Result<NonNull<Block>, BuddyError> {
         let (closet_order, initial_page) = ((wanted_order + 1)
             ..=BuddyOrder::MAX as usize)
             .find_map(|i| {
                 Some((
                     i,
-                    Block::from_meta(self.freelist.lock()[i].next.read()?),
+                    Block::from_meta(
+                        self.freelist.lock()[zone as usize][i]
+                            .next
+                            .read()?,
+                    ),
                 ))
-            })?;
+            })
+            .ok_or(BuddyError::OutOfMemory)?;
 
-        Some(self.split_recursive(
+        let block = self.split_recursive(
+            zone,
             initial_page,
             closet_order,
             wanted_order,
-        ))
+        )?;
+
+        self.freelist.lock()[zone as usize][wanted_order]
+            .attach_block(block);
+
+        Ok(block)
     }
 
     fn split_recursive(
         &self,
+        zone: Zone,
         page: NonNull<Block>,
         current_order: usize,
         target_order: usize,
-    ) -> NonNull<Block> {
-        debug_assert!(target_order < current_order);
+    ) -> Result<NonNull<Block>, BuddyError> {
+        debug_assert!(target_order <= current_order);
 
         if current_order == target_order {
-            return page;
+            return Ok(page);
         }
 
-        let (lhs, rhs) = match self.arena.lock().split(page) {
-            Ok((lhs, rhs)) => (lhs, rhs),
-            Err(e) => todo!("Handle Error, {:?}", e),
-        };
+        let (lhs, rhs) = self.arena.lock().split(page)?;
 
         let next_order = current_order - 1;
 
-        self.freelist.lock()[next_order].attach_block(rhs);
+        self.freelist.lock()[zone as usize][next_order].attach_block(rhs);
 
-        become self.split_recursive(lhs, next_order, target_order)
+        become self.split_recursive(zone, lhs, next_order, target_order)
     }
 
     /// This function will try to merge a page with it's buddy, until it
     /// cannot be merged anymore.
     pub fn merge_recursive(&self, mut page: NonNull<Block>) -> BuddyOrder {
         let arena = self.arena.lock();
+        let zone = Zone::of(arena.address_of(page));
         loop {
             let buddy = match arena.buddy_of(page) {
                 Ok(buddy) => buddy,
@@ -294,7 +741,7 @@ where
             }
             match arena.merge(page, buddy) {
                 Ok(merged) => {
-                    self.attach_block(merged);
+                    self.attach_block(zone, merged);
                     page = merged;
                 }
                 Err(e) => unreachable!("{}", e),
@@ -302,9 +749,10 @@ where
         }
     }
 
-    fn attach_block(&self, block: NonNull<Block>) {
+    fn attach_block(&self, zone: Zone, block: NonNull<Block>) {
         let order = unsafe { block.as_ref().meta().flags.get_order() };
-        self.freelist.lock()[order as usize].attach_block(block);
+        self.freelist.lock()[zone as usize][order as usize]
+            .attach_block(block);
     }
 
     pub fn print_allocated_regions(&self) {
@@ -342,82 +790,56 @@ where
     }
 }
 
+/// Returns the order of the smallest block that can hold the given
+/// layout, or [`None`] if it is larger then a [`BuddyOrder::MAX`] block.
+fn order_of(layout: Layout) -> Option<BuddyOrder> {
+    let num_pages = layout.size().next_multiple_of(
+        REGULAR_PAGE_ALIGNMENT.max(layout.alignment()).as_usize(),
+    ) / REGULAR_PAGE_SIZE;
+
+    if num_pages > (1 << BuddyOrder::MAX as usize) || num_pages == 0 {
+        return None;
+    }
+
+    Some(BuddyOrder::from(
+        num_pages.next_power_of_two().trailing_zeros() as u8,
+    ))
+}
+
 unsafe impl<Arena, Block> GlobalAlloc for BuddyAllocator<Arena, Block>
 where
     Arena: BuddyArena<Block>,
     Block: const BuddyBlock,
 {
     #[track_caller]
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let num_pages = layout.size().next_multiple_of(
-            REGULAR_PAGE_ALIGNMENT.max(layout.alignment()).as_usize(),
-        ) / REGULAR_PAGE_SIZE;
-
-        debug_assert!(num_pages <= (1 << BuddyOrder::MAX as usize));
-
-        if num_pages > (1 << BuddyOrder::MAX as usize) || num_pages == 0 {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(order) = order_of(layout) else {
             return core::ptr::null_mut();
-        }
-
-        let order = (usize::BITS
-            - 1
-            - num_pages.next_power_of_two().leading_zeros())
-            as usize;
-
-        let page =
-            self.freelist.lock()[order].next.read().unwrap_or_else(|| {
-                NonNull::from_ref(unsafe {
-                    self.split_until(order)
-                        .expect("Out of memory, swap is not implemented")
-                        .as_ref()
-                        .meta()
-                })
-            });
-
-        unsafe { self.allocate_meta(page) };
+        };
 
-        self.arena
-            .lock()
-            .address_of(Block::from_meta(page))
-            .as_non_null::<u8>()
-            .as_ptr()
+        match self.try_alloc_pages(order) {
+            Ok(block) => self.pointer_of(block).as_ptr(),
+            Err(_) => {
+                self.report_out_of_memory(layout);
+                core::ptr::null_mut()
+            }
+        }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        let num_pages = layout.size().next_multiple_of(
-            REGULAR_PAGE_ALIGNMENT.max(layout.alignment()).as_usize(),
-        );
-
-        debug_assert!(
-            num_pages <= (1 << BuddyOrder::MAX as usize),
-            "Size cannot be greater then: {}",
-            1 << BuddyOrder::MAX as usize
+    #[track_caller]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let order = order_of(layout).expect(
+            "Tried to deallocate a layout that couldn't possibly be \
+             allocated by the allocate function.",
         );
-        if num_pages > (1 << BuddyOrder::MAX as usize) || num_pages == 0 {
-            panic!(
-                "Tried to deallocate a layout that couldn't possibly be \
-                 allocated by the allocate function."
-            )
-        }
-        let mut page = self
-            .arena
-            .lock()
-            .page_with_address(PhysicalAddress::from(ptr as usize))
-            .unwrap();
 
-        let order = (usize::BITS
-            - 1
-            - num_pages.next_power_of_two().leading_zeros())
-            as usize;
+        let page = self.block_of(NonNull::new(ptr).unwrap()).unwrap();
 
-        unsafe {
-            page.as_mut()
-                .meta_mut()
-                .flags
-                .set_order(BuddyOrder::from(order as u8));
-        }
+        debug_assert_eq!(
+            unsafe { page.as_ref().meta().flags.get_order() },
+            order
+        );
 
-        // Deallocate the page by attaching it back to the freelist.
-        self.freelist.lock()[order].attach_block(page);
+        unsafe { self.free_pages(page) };
     }
 }
@@ -1,12 +1,14 @@
 use core::{fmt::Debug, ptr::NonNull};
 
 use common::{
-    address_types::PhysicalAddress, enums::BuddyOrder, volatile::Volatile,
+    address_types::{Address, PhysicalAddress, VirtualAddress},
+    enums::BuddyOrder,
+    volatile::Volatile,
 };
 
 use macros::bitfields;
 use thiserror::Error;
-use x86::memory_map::MemoryMap;
+use x86::{memory_map::MemoryMap, structures::paging::VirtualAddressExt};
 
 #[derive(Debug, Error)]
 pub enum BuddyError {
@@ -20,6 +22,10 @@ pub enum BuddyError {
     BuddyOutOfRange,
     #[error("Page is not part of the arena")]
     PageNotInArena,
+    #[error("There is no free block of the requested order or larger")]
+    OutOfMemory,
+    #[error("Cannot allocate a block of BuddyOrder::None")]
+    InvalidOrder,
 }
 
 mod private {
@@ -203,6 +209,38 @@ pub trait BuddyArena<Block: BuddyBlock>: Sized {
         address: PhysicalAddress,
     ) -> Result<NonNull<Block>, BuddyError>;
 
+    /// Returns a pointer the kernel can use to access the memory of this
+    /// block.
+    ///
+    /// By default the memory is accessed through the direct map of the
+    /// physical memory.
+    fn pointer_of(&self, block: NonNull<Block>) -> NonNull<u8> {
+        self.address_of(block).translate().as_non_null()
+    }
+
+    /// Returns the physical address of memory that was handed out before
+    /// the buddy allocator, such as a bump allocation, if it is mapped.
+    ///
+    /// By default the address is translated through the active page
+    /// tables.
+    fn physical_address_of(
+        &self,
+        address: VirtualAddress,
+    ) -> Option<PhysicalAddress> {
+        address.translate()
+    }
+
+    /// Return the corresponding block in the arena for a pointer that was
+    /// returned from [`BuddyArena::pointer_of`], if one exists.
+    fn page_with_pointer(
+        &self,
+        ptr: NonNull<u8>,
+    ) -> Result<NonNull<Block>, BuddyError> {
+        self.page_with_address(PhysicalAddress::from_direct_map(
+            VirtualAddress::from(ptr),
+        ))
+    }
+
     /// Split a block into two smaller blocks of previous order.
     fn split(
         &self,
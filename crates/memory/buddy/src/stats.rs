@@ -0,0 +1,91 @@
+use core::fmt::Display;
+
+use common::enums::{BuddyOrder, Zone};
+use strum::VariantArray;
+
+/// A snapshot of the state of a
+/// [`BuddyAllocator`](crate::BuddyAllocator).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuddyStats {
+    /// The amount of free blocks on the freelist of each order.
+    pub free_blocks: [usize; BuddyOrder::MAX as usize + 1],
+    /// The amount of pages in the arena, including pages that are not
+    /// usable memory.
+    pub total_pages: usize,
+    /// The amount of pages that are part of a free block.
+    pub free_pages: usize,
+    /// The amount of pages that are allocated, or not usable.
+    pub used_pages: usize,
+    /// The order of the largest free block, if there is any free block.
+    pub largest_free_block: Option<BuddyOrder>,
+    /// The pages of each zone.
+    pub zones: [ZoneStats; Zone::COUNT],
+}
+
+/// The pages of a single [`Zone`] in a [`BuddyStats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneStats {
+    /// The amount of pages in the arena that are inside the zone.
+    pub total_pages: usize,
+    /// The amount of pages of the zone that are part of a free block.
+    pub free_pages: usize,
+}
+
+impl BuddyStats {
+    /// Returns how fragmented the free memory is for allocations of
+    /// `order`, in percents.
+    ///
+    /// This is the part of the free memory that is in blocks smaller than
+    /// `order`, which cannot hold such an allocation. It is `0` when all
+    /// of the free memory is in blocks of `order` or larger, and `100`
+    /// when all of it is scattered across smaller blocks. This is `0` when
+    /// there is no free memory at all.
+    pub fn fragmentation_index(&self, order: BuddyOrder) -> usize {
+        if self.free_pages == 0 {
+            return 0;
+        }
+
+        let small_pages: usize = self
+            .free_blocks
+            .iter()
+            .take(order as usize)
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum();
+
+        small_pages * 100 / self.free_pages
+    }
+}
+
+impl Display for BuddyStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "Pages: {} total, {} free, {} used",
+            self.total_pages, self.free_pages, self.used_pages
+        )?;
+
+        for (zone, stats) in Zone::VARIANTS.iter().zip(self.zones.iter()) {
+            writeln!(
+                f,
+                "Zone {:?}: {} total, {} free",
+                zone, stats.total_pages, stats.free_pages
+            )?;
+        }
+
+        for (order, count) in self.free_blocks.iter().enumerate() {
+            writeln!(f, "Order{:<2}: {} free blocks", order, count)?;
+        }
+
+        match self.largest_free_block {
+            Some(order) => write!(f, "Largest free block: {:?}", order)?,
+            None => write!(f, "Largest free block: None")?,
+        }
+
+        write!(
+            f,
+            ", Fragmentation: {}%",
+            self.fragmentation_index(BuddyOrder::MAX)
+        )
+    }
+}
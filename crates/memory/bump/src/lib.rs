@@ -6,7 +6,7 @@
 use core::alloc::{GlobalAlloc, Layout};
 
 use common::{
-    address_types::{Address, VirtualAddress},
+    address_types::{Address, PhysicalAddress},
     alloc::{Allocation, Allocations, BumpAllocations},
     constants::REGULAR_PAGE_ALIGNMENT,
     enums::MemoryRegionType,
@@ -42,7 +42,7 @@ unsafe impl<'a> GlobalAlloc for BumpAllocator<'a> {
         let alignment = layout.alignment().max(REGULAR_PAGE_ALIGNMENT);
 
         let mut aligned_cursor = unsafe {
-            VirtualAddress::new_unchecked(*curser).align_up(alignment)
+            PhysicalAddress::new_unchecked(*curser).align_up(alignment)
         };
 
         let regions = self.mmap.regions.read();
@@ -54,7 +54,7 @@ unsafe impl<'a> GlobalAlloc for BumpAllocator<'a> {
                 // If the cursor is before the block, advance it.
                 if aligned_cursor.as_usize() < b.base_address as usize {
                     aligned_cursor = unsafe {
-                        VirtualAddress::new_unchecked(
+                        PhysicalAddress::new_unchecked(
                             b.base_address as usize,
                         )
                         .align_up(alignment)
@@ -70,11 +70,11 @@ unsafe impl<'a> GlobalAlloc for BumpAllocator<'a> {
 
         if memmap_block.is_some() {
             *curser = aligned_cursor.as_usize() + layout.size();
-            self.allocations.lock().write(Allocation {
-                layout,
-                base: aligned_cursor,
-            });
-            unsafe { aligned_cursor.as_non_null().as_mut() }
+
+            // The memory is accessed through the direct map.
+            let base = aligned_cursor.translate();
+            self.allocations.lock().write(Allocation { layout, base });
+            unsafe { base.as_non_null().as_mut() }
         } else {
             core::ptr::null_mut()
         }
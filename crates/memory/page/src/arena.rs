@@ -18,7 +18,10 @@ use buddy::meta::{
     Regular,
 };
 
-use crate::{Page, meta::PageMeta};
+use crate::{
+    Page,
+    meta::{MappingMeta, PageMeta},
+};
 
 pub struct PageMap {
     inner: Box<[Page]>,
@@ -28,13 +31,20 @@ impl BuddyArena<Page> for PageMap {
     fn new(mmap: &MemoryMap, head: &mut BuddyMeta<Head>) -> Self {
         let regions = mmap.regions.read();
 
+        // ACPI reclaimable memory is included, so it can be freed once
+        // the ACPI tables are consumed.
         let last = regions
             .iter()
-            .filter(|r| r.region_type == MemoryRegionType::Usable)
-            .last()
+            .filter(|r| {
+                matches!(
+                    r.region_type,
+                    MemoryRegionType::Usable | MemoryRegionType::Reclaimable
+                )
+            })
+            .next_back()
             .expect("Memory map is empty, no useble region found");
 
-        let last_address = (last.base_address + last.length) as usize;
+        let last_address = last.end() as usize;
         let total_pages = last_address / REGULAR_PAGE_SIZE;
         println!("Total pages: {}", total_pages);
         unsafe {
@@ -53,6 +63,7 @@ impl BuddyArena<Page> for PageMap {
                             .allocated(false),
                     ),
                 },
+                mapping: MappingMeta::new(),
             };
 
             head.attach_block(NonNull::from_ref(prev));
@@ -70,6 +81,7 @@ impl BuddyArena<Page> for PageMap {
                                 .allocated(false),
                         ),
                     },
+                    mapping: MappingMeta::new(),
                 };
                 prev.meta.buddy.attach_block(NonNull::from_mut(next));
             }
@@ -0,0 +1,129 @@
+extern crate alloc;
+
+use core::{alloc::Layout, ptr::NonNull};
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+
+use common::{
+    address_types::{Address, PhysicalAddress, VirtualAddress},
+    constants::REGULAR_PAGE_SIZE,
+};
+use x86::memory_map::MemoryMap;
+
+use buddy::meta::{BuddyArena, BuddyError, BuddyMeta, Head};
+
+use crate::{Page, arena::PageMap};
+
+/// A [`PageMap`] that is backed by a buffer on the heap, so the buddy
+/// allocator can run as a regular process on the host.
+///
+/// Physical addresses are offsets into the buffer, so the memory map can
+/// be used as is, and the pointers that are handed out point into the
+/// buffer.
+pub struct HostPageMap {
+    pages: PageMap,
+    memory: NonNull<u8>,
+    layout: Layout,
+}
+
+// The memory is owned by the arena and is only accessed through it.
+unsafe impl Send for HostPageMap {}
+
+impl BuddyArena<Page> for HostPageMap {
+    fn new(mmap: &MemoryMap, head: &mut BuddyMeta<Head>) -> Self {
+        let pages = PageMap::new(mmap, head);
+
+        let layout = Layout::from_size_align(
+            pages.iter().len() * REGULAR_PAGE_SIZE,
+            REGULAR_PAGE_SIZE,
+        )
+        .unwrap();
+
+        let memory = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .expect("Could not allocate the host arena memory");
+
+        HostPageMap {
+            pages,
+            memory,
+            layout,
+        }
+    }
+
+    fn iter(&self) -> impl ExactSizeIterator<Item = NonNull<Page>> {
+        self.pages.iter()
+    }
+
+    fn buddy_of(
+        &self,
+        block: NonNull<Page>,
+    ) -> Result<NonNull<Page>, BuddyError> {
+        self.pages.buddy_of(block)
+    }
+
+    fn address_of(&self, block: NonNull<Page>) -> PhysicalAddress {
+        self.pages.address_of(block)
+    }
+
+    fn page_with_address(
+        &self,
+        address: PhysicalAddress,
+    ) -> Result<NonNull<Page>, BuddyError> {
+        self.pages.page_with_address(address)
+    }
+
+    fn pointer_of(&self, block: NonNull<Page>) -> NonNull<u8> {
+        unsafe { self.memory.add(self.address_of(block).as_usize()) }
+    }
+
+    /// There are no page tables on the host, and the allocations before
+    /// the handoff are given by their physical address.
+    fn physical_address_of(
+        &self,
+        address: VirtualAddress,
+    ) -> Option<PhysicalAddress> {
+        Some(PhysicalAddress::from(address.as_usize()))
+    }
+
+    fn page_with_pointer(
+        &self,
+        ptr: NonNull<u8>,
+    ) -> Result<NonNull<Page>, BuddyError> {
+        let offset = ptr
+            .addr()
+            .get()
+            .checked_sub(self.memory.addr().get())
+            .ok_or(BuddyError::PageNotInArena)?;
+
+        self.page_with_address(PhysicalAddress::from(offset))
+    }
+
+    fn split(
+        &self,
+        block: NonNull<Page>,
+    ) -> Result<(NonNull<Page>, NonNull<Page>), BuddyError> {
+        self.pages.split(block)
+    }
+
+    fn merge(
+        &self,
+        block: NonNull<Page>,
+        buddy: NonNull<Page>,
+    ) -> Result<NonNull<Page>, BuddyError> {
+        self.pages.merge(block, buddy)
+    }
+
+    fn at(&self, n: usize) -> Option<NonNull<Page>> { self.pages.at(n) }
+
+    unsafe fn section_index_of(
+        &self,
+        block: NonNull<Page>,
+    ) -> (usize, usize) {
+        unsafe { self.pages.section_index_of(block) }
+    }
+}
+
+impl Drop for HostPageMap {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.memory.as_ptr(), self.layout) }
+    }
+}
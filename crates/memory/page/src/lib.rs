@@ -2,16 +2,19 @@
 #![feature(const_trait_impl)]
 
 pub mod arena;
+#[cfg(feature = "host")]
+pub mod host;
 pub mod meta;
 
 use core::ptr::NonNull;
 
-use crate::meta::PageMeta;
+use crate::meta::{MappingMeta, PageMeta};
 use buddy::meta::{BuddyBlock, BuddyMeta, Regular};
 
 #[derive(Debug)]
 pub struct Page {
     pub meta: PageMeta,
+    pub mapping: MappingMeta,
 }
 
 #[rustfmt::skip]
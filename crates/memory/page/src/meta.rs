@@ -1,11 +1,39 @@
-use core::fmt::Debug;
+use core::{
+    fmt::Debug,
+    mem::offset_of,
+    ptr::NonNull,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use buddy::meta::{BuddyMeta, Regular};
 
 pub union PageMeta {
     pub buddy: BuddyMeta<Regular>,
+    pub slab: SlabMeta,
 }
 
+/// Metadata of a page that is owned by a slab cache.
+///
+/// The slab allocator is built on top of the page map, so the types are
+/// erased here, and assigned back by the slab allocator.
+///
+/// **Note**: This only overlaps the list pointers of the buddy meta, so
+/// the buddy flags stay valid while the page is owned by a slab.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SlabMeta {
+    /// The cache that owns this page.
+    pub owner: NonNull<()>,
+    /// The descriptor of the slab this page is a part of.
+    pub descriptor: NonNull<()>,
+}
+
+unsafe impl Send for SlabMeta {}
+
+const _: () = assert!(
+    size_of::<SlabMeta>() <= offset_of!(BuddyMeta<Regular>, flags)
+);
+
 impl Debug for PageMeta {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("PageMeta")
@@ -13,3 +41,53 @@ impl Debug for PageMeta {
             .finish()
     }
 }
+
+/// The number of page table entries that share a page copy-on-write.
+///
+/// This is kept outside of [`PageMeta`], because it must stay valid
+/// while the page is owned by any allocator. A page that is not shared
+/// has a count of zero.
+#[derive(Debug)]
+pub struct MappingMeta {
+    count: AtomicU32,
+}
+
+impl MappingMeta {
+    pub const fn new() -> Self {
+        Self {
+            count: AtomicU32::new(0),
+        }
+    }
+
+    pub fn count(&self) -> u32 { self.count.load(Ordering::Relaxed) }
+
+    /// Add a mapping to the page. A page that was not shared yet is now
+    /// shared by its original mapping and the new one.
+    pub fn share(&self) {
+        let _ = self.count.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |n| Some(n.max(1) + 1),
+        );
+    }
+
+    /// Take the page for a single mapping, if no other mapping shares
+    /// it. Returns true if the page is not shared anymore.
+    pub fn try_unshare(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n <= 1).then_some(0)
+            })
+            .is_ok()
+    }
+
+    /// Remove a mapping that moved to a copy of the page.
+    pub fn release(&self) {
+        let previous = self.count.fetch_sub(1, Ordering::AcqRel);
+        debug_assert!(previous > 1, "Released a page that is not shared");
+    }
+}
+
+impl Default for MappingMeta {
+    fn default() -> Self { Self::new() }
+}
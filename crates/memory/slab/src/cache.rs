@@ -1,17 +1,36 @@
-use core::{num::NonZero, ptr::NonNull};
+use core::ptr::NonNull;
 
-use common::address_types::VirtualAddress;
+use buddy::meta::BuddyError;
+use common::enums::BuddyOrder;
+use page::meta::SlabMeta;
+use thiserror::Error;
 
-use crate::{traits::Slab, unassigned::UnassignSlab};
+use crate::{
+    SLAB_ALLOCATOR, buddy,
+    traits::{Slab, SlabPosition},
+    unassigned::{AssignSlab, UnassignSlab},
+};
 
 use super::{descriptor::SlabDescriptor, traits::SlabCacheConstructor};
 
+#[derive(Debug, Error)]
+pub enum SlabError {
+    #[error("Could not allocate pages for a new slab: {0}")]
+    OutOfMemory(#[from] BuddyError),
+}
+
 #[derive(Clone, Debug)]
 pub struct SlabCache<T: Slab> {
     pub buddy_order: usize,
     pub free: Option<NonNull<SlabDescriptor<T>>>,
     pub partial: Option<NonNull<SlabDescriptor<T>>>,
     pub full: Option<NonNull<SlabDescriptor<T>>>,
+    /// The amount of slabs on the free list.
+    pub free_slabs: usize,
+    /// The amount of free slabs that are kept when the cache is reaped.
+    pub low_watermark: usize,
+    /// The amount of free slabs that triggers a reap of the cache.
+    pub high_watermark: usize,
 }
 
 impl<T: Slab> UnassignSlab for NonNull<SlabCache<T>> {
@@ -25,80 +44,252 @@ impl<T: Slab> UnassignSlab for NonNull<SlabCache<T>> {
 }
 
 impl<T: Slab> SlabCache<T> {
-    /// Allocate a new slab descriptor, attaches it to the free slab list,
-    /// and initialize it's page.
+    /// Allocate pages for a new slab and attach it to the free slab
+    /// list.
+    pub fn grow(&mut self) -> Result<(), SlabError> {
+        let is_descriptors_cache = T::SLAB_POSITION
+            == <SlabDescriptor<()> as SlabPosition>::SLAB_POSITION;
+
+        // The descriptors cache cannot allocate from itself while it is
+        // growing, so the new slab holds its own descriptor.
+        let descriptor = if is_descriptors_cache {
+            None
+        } else {
+            Some(SLAB_ALLOCATOR.kmalloc::<SlabDescriptor<()>>()?)
+        };
+
+        let block = match buddy()
+            .try_alloc_pages(BuddyOrder::from(self.buddy_order as u8))
+        {
+            Ok(block) => block,
+            Err(e) => {
+                if let Some(descriptor) = descriptor {
+                    unsafe { SLAB_ALLOCATOR.kfree(descriptor) };
+                }
+                return Err(e.into());
+            }
+        };
+
+        let memory = buddy().pointer_of(block);
+
+        let slab = match descriptor {
+            Some(descriptor) => unsafe {
+                let slab = descriptor.assign::<T>();
+                slab.write(SlabDescriptor::new(
+                    memory,
+                    self.buddy_order,
+                    None,
+                ));
+                slab
+            },
+            None => unsafe {
+                SlabDescriptor::<SlabDescriptor<()>>::initial_descriptor(
+                    memory,
+                    self.buddy_order,
+                )
+                .as_unassigned()
+                .assign::<T>()
+            },
+        };
+
+        // The objects are constructed once, when their slab is created,
+        // and keep their state while they are free. The constructor
+        // overwrites the index of the next free object, which is put back.
+        let objects = unsafe { slab.as_ref().objects.as_ptr() };
+        let mut next = unsafe { slab.as_ref().next_free_idx };
+        while let Some(index) = next {
+            let slot =
+                unsafe { &mut (*objects)[index.get() as usize].slot };
+            next = unsafe { slot.next_free_idx };
+            Self::construct(NonNull::from_mut(slot).cast());
+            slot.next_free_idx = next;
+        }
+
+        for i in 0..(1 << self.buddy_order) {
+            unsafe {
+                block.add(i).as_mut().meta.slab = SlabMeta {
+                    owner: NonNull::from_ref(self).cast(),
+                    descriptor: slab.cast(),
+                }
+            };
+        }
+
+        self.attach(slab);
+
+        Ok(())
+    }
+
+    #[track_caller]
+    pub fn alloc(&mut self) -> Result<NonNull<T>, SlabError> {
+        if self.partial.is_none() && self.free.is_none() {
+            self.grow()?;
+        }
+
+        let list = if self.partial.is_some() {
+            &mut self.partial
+        } else {
+            &mut self.free
+        };
+
+        let mut slab = list.unwrap();
+        *list = unsafe { slab.as_ref().next };
+
+        if unsafe { slab.as_ref().is_empty() } {
+            self.free_slabs -= 1;
+        }
+
+        let allocation = unsafe { slab.as_mut().alloc() };
+
+        self.attach(slab);
+
+        Ok(allocation)
+    }
+
+    /// Return the object to its slab.
+    ///
+    /// The object is not dropped, see [`SlabCacheConstructor::destruct`].
+    ///
+    /// # Safety
+    /// The object must be allocated from this cache, and left in the state
+    /// that [`SlabCacheConstructor::construct`] put it in.
+    #[track_caller]
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<T>) {
+        let page = buddy()
+            .block_of(ptr.cast())
+            .expect("Object is not on the page map");
+
+        let meta = unsafe { page.as_ref().meta.slab };
+
+        debug_assert_eq!(
+            meta.owner,
+            NonNull::from_ref(self).cast(),
+            "Object is not owned by this cache"
+        );
+
+        let mut slab = meta.descriptor.cast::<SlabDescriptor<T>>();
+
+        #[cfg(feature = "memory-debug")]
+        unsafe { slab.as_ref() }.check_dealloc(ptr);
+
+        self.detach(slab);
+        unsafe { slab.as_mut().dealloc(ptr) };
+        self.attach(slab);
+
+        if self.free_slabs > self.high_watermark {
+            self.reap(self.low_watermark);
+        }
+    }
+
+    /// Set the watermarks of this cache.
     ///
-    /// TODO: THIS FUNCTION SHOULD MOVE TO THE SLAB ALLOCATOR AND GET A
-    /// TYPE OF A SPECIFIC SLAB
-    pub fn grow(&mut self) {
-        // Allocate a new slab descriptor for this slab
-        // let mut slab = unsafe {
-        //     SLAB_ALLOCATOR.kmalloc::<SlabDescriptor<()>>().assign::<T>()
-        // };
-
-        // unsafe {
-        //     *slab.as_mut() =
-        //         SlabDescriptor::<T>::new(self.buddy_order, self.free)
-        // }
-
-        // self.take_ownership(slab);
-
-        // self.free = Some(slab);
+    /// When there are more than `high` free slabs, free slabs are released
+    /// back to the buddy allocator until `low` free slabs are left.
+    pub fn set_watermarks(&mut self, low: usize, high: usize) {
+        assert!(low <= high, "Low watermark is above the high watermark");
+
+        self.low_watermark = low;
+        self.high_watermark = high;
     }
 
-    pub fn take_ownership(&self, slab: NonNull<SlabDescriptor<T>>) {
-        let slab_address: VirtualAddress =
-            unsafe { slab.as_ref().objects.as_ptr().addr().into() };
+    /// Release free slabs back to the buddy allocator until only `keep`
+    /// free slabs are left.
+    ///
+    /// Returns the amount of pages that were released.
+    pub fn reap(&mut self, keep: usize) -> usize {
+        let mut released = 0;
 
-        slab_address
-            .set_flags(T::PFLAGS, T::PSIZE, unsafe {
-                NonZero::<usize>::new_unchecked(1 << self.buddy_order)
-            })
-            .unwrap();
+        while self.free_slabs > keep {
+            let slab = self.free.unwrap();
+            self.free = unsafe { slab.as_ref().next };
+            self.free_slabs -= 1;
 
-        let slab_page =
-            unsafe { UnassignedPage::from_virt(slab_address).as_mut() };
+            released += self.release(slab);
+        }
 
-        // Set owner and freelist.
-        unsafe {
-            (*slab_page.meta.slab).freelist = slab.as_unassigned();
-            (*slab_page.meta.slab).owner =
-                NonNull::from_ref(self).as_unassigned();
-        };
+        released
     }
 
-    pub fn alloc(&mut self) -> NonNull<T> {
-        if let Some(mut partial) = self.partial {
-            let partial = unsafe { partial.as_mut() };
+    /// Release all of the free slabs back to the buddy allocator.
+    ///
+    /// Returns the amount of pages that were released.
+    pub fn shrink(&mut self) -> usize { self.reap(0) }
 
-            let allocation = partial.alloc();
+    /// Destruct the objects of a detached empty slab, return its pages to
+    /// the buddy allocator, and free its descriptor.
+    fn release(&mut self, slab: NonNull<SlabDescriptor<T>>) -> usize {
+        let descriptor = unsafe { slab.as_ref() };
 
-            if partial.next_free_idx.is_none() {
-                self.partial = partial.next;
-                partial.next = self.full;
-                self.full = Some(NonNull::from_mut(partial));
-            }
-            return allocation;
+        debug_assert!(descriptor.is_empty(), "Released a slab in use");
+
+        let objects = descriptor.objects.as_ptr();
+        let mut next = descriptor.next_free_idx;
+        while let Some(index) = next {
+            let slot =
+                unsafe { &mut (*objects)[index.get() as usize].slot };
+            next = unsafe { slot.next_free_idx };
+            slot.next_free_idx = None;
+            Self::destruct(NonNull::from_mut(slot).cast());
         }
-        if let Some(mut free) = self.free {
-            let free = unsafe { free.as_mut() };
 
-            let allocation = free.alloc();
+        let block = buddy()
+            .block_of(descriptor.objects.cast::<u8>())
+            .expect("Slab is not on the page map");
+
+        let is_self_hosted = descriptor.is_self_hosted();
+
+        // A self hosted descriptor is freed along with its pages.
+        unsafe { buddy().free_pages(block) };
+
+        if !is_self_hosted {
+            unsafe { SLAB_ALLOCATOR.kfree(slab.as_unassigned()) };
+        }
+
+        1 << self.buddy_order
+    }
 
-            self.free = free.next;
-            free.next = self.partial;
-            self.partial = Some(NonNull::from_mut(free));
+    /// Returns the list that a slab in the current state belongs to.
+    fn list_of(
+        &mut self,
+        slab: NonNull<SlabDescriptor<T>>,
+    ) -> &mut Option<NonNull<SlabDescriptor<T>>> {
+        let slab = unsafe { slab.as_ref() };
+        if slab.is_full() {
+            &mut self.full
+        } else if slab.is_empty() {
+            &mut self.free
+        } else {
+            &mut self.partial
+        }
+    }
 
-            return allocation;
+    /// Attach a slab to the head of the list that matches its state.
+    fn attach(&mut self, mut slab: NonNull<SlabDescriptor<T>>) {
+        if unsafe { slab.as_ref().is_empty() } {
+            self.free_slabs += 1;
         }
 
-        todo!(
-            "Handle cases where partial and free are full, and \
-             allocation from the page allocator is needed."
-        )
+        let list = self.list_of(slab);
+        unsafe { slab.as_mut().next = *list };
+        *list = Some(slab);
     }
-    pub fn dealloc(&self, _ptr: NonNull<T>) {
-        todo!()
+
+    /// Detach a slab from the list that matches its state.
+    fn detach(&mut self, slab: NonNull<SlabDescriptor<T>>) {
+        let mut current = self.list_of(slab);
+
+        while let Some(mut node) = *current {
+            if node == slab {
+                *current = unsafe { node.as_ref().next };
+
+                if unsafe { slab.as_ref().is_empty() } {
+                    self.free_slabs -= 1;
+                }
+                return;
+            }
+            current = unsafe { &mut node.as_mut().next };
+        }
+
+        unreachable!("Slab is not on the list that matches its state");
     }
 }
 
@@ -110,46 +301,22 @@ impl SlabCache<()> {
     }
 }
 
-impl<T: Slab> SlabCacheConstructor for SlabCache<T> {
+impl<T: Slab> SlabCacheConstructor<T> for SlabCache<T> {
     default fn new(buddy_order: usize) -> SlabCache<T> {
         SlabCache {
             buddy_order,
             free: None,
             partial: None,
             full: None,
+            free_slabs: 0,
+            low_watermark: 1,
+            high_watermark: 4,
         }
     }
-}
 
-impl SlabCacheConstructor for SlabCache<SlabDescriptor<()>> {
-    fn new(buddy_order: usize) -> SlabCache<SlabDescriptor<()>> {
-        let partial =
-            SlabDescriptor::<SlabDescriptor<()>>::initial_descriptor(
-                buddy_order,
-            );
+    default const CONSTRUCTED: bool = false;
 
-        // This assumption can be made, because the created cache in
-        // this function will go to the constant position on the slab
-        // array defined with the `SlabPosition` array
-        let mut future_owner =
-            unsafe { SLAB_ALLOCATOR.slab_of::<SlabDescriptor<()>>() };
+    default fn construct(_object: NonNull<T>) {}
 
-        let cache = SlabCache {
-            buddy_order,
-            free: None,
-            partial: Some(partial),
-            full: None,
-        };
-
-        // Only in this function, we initialiuze the global array in the
-        // new function.
-        //
-        // Because then we can use the `take_ownership` function
-        unsafe {
-            *future_owner.as_mut() = cache.clone();
-            future_owner.as_mut().take_ownership(partial);
-        }
-
-        cache
-    }
+    default fn destruct(_object: NonNull<T>) {}
 }
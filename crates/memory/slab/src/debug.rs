@@ -0,0 +1,160 @@
+//! Redzones around slab objects and poisoning of free objects, enabled
+//! with the `memory-debug` feature.
+
+use core::{
+    mem::{offset_of, size_of},
+    panic::Location,
+    ptr::{self, NonNull},
+};
+
+use buddy::debug::{POISON_FREE, fill, find_mismatch};
+use nonmax::NonMaxU16;
+
+use crate::{
+    cache::SlabCache,
+    descriptor::{ObjectSlot, PreallocatedObject, SlabDescriptor},
+    traits::{Slab, SlabCacheConstructor},
+};
+
+/// The size of each redzone around an object.
+///
+/// The padding that aligns the object is a part of the redzones as well.
+pub const REDZONE_SIZE: usize = 16;
+
+/// The byte that fills the redzones.
+pub const REDZONE: u8 = 0xbb;
+
+impl<T: Slab> PreallocatedObject<T> {
+    const OBJECT_START: usize = offset_of!(Self, slot);
+
+    const OBJECT_END: usize =
+        Self::OBJECT_START + size_of::<ObjectSlot<T>>();
+
+    /// A free object is poisoned after the index of the next free object.
+    const POISON_START: usize =
+        Self::OBJECT_START + size_of::<Option<NonMaxU16>>();
+
+    /// Free objects of a cache with object hooks keep their state, so
+    /// they are not poisoned.
+    const POISONED: bool =
+        !<SlabCache<T> as SlabCacheConstructor<T>>::CONSTRUCTED;
+
+    /// Fill the object and its redzones with [`REDZONE`].
+    pub fn fill_redzones(&mut self) {
+        unsafe {
+            fill(
+                NonNull::from_mut(self).cast(),
+                size_of::<Self>(),
+                REDZONE,
+            )
+        }
+    }
+
+    /// Fill the object with [`POISON_FREE`], except for the index of the
+    /// next free object.
+    pub fn poison(&mut self) {
+        if !Self::POISONED {
+            return;
+        }
+
+        unsafe {
+            fill(
+                NonNull::from_mut(self)
+                    .cast::<u8>()
+                    .add(Self::POISON_START),
+                Self::OBJECT_END - Self::POISON_START,
+                POISON_FREE,
+            )
+        }
+    }
+
+    /// Panic if anything was written to the redzones of the object.
+    pub fn check_redzones(&self, action: &str, caller: &Location) {
+        let before = unsafe {
+            find_mismatch(
+                NonNull::from_ref(&self.redzone_before).cast(),
+                Self::OBJECT_START,
+                REDZONE,
+            )
+        };
+        let after = unsafe {
+            find_mismatch(
+                NonNull::from_ref(&self.redzone_after).cast(),
+                size_of::<Self>() - Self::OBJECT_END,
+                REDZONE,
+            )
+        };
+
+        if before.is_some() || after.is_some() {
+            panic!(
+                "Slab object at {:p} overflowed into its redzone, found \
+                 when {} at {}",
+                &self.slot, action, caller
+            );
+        }
+    }
+
+    /// Panic if a free object, or its redzones, were written.
+    pub fn check_free(&self, caller: &Location) {
+        self.check_redzones("allocated", caller);
+
+        if !Self::POISONED {
+            return;
+        }
+
+        if let Some(offset) = unsafe {
+            find_mismatch(
+                NonNull::from_ref(self)
+                    .cast::<u8>()
+                    .add(Self::POISON_START),
+                Self::OBJECT_END - Self::POISON_START,
+                POISON_FREE,
+            )
+        } {
+            panic!(
+                "Use after free: the slab object at {:p} was written at \
+                 offset {:#x} while it was free, found when allocated at \
+                 {}",
+                &self.slot,
+                Self::POISON_START - Self::OBJECT_START + offset,
+                caller
+            );
+        }
+    }
+}
+
+impl<T: Slab> SlabDescriptor<T> {
+    /// Panic if `ptr` is not an allocated object of this slab, or if it
+    /// overflowed into its redzones.
+    #[track_caller]
+    pub fn check_dealloc(&self, ptr: NonNull<T>) {
+        let caller = Location::caller();
+        let objects = unsafe { self.objects.as_ref() };
+
+        let Some(index) = objects
+            .iter()
+            .position(|o| ptr::addr_eq(&o.slot, ptr.as_ptr()))
+        else {
+            panic!(
+                "Freed {:p}, which is not an object of its slab, at {}",
+                ptr, caller
+            );
+        };
+
+        // The walk is bounded, in case the free list is corrupted.
+        let mut free =
+            core::iter::successors(self.next_free_idx, |i| unsafe {
+                objects[i.get() as usize].slot.next_free_idx
+            })
+            .take(objects.len());
+
+        if free.any(|i| i.get() as usize == index) {
+            panic!(
+                "Double free of the slab object at {:p} at {}",
+                ptr, caller
+            );
+        }
+
+        objects[index].check_redzones("freed", caller);
+    }
+}
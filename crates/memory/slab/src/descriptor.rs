@@ -1,9 +1,6 @@
 use crate::{
-    alloc_pages,
-    memory::{
-        allocators::slab::traits::Slab,
-        unassigned::{AssignSlab, UnassignSlab},
-    },
+    traits::Slab,
+    unassigned::{AssignSlab, UnassignSlab},
 };
 use common::constants::REGULAR_PAGE_SIZE;
 use core::{
@@ -13,12 +10,31 @@ use core::{
 };
 use nonmax::NonMaxU16;
 
-/// Preallocated object in the slab allocator.
-pub union PreallocatedObject<T: 'static + Sized> {
+#[cfg(feature = "memory-debug")]
+use crate::debug::REDZONE_SIZE;
+#[cfg(feature = "memory-debug")]
+use core::panic::Location;
+
+/// The memory of an object, which holds the index of the next free
+/// object while the object is free.
+pub union ObjectSlot<T: 'static + Sized> {
     pub allocated: ManuallyDrop<T>,
     pub next_free_idx: Option<NonMaxU16>,
 }
 
+/// Preallocated object in the slab allocator.
+///
+/// With the `memory-debug` feature, the object is surrounded by redzones
+/// that catch writes past its bounds.
+#[repr(C)]
+pub struct PreallocatedObject<T: 'static + Sized> {
+    #[cfg(feature = "memory-debug")]
+    pub(crate) redzone_before: [u8; REDZONE_SIZE],
+    pub slot: ObjectSlot<T>,
+    #[cfg(feature = "memory-debug")]
+    pub(crate) redzone_after: [u8; REDZONE_SIZE],
+}
+
 impl<T> Debug for PreallocatedObject<T> {
     fn fmt(&self, _f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         Ok(())
@@ -54,23 +70,24 @@ impl<T: Slab> UnassignSlab for NonNull<SlabDescriptor<T>> {
 }
 
 impl<T: Slab> SlabDescriptor<T> {
-    /// Create a new slab descriptor.
+    /// Create a new slab descriptor over the given memory.
     ///
     /// # Safety
-    /// This function is marked as unsafe because it does not initialize
-    /// the page that the allocation is on.
+    /// `memory` must point to `1 << order` pages that are not used by
+    /// anything else.
+    ///
+    /// This function does not initialize the pages that the memory is on,
+    /// it is meant to be called from the [`grow`] function inside slab
+    /// cache. (Which is safe and do initialize the pages)
     ///
-    /// This function is meant to be called from the [`grow`]
-    /// function inside slab cache. (Which is safe and do initialize
-    /// the page)
+    /// [`grow`]: crate::cache::SlabCache::grow
     pub unsafe fn new(
+        memory: NonNull<u8>,
         order: usize,
         next: Option<NonNull<SlabDescriptor<T>>>,
     ) -> SlabDescriptor<T> {
-        let address = unsafe { alloc_pages!(1 << order).translate() };
-
         let mut objects = NonNull::slice_from_raw_parts(
-            address.as_non_null::<PreallocatedObject<T>>(),
+            memory.cast::<PreallocatedObject<T>>(),
             ((1 << order) * REGULAR_PAGE_SIZE)
                 / size_of::<PreallocatedObject<T>>(),
         );
@@ -78,15 +95,18 @@ impl<T: Slab> SlabDescriptor<T> {
         for (i, object) in
             unsafe { objects.as_mut() }.iter_mut().enumerate()
         {
-            *object = PreallocatedObject {
-                next_free_idx: Some(unsafe {
-                    NonMaxU16::new_unchecked(i as u16 + 1)
-                }),
-            }
+            #[cfg(feature = "memory-debug")]
+            object.fill_redzones();
+
+            object.slot.next_free_idx =
+                Some(unsafe { NonMaxU16::new_unchecked(i as u16 + 1) });
+
+            #[cfg(feature = "memory-debug")]
+            object.poison();
         }
 
         unsafe {
-            objects.as_mut().last_mut().unwrap().next_free_idx = None
+            objects.as_mut().last_mut().unwrap().slot.next_free_idx = None
         };
 
         SlabDescriptor {
@@ -97,6 +117,26 @@ impl<T: Slab> SlabDescriptor<T> {
         }
     }
 
+    /// Returns true if there are no free objects on this slab.
+    pub fn is_full(&self) -> bool { self.next_free_idx.is_none() }
+
+    /// Returns true if no object is allocated from this slab.
+    ///
+    /// A self hosted slab is empty when its only allocated object is its
+    /// own descriptor.
+    pub fn is_empty(&self) -> bool {
+        self.total_allocated == self.is_self_hosted() as u16
+    }
+
+    /// Returns true if this descriptor is allocated on its own slab.
+    ///
+    /// See [`SlabDescriptor::initial_descriptor`].
+    pub fn is_self_hosted(&self) -> bool {
+        let first = self.objects.cast::<PreallocatedObject<T>>().as_ptr();
+        core::ptr::addr_eq(self, unsafe { &raw const (*first).slot })
+    }
+
+    #[track_caller]
     pub fn alloc(&mut self) -> NonNull<T> {
         debug_assert!(
             self.next_free_idx.is_some(),
@@ -106,26 +146,47 @@ impl<T: Slab> SlabDescriptor<T> {
         let idx = self.next_free_idx.unwrap().get() as usize;
         let preallocated = unsafe { &mut self.objects.as_mut()[idx] };
 
-        self.next_free_idx = unsafe { preallocated.next_free_idx };
+        self.next_free_idx = unsafe { preallocated.slot.next_free_idx };
+
+        #[cfg(feature = "memory-debug")]
+        preallocated.check_free(Location::caller());
+
+        preallocated.slot.next_free_idx = None;
 
         self.total_allocated += 1;
 
-        unsafe { NonNull::from_mut(&mut preallocated.allocated) }
+        unsafe { NonNull::from_mut(&mut preallocated.slot.allocated) }
+            .cast()
     }
 
-    // TODO: In tests rembmber to implement something on T that implement
-    // drop and see that when freeing the memory it is called
+    /// Return the object to the free objects of this slab.
+    ///
+    /// The object is not dropped, see [`SlabCacheConstructor::destruct`].
+    ///
+    /// # Safety
+    /// The object must be allocated from this slab.
+    ///
+    /// [`SlabCacheConstructor::destruct`]: crate::traits::SlabCacheConstructor::destruct
     pub unsafe fn dealloc(&mut self, ptr: NonNull<T>) {
-        todo!("Remember to call drop on the item");
+        debug_assert!(
+            self.objects.as_ptr().cast::<T>() <= ptr.as_ptr()
+                && ptr.as_ptr().addr()
+                    < self.objects.as_ptr().addr()
+                        + self.objects.len()
+                            * size_of::<PreallocatedObject<T>>(),
+            "Object is not a part of this slab"
+        );
 
         let freed_index = (ptr.as_ptr().addr()
             - self.objects.as_ptr().addr())
             / size_of::<PreallocatedObject<T>>();
 
-        unsafe {
-            self.objects.as_mut()[freed_index].next_free_idx =
-                self.next_free_idx;
-        };
+        let freed = unsafe { &mut self.objects.as_mut()[freed_index] };
+
+        #[cfg(feature = "memory-debug")]
+        freed.poison();
+
+        freed.slot.next_free_idx = self.next_free_idx;
         self.next_free_idx =
             unsafe { Some(NonMaxU16::new_unchecked(freed_index as u16)) };
 
@@ -134,27 +195,27 @@ impl<T: Slab> SlabDescriptor<T> {
 }
 
 impl SlabDescriptor<SlabDescriptor<()>> {
-    /// Return a pointer to the initial descriptor after it allocated
-    /// himself.
+    /// Return a pointer to a descriptor that allocated itself from the
+    /// given memory.
     ///
     /// The pointer the is returned by this function contains an already
-    /// initialized descriptor that allocates itself.
-    pub fn initial_descriptor(
+    /// initialized descriptor, that has its own object allocated.
+    ///
+    /// # Safety
+    /// Same as [`SlabDescriptor::new`].
+    pub unsafe fn initial_descriptor(
+        memory: NonNull<u8>,
         order: usize,
     ) -> NonNull<SlabDescriptor<SlabDescriptor<()>>> {
         let mut descriptor = unsafe {
-            SlabDescriptor::<SlabDescriptor<()>>::new(order, None)
+            SlabDescriptor::<SlabDescriptor<()>>::new(memory, order, None)
         };
 
-        let mut self_allocation = descriptor.alloc();
+        let self_allocation =
+            descriptor.alloc().assign::<SlabDescriptor<()>>();
 
-        unsafe {
-            *self_allocation.as_mut() = NonNull::from_ref(&descriptor)
-                .as_unassigned()
-                .as_ref()
-                .clone()
-        }
+        unsafe { self_allocation.write(descriptor) };
 
-        self_allocation.assign::<SlabDescriptor<()>>()
+        self_allocation
     }
 }
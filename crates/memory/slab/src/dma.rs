@@ -0,0 +1,219 @@
+use core::{mem::Alignment, ptr::NonNull};
+
+use buddy::meta::BuddyError;
+use common::{
+    address_types::{Address, PhysicalAddress, VirtualAddress},
+    constants::{GiB, REGULAR_PAGE_ALIGNMENT, REGULAR_PAGE_SIZE},
+    enums::{BuddyOrder, PageSize},
+    error::MappingError,
+};
+use libk::alloc::VirtualAddressMapping;
+use thiserror::Error;
+use x86::structures::paging::PageEntryFlags;
+
+use crate::{buddy, traits::DmaGeneric};
+
+#[derive(Debug, Error)]
+pub enum DmaError {
+    #[error("Could not allocate pages for a DMA buffer: {0}")]
+    OutOfMemory(#[from] BuddyError),
+    #[error("A DMA buffer of {0} bytes is larger than the largest block")]
+    TooLarge(usize),
+    #[error(
+        "A DMA buffer of {size} bytes cannot fit inside a {boundary} \
+         bytes boundary"
+    )]
+    CrossesBoundary { size: usize, boundary: usize },
+    #[error("Could not map the DMA buffer: {0}")]
+    Mapping(#[from] MappingError),
+}
+
+/// Constraints on the physical memory of a DMA buffer.
+///
+/// ```ignore
+/// let constraints = DmaConstraints::new()
+///     .below_4gib()
+///     .alignment(1024)
+///     .boundary(4 * MiB);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaConstraints {
+    /// The end of the physical memory the device can address.
+    pub limit: usize,
+    /// The alignment of the physical address of the buffer.
+    pub alignment: Alignment,
+    /// The buffer must not cross a multiple of this value.
+    pub boundary: Option<Alignment>,
+}
+
+impl DmaConstraints {
+    /// Page aligned memory anywhere in the physical memory.
+    pub const fn new() -> Self {
+        Self {
+            limit: usize::MAX,
+            alignment: REGULAR_PAGE_ALIGNMENT,
+            boundary: None,
+        }
+    }
+
+    /// Only use memory below 4GiB, for devices with 32 bit addressing.
+    pub const fn below_4gib(self) -> Self {
+        Self {
+            limit: 4 * GiB,
+            ..self
+        }
+    }
+
+    /// Align the physical address of the buffer.
+    ///
+    /// # Panics
+    /// If the alignment is not a power of two.
+    pub const fn alignment(self, alignment: usize) -> Self {
+        Self {
+            alignment: Alignment::new(alignment)
+                .expect("DMA alignment is not a power of two"),
+            ..self
+        }
+    }
+
+    /// Don't let the buffer cross a multiple of `boundary`.
+    ///
+    /// # Panics
+    /// If the boundary is not a power of two.
+    pub const fn boundary(self, boundary: usize) -> Self {
+        Self {
+            boundary: Some(
+                Alignment::new(boundary)
+                    .expect("DMA boundary is not a power of two"),
+            ),
+            ..self
+        }
+    }
+}
+
+impl Default for DmaConstraints {
+    fn default() -> Self { Self::new() }
+}
+
+/// Physically contiguous memory that is shared with a device.
+///
+/// The buffer is uncached in the direct map, so writes are visible to the
+/// device without flushing caches.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    physical: PhysicalAddress,
+    size: usize,
+}
+
+impl DmaBuffer {
+    /// The physical address of the buffer, to hand to the device.
+    pub fn physical(&self) -> PhysicalAddress { self.physical }
+
+    /// The virtual address of the buffer, to access it from the kernel.
+    pub fn virt(&self) -> VirtualAddress { self.physical.translate() }
+
+    /// The size of the buffer in bytes, which is rounded up to a whole
+    /// block.
+    pub fn size(&self) -> usize { self.size }
+
+    pub fn as_non_null<T>(&self) -> NonNull<T> {
+        self.virt().as_non_null()
+    }
+}
+
+/// Allocate a zeroed DMA buffer of at least `size` bytes.
+///
+/// The buffer is a buddy block, which is naturally aligned to its size,
+/// so the block is grown until it satisfies the alignment, and must not
+/// be larger than the boundary.
+pub fn alloc(
+    size: usize,
+    constraints: DmaConstraints,
+) -> Result<DmaBuffer, DmaError> {
+    let pages = size
+        .max(constraints.alignment.as_usize())
+        .div_ceil(REGULAR_PAGE_SIZE)
+        .next_power_of_two();
+
+    let order = pages.trailing_zeros() as usize;
+    if order > BuddyOrder::MAX as usize {
+        return Err(DmaError::TooLarge(size));
+    }
+
+    let block_size = pages * REGULAR_PAGE_SIZE;
+    if let Some(boundary) = constraints.boundary
+        && block_size > boundary.as_usize()
+    {
+        return Err(DmaError::CrossesBoundary {
+            size,
+            boundary: boundary.as_usize(),
+        });
+    }
+
+    let block = buddy().try_alloc_pages_below(
+        BuddyOrder::from(order as u8),
+        PhysicalAddress::from(constraints.limit),
+    )?;
+
+    let buffer = DmaBuffer {
+        physical: buddy().address_of(block),
+        size: block_size,
+    };
+
+    if let Err(e) =
+        protect(&buffer, PageEntryFlags::regular_io_page_flags())
+    {
+        let _ = protect(&buffer, PageEntryFlags::regular_page_flags());
+        unsafe { buddy().free_pages(block) };
+        return Err(e.into());
+    }
+
+    unsafe { buffer.as_non_null::<u8>().write_bytes(0, block_size) };
+
+    Ok(buffer)
+}
+
+/// Allocate a zeroed DMA buffer that holds a `T`.
+pub fn alloc_object<T: DmaGeneric>() -> Result<DmaBuffer, DmaError> {
+    let constraints = T::CONSTRAINTS;
+
+    alloc(
+        size_of::<T>(),
+        constraints.alignment(
+            constraints.alignment.as_usize().max(align_of::<T>()),
+        ),
+    )
+}
+
+/// Return a DMA buffer to the buddy allocator.
+///
+/// # Safety
+/// The device must not access the buffer anymore.
+pub unsafe fn free(buffer: DmaBuffer) {
+    let block = buddy()
+        .block_at(buffer.physical)
+        .expect("DMA buffer is not on the page map");
+
+    protect(&buffer, PageEntryFlags::regular_page_flags())
+        .expect("DMA buffer is not in the direct map");
+
+    unsafe { buddy().free_pages(block) };
+}
+
+/// Set the flags of the pages of the buffer in the direct map.
+///
+/// The direct map uses large pages, which are split down to regular
+/// pages first, so the flags of the memory around the buffer are kept.
+fn protect(
+    buffer: &DmaBuffer,
+    flags: PageEntryFlags,
+) -> Result<(), MappingError> {
+    for offset in (0..buffer.size).step_by(REGULAR_PAGE_SIZE) {
+        let virt = VirtualAddress::from(buffer.virt().as_usize() + offset);
+
+        while virt.split()? != PageSize::Regular {}
+
+        virt.protect(flags)?;
+    }
+    Ok(())
+}
@@ -1,24 +1,50 @@
+#![no_std]
 #![feature(specialization)]
+#![feature(allocator_api)]
+#![feature(ptr_alignment_type)]
+#![allow(incomplete_features)]
 
 pub mod cache;
+#[cfg(feature = "memory-debug")]
+pub mod debug;
 pub mod descriptor;
+pub mod dma;
 pub mod macros;
 pub mod traits;
 pub mod unassigned;
+pub mod vmalloc;
 
 use ::macros::generate_generics;
 
+use buddy::BuddyAllocator;
+use common::late_init::LateInit;
+use page::Page;
+#[cfg(not(feature = "host"))]
+use page::arena::PageMap;
+#[cfg(feature = "host")]
+use page::host::HostPageMap as PageMap;
+use x86::structures::paging::PageTable;
+
 use crate::{
-    cache::SlabCache,
-    define_slab_system,
+    cache::{SlabCache, SlabError},
     descriptor::SlabDescriptor,
     traits::{Generic, Slab, SlabPosition},
+    unassigned::UnassignSlab,
 };
 use core::{
-    alloc::{AllocError, Allocator},
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
     ptr::NonNull,
 };
 
+unsafe extern "Rust" {
+    static BUDDY_ALLOCATOR: LateInit<BuddyAllocator<PageMap, Page>>;
+}
+
+/// Returns the buddy allocator that the slabs take their pages from.
+fn buddy() -> &'static BuddyAllocator<PageMap, Page> {
+    unsafe { &BUDDY_ALLOCATOR }
+}
+
 generate_generics!(
     8, 16, 32, 64, 96, 128, 192, 256, 512, 1024, 2048, 4096, 8192
 );
@@ -38,24 +64,103 @@ define_slab_system!(
     Generic2048,
     Generic4096,
     Generic8192,
+    PageTable {
+        construct: construct_page_table,
+    },
 );
 
+/// Page tables are handed out empty, so they can be linked right away.
+fn construct_page_table(table: NonNull<PageTable>) {
+    unsafe { table.write(PageTable::empty()) }
+}
+
+/// Allocate an empty page table from its cache.
+///
+/// This is the allocator of [`libk::alloc::alloc_table`] once the slab
+/// allocator is initialized.
+pub fn alloc_page_table() -> Option<NonNull<PageTable>> {
+    SLAB_ALLOCATOR.kmalloc::<PageTable>().ok()
+}
+
+/// Clear a page table and return it to its cache.
+///
+/// # Safety
+/// The table must be allocated with [`alloc_page_table`], and not used
+/// anymore.
+pub unsafe fn free_page_table(table: NonNull<PageTable>) {
+    // The tables of a dropped address space still map their pages, and
+    // the cache keeps its tables empty.
+    unsafe {
+        table.write(PageTable::empty());
+        SLAB_ALLOCATOR.kfree(table);
+    }
+}
+
+pub static SLAB_ALLOCATOR: SlabAllocator = SlabAllocator::new();
+
 impl SlabAllocator {
-    pub fn slab_of<T: Slab>(&self) -> NonNull<SlabCache<T>> {
-        self.slabs[T::SLAB_POSITION].assign::<T>()
+    #[track_caller]
+    pub fn kmalloc<T: Slab>(&self) -> Result<NonNull<T>, SlabError> {
+        let cache = self.slabs[T::SLAB_POSITION].lock();
+        unsafe { cache.assign::<T>().as_mut().alloc() }
     }
 
-    pub fn kmalloc<T: Slab>(&self) -> NonNull<T> {
-        let mut slab = self.slab_of::<T>();
-        unsafe { slab.as_mut().alloc() }
+    /// Return the object to its cache.
+    ///
+    /// # Safety
+    /// The object must be allocated with [`SlabAllocator::kmalloc`], and
+    /// meet the requirements of [`SlabCache::dealloc`].
+    #[track_caller]
+    pub unsafe fn kfree<T: Slab>(&self, ptr: NonNull<T>) {
+        let cache = self.slabs[T::SLAB_POSITION].lock();
+        unsafe { cache.assign::<T>().as_mut().dealloc(ptr) };
     }
 
-    pub fn kfree<T: Slab>(&self, ptr: NonNull<T>) {
-        let page = unsafe { Page::<T>::from_virt(ptr.into()).as_ref() };
+    /// Set the watermarks of the cache of the given type.
+    ///
+    /// See [`SlabCache::set_watermarks`].
+    pub fn set_watermarks<T: Slab>(&self, low: usize, high: usize) {
+        let cache = self.slabs[T::SLAB_POSITION].lock();
+        unsafe { cache.assign::<T>().as_mut().set_watermarks(low, high) };
+    }
+
+    /// Release all of the free slabs in all caches back to the buddy
+    /// allocator.
+    ///
+    /// This is meant to be called when the buddy allocator is under memory
+    /// pressure, which can happen while a cache is growing. Caches that
+    /// are currently locked are skipped.
+    ///
+    /// Returns the amount of pages that were released.
+    pub fn shrink(&self) -> usize {
+        let descriptors =
+            <SlabDescriptor<()> as SlabPosition>::SLAB_POSITION;
+
+        // Releasing a slab frees its descriptor, so nothing can be
+        // released while the descriptors cache is locked.
+        if self.slabs[descriptors].try_lock().is_none() {
+            return 0;
+        }
 
-        let descriptor = unsafe { page.meta.slab.freelist };
+        let mut released = 0;
 
-        unsafe { descriptor.assign::<T>().as_mut().dealloc(ptr) };
+        for (i, slab) in self.slabs.iter().enumerate() {
+            if i == descriptors {
+                continue;
+            }
+            if let Some(mut cache) = slab.try_lock() {
+                released += cache.shrink();
+            }
+        }
+
+        // Shrink the descriptors cache last, because shrinking the other
+        // caches frees descriptors. It may have been locked since it was
+        // checked, and then it is skipped like the other caches.
+        if let Some(mut cache) = self.slabs[descriptors].try_lock() {
+            released += cache.shrink();
+        }
+
+        released
     }
 }
 
@@ -75,107 +180,160 @@ pub impl<T: Generic> NonNull<T> {
 }
 
 unsafe impl Allocator for SlabAllocator {
+    #[track_caller]
     fn allocate(
         &self,
         layout: core::alloc::Layout,
     ) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
-        if layout.size() < layout.align() {
+        // Objects are placed one after the other on a page aligned slab,
+        // so an object of a padded size is aligned to the layout. A type
+        // of a cache is aligned to a page at most, and under
+        // `memory-debug` the redzone before an object only keeps the
+        // alignment of the type, so larger alignments cannot be served.
+        if layout.align() > common::constants::REGULAR_PAGE_SIZE {
             return Err(AllocError);
         }
 
-        match layout.size() {
+        let allocation = match layout.pad_to_align().size() {
             Generic8::START..=Generic8::END => {
-                Ok(self.kmalloc::<Generic8>().into_u8())
+                self.kmalloc::<Generic8>().map(|p| p.into_u8())
             }
             Generic16::START..=Generic16::END => {
-                Ok(self.kmalloc::<Generic16>().into_u8())
+                self.kmalloc::<Generic16>().map(|p| p.into_u8())
             }
             Generic32::START..=Generic32::END => {
-                Ok(self.kmalloc::<Generic32>().into_u8())
+                self.kmalloc::<Generic32>().map(|p| p.into_u8())
             }
             Generic64::START..=Generic64::END => {
-                Ok(self.kmalloc::<Generic64>().into_u8())
+                self.kmalloc::<Generic64>().map(|p| p.into_u8())
             }
             Generic96::START..=Generic96::END => {
-                Ok(self.kmalloc::<Generic96>().into_u8())
+                self.kmalloc::<Generic96>().map(|p| p.into_u8())
             }
             Generic128::START..=Generic128::END => {
-                Ok(self.kmalloc::<Generic128>().into_u8())
+                self.kmalloc::<Generic128>().map(|p| p.into_u8())
             }
             Generic192::START..=Generic192::END => {
-                Ok(self.kmalloc::<Generic192>().into_u8())
+                self.kmalloc::<Generic192>().map(|p| p.into_u8())
             }
             Generic256::START..=Generic256::END => {
-                Ok(self.kmalloc::<Generic256>().into_u8())
+                self.kmalloc::<Generic256>().map(|p| p.into_u8())
             }
             Generic512::START..=Generic512::END => {
-                Ok(self.kmalloc::<Generic512>().into_u8())
+                self.kmalloc::<Generic512>().map(|p| p.into_u8())
             }
             Generic1024::START..=Generic1024::END => {
-                Ok(self.kmalloc::<Generic1024>().into_u8())
+                self.kmalloc::<Generic1024>().map(|p| p.into_u8())
             }
             Generic2048::START..=Generic2048::END => {
-                Ok(self.kmalloc::<Generic2048>().into_u8())
+                self.kmalloc::<Generic2048>().map(|p| p.into_u8())
             }
             Generic4096::START..=Generic4096::END => {
-                Ok(self.kmalloc::<Generic4096>().into_u8())
+                self.kmalloc::<Generic4096>().map(|p| p.into_u8())
             }
             Generic8192::START..=Generic8192::END => {
-                Ok(self.kmalloc::<Generic8192>().into_u8())
+                self.kmalloc::<Generic8192>().map(|p| p.into_u8())
             }
-            _ => Err(AllocError),
-        }
+            _ => return Err(AllocError),
+        };
+
+        allocation.map_err(|_| AllocError)
     }
 
+    #[track_caller]
     unsafe fn deallocate(
         &self,
         ptr: core::ptr::NonNull<u8>,
         layout: core::alloc::Layout,
     ) {
-        match layout.size() {
-            Generic8::START..=Generic8::END => {
+        match layout.pad_to_align().size() {
+            Generic8::START..=Generic8::END => unsafe {
                 self.kfree::<Generic8>(NonNull::from_u8(ptr))
-            }
-            Generic16::START..=Generic16::END => {
+            },
+            Generic16::START..=Generic16::END => unsafe {
                 self.kfree::<Generic16>(NonNull::from_u8(ptr))
-            }
-            Generic32::START..=Generic32::END => {
+            },
+            Generic32::START..=Generic32::END => unsafe {
                 self.kfree::<Generic32>(NonNull::from_u8(ptr))
-            }
-            Generic64::START..=Generic64::END => {
+            },
+            Generic64::START..=Generic64::END => unsafe {
                 self.kfree::<Generic64>(NonNull::from_u8(ptr))
-            }
-            Generic96::START..=Generic96::END => {
+            },
+            Generic96::START..=Generic96::END => unsafe {
                 self.kfree::<Generic96>(NonNull::from_u8(ptr))
-            }
-            Generic128::START..=Generic128::END => {
+            },
+            Generic128::START..=Generic128::END => unsafe {
                 self.kfree::<Generic128>(NonNull::from_u8(ptr))
-            }
-            Generic192::START..=Generic192::END => {
+            },
+            Generic192::START..=Generic192::END => unsafe {
                 self.kfree::<Generic192>(NonNull::from_u8(ptr))
-            }
-            Generic256::START..=Generic256::END => {
+            },
+            Generic256::START..=Generic256::END => unsafe {
                 self.kfree::<Generic256>(NonNull::from_u8(ptr))
-            }
-            Generic512::START..=Generic512::END => {
+            },
+            Generic512::START..=Generic512::END => unsafe {
                 self.kfree::<Generic512>(NonNull::from_u8(ptr))
-            }
-            Generic1024::START..=Generic1024::END => {
+            },
+            Generic1024::START..=Generic1024::END => unsafe {
                 self.kfree::<Generic1024>(NonNull::from_u8(ptr))
-            }
-            Generic2048::START..=Generic2048::END => {
+            },
+            Generic2048::START..=Generic2048::END => unsafe {
                 self.kfree::<Generic2048>(NonNull::from_u8(ptr))
-            }
-            Generic4096::START..=Generic4096::END => {
+            },
+            Generic4096::START..=Generic4096::END => unsafe {
                 self.kfree::<Generic4096>(NonNull::from_u8(ptr))
-            }
-            Generic8192::START..=Generic8192::END => {
+            },
+            Generic8192::START..=Generic8192::END => unsafe {
                 self.kfree::<Generic8192>(NonNull::from_u8(ptr))
-            }
+            },
             _ => unreachable!(),
         }
     }
 }
+
+/// Small allocations are served from the generic caches, and everything
+/// that doesn't fit in a generic cache is mapped from single pages by
+/// [`vmalloc`]. Allocations of any size that must be aligned to more than
+/// a page go directly to the buddy allocator.
+unsafe impl GlobalAlloc for SlabAllocator {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > common::constants::REGULAR_PAGE_SIZE {
+            // The buddy allocator reports its own failures.
+            return unsafe { buddy().alloc(layout) };
+        }
+
+        let ptr = if layout.pad_to_align().size() > Generic8192::END {
+            vmalloc::alloc(layout.size())
+                .map_or(core::ptr::null_mut(), |p| p.as_ptr())
+        } else {
+            self.allocate(layout)
+                .map_or(core::ptr::null_mut(), |p| p.cast::<u8>().as_ptr())
+        };
+
+        if ptr.is_null() {
+            buddy().report_out_of_memory(layout);
+        }
+
+        ptr
+    }
+
+    #[track_caller]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.align() > common::constants::REGULAR_PAGE_SIZE {
+            return unsafe { buddy().dealloc(ptr, layout) };
+        }
+
+        if layout.pad_to_align().size() > Generic8192::END {
+            return unsafe {
+                vmalloc::free(NonNull::new_unchecked(ptr), layout.size())
+            };
+        }
+
+        unsafe { self.deallocate(NonNull::new_unchecked(ptr), layout) }
+    }
+}
+
 unsafe impl<T: Slab> Send for SlabDescriptor<T> {}
 unsafe impl<T: Slab> Sync for SlabDescriptor<T> {}
 unsafe impl<T: Slab> Send for SlabCache<T> {}
@@ -25,18 +25,46 @@ macro_rules! register_slabs {
     (@step $idx:expr; ) => {};
 }
 
+/// Define the slab allocator with a cache for each of the given types.
+///
+/// A type can be followed by hooks of [`SlabCacheConstructor`] that are
+/// called once on each of its objects, for example:
+///
+/// ```ignore
+/// define_slab_system!(
+///     Generic8,
+///     PageTable { construct: construct_page_table },
+/// );
+/// ```
+///
+/// [`SlabCacheConstructor`]: crate::traits::SlabCacheConstructor
 #[macro_export]
 macro_rules! define_slab_system {
-    ($($t:ty),* $(,)?) => {
+    ($($t:ty $({ $($hook:ident: $f:expr),* $(,)? })?),* $(,)?) => {
         use common::constants::REGULAR_PAGE_SIZE;
         use $crate::traits::SlabCacheConstructor;
 
         $crate::register_slabs!($($t),*);
 
+        $($(
+            impl SlabCacheConstructor<$t> for SlabCache<$t> {
+                const CONSTRUCTED: bool = true;
+
+                $(
+                    fn $hook(object: NonNull<$t>) { ($f)(object) }
+                )*
+            }
+        )?)*
+
         const COUNT: usize = [$(stringify!($t)),*].len();
 
         pub struct SlabAllocator {
-            slabs: [common::late_init::LateInit<SlabCache<()>>; COUNT]
+            slabs: [
+                sync::mutex::SpinMutex<
+                    common::late_init::LateInit<SlabCache<()>>
+                >;
+                COUNT
+            ]
         }
 
         impl SlabAllocator {
@@ -45,21 +73,29 @@ macro_rules! define_slab_system {
                     slabs: [
                         $({
                             let _ = stringify!($t);
-                            common::late_init::LateInit::uninit()
+                            sync::mutex::SpinMutex::new(
+                                common::late_init::LateInit::uninit()
+                            )
                         }),*
                     ]
                 }
             }
 
-            pub fn init(&'static mut self) {
+            pub fn init(&self) {
                 $(
                     let index = <$t>::SLAB_POSITION;
 
-                    let initialized = SlabCache::<$t>::new(size_of::<$t>().div_ceil(REGULAR_PAGE_SIZE));
+                    let pages = size_of::<
+                        $crate::descriptor::PreallocatedObject<$t>
+                    >().div_ceil(REGULAR_PAGE_SIZE);
+
+                    let initialized = SlabCache::<$t>::new(
+                        pages.next_power_of_two().trailing_zeros() as usize
+                    );
 
                     let unassigned = NonNull::from_ref(&initialized).as_unassigned();
 
-                    self.slabs[index].write(unsafe { unassigned.as_ref().clone() });
+                    self.slabs[index].lock().init(unsafe { unassigned.as_ref().clone() });
                 )*
             }
         }
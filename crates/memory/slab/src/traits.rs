@@ -1,6 +1,10 @@
+use core::ptr::NonNull;
+
 use common::enums::PageSize;
 use x86::structures::paging::PageEntryFlags;
 
+use crate::dma::DmaConstraints;
+
 /// Get the position on the slab array, for a slab of the given type.
 ///
 /// Shouldn't implement this trait manually; it is implemented
@@ -30,12 +34,33 @@ impl<T: SlabPosition> SlabFlags for T {
 }
 
 impl SlabFlags for () {
-    const PFLAGS: PageEntryFlags = PageEntryFlags::default();
+    const PFLAGS: PageEntryFlags = PageEntryFlags::new();
     const PSIZE: PageSize = PageSize::Regular;
 }
 
-pub trait SlabCacheConstructor {
+/// Construction of a cache, and of the objects that are handed out by it.
+///
+/// The object hooks can be specialized per type with the
+/// `define_slab_system` macro.
+pub trait SlabCacheConstructor<T: Slab> {
+    /// True if the cache has object hooks, so its free objects keep their
+    /// state, and are not poisoned by `memory-debug`.
+    const CONSTRUCTED: bool;
+
     fn new(buddy_order: usize) -> Self;
+
+    /// Called once on every object of a new slab, before any of them is
+    /// handed out.
+    ///
+    /// The object is not initialized when this is called. An object must
+    /// be freed in the state that this puts it in, except for its first
+    /// bytes, which hold the index of the next free object while it is
+    /// free, and are zeroed before it is handed out again.
+    fn construct(object: NonNull<T>);
+
+    /// Called once on every object of a slab, right before the pages of
+    /// the slab are returned to the buddy allocator.
+    fn destruct(object: NonNull<T>);
 }
 
 pub trait Generic {
@@ -45,9 +70,11 @@ pub trait Generic {
     fn size(&self) -> usize;
 }
 
-pub trait DmaGeneric {
-    const START: usize;
-    const END: usize;
-
-    fn size(&self) -> usize;
+/// A structure that is shared with a device through DMA.
+///
+/// Allocate it with [`dma::alloc_object`](crate::dma::alloc_object).
+pub trait DmaGeneric: Sized {
+    /// Constraints on the physical memory of the structure, the
+    /// alignment of the type is added to them.
+    const CONSTRAINTS: DmaConstraints = DmaConstraints::new();
 }
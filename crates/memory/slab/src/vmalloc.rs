@@ -0,0 +1,202 @@
+use core::{ptr::NonNull, slice};
+
+use buddy::meta::BuddyError;
+use common::{
+    address_types::{Address, VirtualAddress},
+    constants::{REGULAR_PAGE_SIZE, VMALLOC_OFFSET, VMALLOC_SIZE},
+    enums::{BuddyOrder, PageSize},
+    error::MappingError,
+    late_init::LateInit,
+};
+use libk::alloc::VirtualAddressMapping;
+use sync::mutex::SpinMutex;
+use thiserror::Error;
+use x86::structures::paging::PageEntryFlags;
+
+use crate::buddy;
+
+const VMALLOC_PAGES: usize = VMALLOC_SIZE / REGULAR_PAGE_SIZE;
+
+/// The number of words in the map of the reserved pages.
+const MAP_WORDS: usize = VMALLOC_PAGES / u64::BITS as usize;
+
+static AREAS: LateInit<SpinMutex<VirtualAreas>> = LateInit::uninit();
+
+#[derive(Debug, Error)]
+pub enum VmallocError {
+    #[error("There is no free virtual area of {0} pages")]
+    NoVirtualSpace(usize),
+    #[error("Could not allocate a page for the area: {0}")]
+    OutOfMemory(#[from] BuddyError),
+    #[error("Could not map the area: {0}")]
+    Mapping(#[from] MappingError),
+}
+
+/// The pages of the vmalloc window that are reserved, one bit per page.
+struct VirtualAreas {
+    map: &'static mut [u64],
+}
+
+impl VirtualAreas {
+    fn is_reserved(&self, page: usize) -> bool {
+        self.map[page / u64::BITS as usize] & (1 << (page % 64)) != 0
+    }
+
+    fn set(&mut self, start: usize, pages: usize, reserved: bool) {
+        for page in start..start + pages {
+            let word = &mut self.map[page / u64::BITS as usize];
+            if reserved {
+                *word |= 1 << (page % 64);
+            } else {
+                *word &= !(1 << (page % 64));
+            }
+        }
+    }
+
+    /// Reserve the first run of `pages` free pages, and return the index
+    /// of its first page.
+    fn reserve(&mut self, pages: usize) -> Option<usize> {
+        let mut run = 0;
+        let mut page = 0;
+
+        while page < VMALLOC_PAGES {
+            if page.is_multiple_of(64)
+                && self.map[page / u64::BITS as usize] == u64::MAX
+            {
+                run = 0;
+                page += u64::BITS as usize;
+                continue;
+            }
+
+            if self.is_reserved(page) {
+                run = 0;
+            } else {
+                run += 1;
+            }
+
+            page += 1;
+
+            if run == pages {
+                let start = page - pages;
+                self.set(start, pages, true);
+                return Some(start);
+            }
+        }
+
+        None
+    }
+}
+
+/// Initialize the vmalloc window.
+///
+/// The map of the window is taken from the buddy allocator directly,
+/// because large heap allocations are served from the window itself.
+pub fn init() {
+    let size = MAP_WORDS * size_of::<u64>();
+    let order = size
+        .div_ceil(REGULAR_PAGE_SIZE)
+        .next_power_of_two()
+        .trailing_zeros() as u8;
+
+    let block = buddy()
+        .try_alloc_pages(BuddyOrder::from(order))
+        .expect("Could not allocate the vmalloc map");
+    let ptr = buddy().pointer_of(block);
+
+    let map = unsafe {
+        ptr.write_bytes(0, size);
+        slice::from_raw_parts_mut(ptr.cast::<u64>().as_ptr(), MAP_WORDS)
+    };
+
+    // The window fits in a single PML4 entry. Its table is allocated now,
+    // so address spaces that are created later share it.
+    VirtualAddress::from(VMALLOC_OFFSET)
+        .walk_map(PageSize::Huge)
+        .expect("Could not allocate the vmalloc tables");
+
+    AREAS.init(SpinMutex::new(VirtualAreas { map }));
+}
+
+/// Allocate `size` bytes of virtually contiguous memory.
+///
+/// Every page is a separate page from the buddy allocator, so this never
+/// needs a high order block. The area is followed by an unmapped guard
+/// page, so an overflow faults instead of running into the next area.
+pub fn alloc(size: usize) -> Result<NonNull<u8>, VmallocError> {
+    let pages = size.div_ceil(REGULAR_PAGE_SIZE);
+
+    let start = AREAS
+        .lock()
+        .reserve(pages + 1)
+        .ok_or(VmallocError::NoVirtualSpace(pages))?;
+
+    let address =
+        VirtualAddress::from(VMALLOC_OFFSET + start * REGULAR_PAGE_SIZE);
+
+    for page in 0..pages {
+        if let Err(e) = map_page(page_of(address, page)) {
+            unsafe { release(address, page, pages) };
+            return Err(e);
+        }
+    }
+
+    Ok(address.as_non_null())
+}
+
+/// Free an area that was allocated with [`alloc`].
+///
+/// # Safety
+/// The area must be allocated with [`alloc`] with the same size, and not
+/// used anymore.
+pub unsafe fn free(ptr: NonNull<u8>, size: usize) {
+    let pages = size.div_ceil(REGULAR_PAGE_SIZE);
+    unsafe {
+        release(VirtualAddress::from(ptr.addr().get()), pages, pages)
+    };
+}
+
+/// Unmap the first `mapped` pages of an area of `pages` pages, free the
+/// ones that are not shared anymore, and release the area with its guard
+/// page.
+unsafe fn release(address: VirtualAddress, mapped: usize, pages: usize) {
+    for page in 0..mapped {
+        let (physical, _) = page_of(address, page)
+            .unmap()
+            .expect("A vmalloc page is not mapped");
+
+        let block = buddy()
+            .block_at(physical)
+            .expect("A vmalloc page is not on the page map");
+
+        // The page is still mapped copy-on-write somewhere else.
+        let mapping = unsafe { &block.as_ref().mapping };
+        if !mapping.try_unshare() {
+            mapping.release();
+            continue;
+        }
+
+        unsafe { buddy().free_pages(block) };
+    }
+
+    let start = (address.as_usize() - VMALLOC_OFFSET) / REGULAR_PAGE_SIZE;
+    AREAS.lock().set(start, pages + 1, false);
+}
+
+fn page_of(address: VirtualAddress, page: usize) -> VirtualAddress {
+    VirtualAddress::from(address.as_usize() + page * REGULAR_PAGE_SIZE)
+}
+
+fn map_page(page: VirtualAddress) -> Result<(), VmallocError> {
+    let block = buddy().try_alloc_pages(BuddyOrder::Order0)?;
+
+    if let Err(e) = page.map(
+        buddy().address_of(block),
+        Some(PageEntryFlags::regular_page_flags()),
+        PageSize::Regular,
+    ) {
+        unsafe { buddy().free_pages(block) };
+        return Err(e.into());
+    }
+
+    Ok(())
+}
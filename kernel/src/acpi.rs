@@ -0,0 +1,86 @@
+use core::mem::size_of;
+
+use common::{
+    address_types::{Address, PhysicalAddress},
+    error::AcpiError,
+    late_init::LateInit,
+};
+use x86::{
+    acpi::{AcpiTable, RootTable, Rsdp, SdtHeader},
+    structures::paging::PageEntryFlags,
+};
+
+use crate::direct_map;
+
+/// The RSDT or the XSDT, which lists the other tables.
+static ROOT_TABLE: LateInit<RootTable> = LateInit::uninit();
+
+/// Find the root table of the ACPI tables, so tables can be found with
+/// [`find_table`].
+pub fn init() -> Result<(), AcpiError> {
+    let rsdp = Rsdp::find().ok_or(AcpiError::RsdpNotFound)?;
+    let extended = rsdp.is_extended();
+
+    let header = map_table(rsdp.root_table_address())?;
+    if !unsafe { header.is_valid() } {
+        return Err(AcpiError::InvalidChecksum(if extended {
+            "XSDT"
+        } else {
+            "RSDT"
+        }));
+    }
+
+    ROOT_TABLE.init(unsafe { RootTable::new(header, extended) });
+    Ok(())
+}
+
+/// Returns the table of type `T`, which is mapped as a whole.
+pub fn find_table<T: AcpiTable>() -> Result<&'static T, AcpiError> {
+    for address in ROOT_TABLE.entries() {
+        let header = map_header(address)?;
+        if header.signature != *T::SIGNATURE.as_bytes() {
+            continue;
+        }
+
+        let header = map_table(address)?;
+        if !unsafe { header.is_valid() } {
+            return Err(AcpiError::InvalidChecksum(T::SIGNATURE));
+        }
+
+        return Ok(unsafe {
+            address.translate().as_non_null::<T>().as_ref()
+        });
+    }
+
+    Err(AcpiError::TableNotFound(T::SIGNATURE))
+}
+
+/// Map the header of the table at `address`.
+///
+/// The tables may be in reserved memory, which is not in the direct map.
+fn map_header(
+    address: PhysicalAddress,
+) -> Result<&'static SdtHeader, AcpiError> {
+    direct_map::map_device_memory(
+        address,
+        size_of::<SdtHeader>(),
+        PageEntryFlags::regular_page_flags(),
+    )?;
+
+    Ok(unsafe { address.translate().as_non_null::<SdtHeader>().as_ref() })
+}
+
+/// Map the whole table at `address`.
+fn map_table(
+    address: PhysicalAddress,
+) -> Result<&'static SdtHeader, AcpiError> {
+    let header = map_header(address)?;
+
+    direct_map::map_device_memory(
+        address,
+        header.length as usize,
+        PageEntryFlags::regular_page_flags(),
+    )?;
+
+    Ok(header)
+}
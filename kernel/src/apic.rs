@@ -0,0 +1,62 @@
+use common::{
+    constants::REGULAR_PAGE_SIZE,
+    enums::{IsaIrq, interrupts::Interrupt},
+    error::{ApicError, MappingError},
+};
+use x86::{
+    acpi::{Madt, MadtEntry},
+    apic::{InterruptSource, LocalApic},
+    structures::paging::PageEntryFlags,
+};
+
+use crate::{IO_APICS, LOCAL_APIC, direct_map};
+
+/// The size of the registers of an I/O APIC.
+const IO_APIC_REGISTERS_SIZE: usize = 0x20;
+
+/// Map the registers of the local APIC, and of the I/O APICs that the
+/// MADT describes, uncached in the direct map.
+pub fn map_registers(madt: &Madt) -> Result<(), MappingError> {
+    direct_map::map_device_memory(
+        LocalApic::physical_base(),
+        REGULAR_PAGE_SIZE,
+        PageEntryFlags::regular_io_page_flags(),
+    )?;
+
+    for entry in madt.entries() {
+        if let MadtEntry::IoApic { address, .. } = entry {
+            direct_map::map_device_memory(
+                address,
+                IO_APIC_REGISTERS_SIZE,
+                PageEntryFlags::regular_io_page_flags(),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deliver the interrupt to `vector` on this cpu.
+///
+/// # Safety
+/// The vector must have a handler, which signals the end of the interrupt
+/// to [`LOCAL_APIC`].
+pub unsafe fn route(
+    source: InterruptSource,
+    vector: Interrupt,
+) -> Result<(), ApicError> {
+    unsafe { IO_APICS.lock().route(source, vector, &LOCAL_APIC) }
+}
+
+/// Deliver the legacy ISA IRQ to `vector` on this cpu, through the global
+/// system interrupt that it is connected to.
+///
+/// # Safety
+/// Same as [`route`].
+pub unsafe fn route_isa_irq(
+    irq: IsaIrq,
+    vector: Interrupt,
+) -> Result<(), ApicError> {
+    let source = IO_APICS.lock().isa_irq(irq);
+    unsafe { route(source, vector) }
+}
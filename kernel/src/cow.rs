@@ -0,0 +1,165 @@
+use core::ptr::NonNull;
+
+use common::{
+    address_types::{Address, PhysicalAddress, VirtualAddress},
+    constants::{REGULAR_PAGE_ALIGNMENT, REGULAR_PAGE_SIZE},
+    enums::{BuddyOrder, PageSize},
+    error::{CowError, MappingError},
+};
+use libk::alloc::VirtualAddressMapping;
+use page::Page;
+use slab::vmalloc;
+use x86::{instructions::tlb, structures::paging::PageTableEntry};
+
+use crate::BUDDY_ALLOCATOR;
+
+/// Share the `length` bytes at `source` with `destination`, copy-on-write.
+///
+/// Both sides are mapped read-only to the same pages, and the first write
+/// to a page from either side copies it. Pages that are read-only to
+/// begin with are shared as they are.
+///
+/// If a page cannot be shared, the pages before it stay shared.
+pub fn share_cow(
+    source: VirtualAddress,
+    destination: VirtualAddress,
+    length: usize,
+) -> Result<(), CowError> {
+    if !source.is_aligned(REGULAR_PAGE_ALIGNMENT)
+        || !destination.is_aligned(REGULAR_PAGE_ALIGNMENT)
+        || !length.is_multiple_of(REGULAR_PAGE_SIZE)
+    {
+        return Err(MappingError::Unaligned.into());
+    }
+
+    for offset in (0..length).step_by(REGULAR_PAGE_SIZE) {
+        let source = VirtualAddress::from(source.as_usize() + offset);
+        let destination =
+            VirtualAddress::from(destination.as_usize() + offset);
+
+        let mut entry = regular_entry(source)?;
+        let (frame, flags) = unsafe {
+            (entry.as_ref().get_address(), entry.as_ref().get_flags())
+        };
+
+        // Read-only pages are counted too, so the page is only freed
+        // once the last side unmaps it.
+        if !flags.is_writable() && !flags.is_copy_on_write() {
+            destination.map(frame, Some(flags), PageSize::Regular)?;
+            unsafe { page_of(frame).as_ref() }.mapping.share();
+            continue;
+        }
+
+        let shared = flags.writable(false).copy_on_write(true);
+        destination.map(frame, Some(shared), PageSize::Regular)?;
+
+        unsafe { page_of(frame).as_ref() }.mapping.share();
+
+        unsafe { entry.as_mut().set_flags(shared) };
+        tlb::flash_address(source);
+    }
+
+    Ok(())
+}
+
+/// Resolve a write to the copy-on-write page that maps `address`.
+///
+/// The last mapping of a shared page takes it over, and the others get a
+/// copy of it.
+pub fn copy_on_write(address: VirtualAddress) -> Result<(), CowError> {
+    let page = address.align_down(REGULAR_PAGE_ALIGNMENT);
+
+    let mut entry = regular_entry(page)?;
+    let (frame, flags) = unsafe {
+        (entry.as_ref().get_address(), entry.as_ref().get_flags())
+    };
+
+    if !flags.is_copy_on_write() {
+        return Err(CowError::NotCopyOnWrite);
+    }
+
+    let writable = flags.writable(true).copy_on_write(false);
+    let shared = page_of(frame);
+
+    if unsafe { shared.as_ref() }.mapping.try_unshare() {
+        unsafe { entry.as_mut().set_flags(writable) };
+        tlb::flash_address(page);
+        return Ok(());
+    }
+
+    let block = BUDDY_ALLOCATOR
+        .try_alloc_pages(BuddyOrder::Order0)
+        .map_err(|_| CowError::OutOfMemory)?;
+
+    unsafe {
+        BUDDY_ALLOCATOR.pointer_of(block).copy_from_nonoverlapping(
+            page.as_non_null::<u8>(),
+            REGULAR_PAGE_SIZE,
+        );
+        entry
+            .as_mut()
+            .map_unchecked(BUDDY_ALLOCATOR.address_of(block), writable);
+    }
+    tlb::flash_address(page);
+
+    unsafe { shared.as_ref() }.mapping.release();
+
+    Ok(())
+}
+
+/// Share a page copy-on-write, and return true if a write through each
+/// side leaves the other side unchanged.
+pub fn writes_are_private() -> Result<bool, CowError> {
+    let area = vmalloc::alloc(2 * REGULAR_PAGE_SIZE)
+        .map_err(|_| CowError::OutOfMemory)?;
+
+    let source = VirtualAddress::from(area.addr().get());
+    let destination =
+        VirtualAddress::from(source.as_usize() + REGULAR_PAGE_SIZE);
+
+    // The page is shared into the second page of the area, so its own
+    // page is freed first.
+    let (frame, _) = destination.unmap()?;
+    unsafe { BUDDY_ALLOCATOR.free_pages(page_of(frame)) };
+
+    let source_byte = source.as_non_null::<u8>();
+    let destination_byte = destination.as_non_null::<u8>();
+
+    unsafe { source_byte.write_volatile(1) };
+    share_cow(source, destination, REGULAR_PAGE_SIZE)?;
+
+    let private = unsafe {
+        source_byte.write_volatile(2);
+        let source_is_private = destination_byte.read_volatile() == 1;
+
+        destination_byte.write_volatile(3);
+        let destination_is_private = source_byte.read_volatile() == 2;
+
+        source_is_private && destination_is_private
+    };
+
+    // Both sides own a separate writable page after their writes.
+    unsafe { vmalloc::free(area, 2 * REGULAR_PAGE_SIZE) };
+
+    Ok(private)
+}
+
+/// Returns the entry of the regular page that maps `address`.
+fn regular_entry(
+    address: VirtualAddress,
+) -> Result<NonNull<PageTableEntry>, CowError> {
+    let (page_size, path) = address.mapping_path()?;
+
+    if page_size != PageSize::Regular {
+        return Err(CowError::NotRegular);
+    }
+
+    Ok(path[page_size.mapping_table() as usize]
+        .expect("Mapping path is missing its page"))
+}
+
+fn page_of(frame: PhysicalAddress) -> NonNull<Page> {
+    BUDDY_ALLOCATOR
+        .block_at(frame)
+        .expect("Shared page is not on the page map")
+}
@@ -0,0 +1,164 @@
+use common::{
+    address_types::{Address, VirtualAddress},
+    constants::{REGULAR_PAGE_ALIGNMENT, REGULAR_PAGE_SIZE},
+    enums::{BuddyOrder, PageSize},
+    error::RegionError,
+};
+use libk::alloc::VirtualAddressMapping;
+use sync::mutex::SpinMutex;
+use x86::structures::paging::PageEntryFlags;
+
+use crate::BUDDY_ALLOCATOR;
+
+/// The maximum number of lazily backed regions.
+const MAX_LAZY_REGIONS: usize = 32;
+
+pub static LAZY_REGIONS: SpinMutex<LazyRegions> =
+    SpinMutex::new(LazyRegions::new());
+
+/// A range of virtual memory that is backed by a page from the buddy
+/// allocator on the first access to each of its pages.
+#[derive(Debug, Clone, Copy)]
+pub struct LazyRegion {
+    /// The name of the region, for crash reports.
+    pub name: &'static str,
+    pub start: VirtualAddress,
+    pub length: usize,
+    /// The flags the backing pages are mapped with.
+    pub flags: PageEntryFlags,
+}
+
+impl LazyRegion {
+    pub fn end(&self) -> usize { self.start.as_usize() + self.length }
+
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        (self.start.as_usize()..self.end()).contains(&address.as_usize())
+    }
+
+    fn overlaps(&self, other: &LazyRegion) -> bool {
+        self.start.as_usize() < other.end()
+            && other.start.as_usize() < self.end()
+    }
+}
+
+pub struct LazyRegions {
+    regions: [Option<LazyRegion>; MAX_LAZY_REGIONS],
+}
+
+impl LazyRegions {
+    pub const fn new() -> Self {
+        Self {
+            regions: [None; MAX_LAZY_REGIONS],
+        }
+    }
+
+    /// Register a region, so faults inside it are backed on demand.
+    ///
+    /// The region must be page aligned, and must not overlap another
+    /// region.
+    pub fn register(
+        &mut self,
+        region: LazyRegion,
+    ) -> Result<(), RegionError> {
+        if !region.start.is_aligned(REGULAR_PAGE_ALIGNMENT)
+            || !region.length.is_multiple_of(REGULAR_PAGE_SIZE)
+        {
+            return Err(RegionError::Unaligned);
+        }
+
+        if let Some(other) =
+            self.regions.iter().flatten().find(|r| r.overlaps(&region))
+        {
+            return Err(RegionError::Overlaps(other.name));
+        }
+
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|r| r.is_none())
+            .ok_or(RegionError::Full)?;
+
+        *slot = Some(region);
+        Ok(())
+    }
+
+    /// Remove the region that starts at `start`, and return the pages
+    /// that were backed to the buddy allocator.
+    pub fn unregister(
+        &mut self,
+        start: VirtualAddress,
+    ) -> Option<LazyRegion> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|r| r.is_some_and(|r| r.start == start))?
+            .take()?;
+
+        for page in (region.start.as_usize()..region.end())
+            .step_by(REGULAR_PAGE_SIZE)
+        {
+            let Ok((physical, _)) =
+                unsafe { VirtualAddress::new_unchecked(page) }.unmap()
+            else {
+                continue;
+            };
+
+            let block = BUDDY_ALLOCATOR
+                .block_at(physical)
+                .expect("Backing page is not on the page map");
+
+            // The page is still mapped copy-on-write somewhere else.
+            let mapping = unsafe { &block.as_ref().mapping };
+            if !mapping.try_unshare() {
+                mapping.release();
+                continue;
+            }
+
+            unsafe { BUDDY_ALLOCATOR.free_pages(block) };
+        }
+
+        Some(region)
+    }
+
+    /// Find the region that contains the given address.
+    pub fn find(&self, address: VirtualAddress) -> Option<LazyRegion> {
+        self.regions
+            .iter()
+            .flatten()
+            .find(|r| r.contains(address))
+            .copied()
+    }
+}
+
+/// Back the page that contains `address` with a zeroed page from the
+/// buddy allocator.
+pub fn back_page(
+    region: &LazyRegion,
+    address: VirtualAddress,
+) -> Result<(), RegionError> {
+    let page = address.align_down(REGULAR_PAGE_ALIGNMENT);
+
+    let block = BUDDY_ALLOCATOR
+        .try_alloc_pages(BuddyOrder::Order0)
+        .map_err(|_| RegionError::OutOfMemory)?;
+
+    // The page is zeroed before it is mapped, so the data of its previous
+    // owner is never visible through the region, and a read-only region
+    // is not written through its own mapping.
+    unsafe {
+        BUDDY_ALLOCATOR
+            .pointer_of(block)
+            .write_bytes(0, REGULAR_PAGE_SIZE)
+    };
+
+    let physical = BUDDY_ALLOCATOR.address_of(block);
+
+    if let Err(e) =
+        page.map(physical, Some(region.flags), PageSize::Regular)
+    {
+        unsafe { BUDDY_ALLOCATOR.free_pages(block) };
+        return Err(e.into());
+    }
+
+    Ok(())
+}
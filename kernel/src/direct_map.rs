@@ -0,0 +1,181 @@
+use common::{
+    address_types::{Address, PhysicalAddress},
+    constants::{BIG_PAGE_SIZE, REGULAR_PAGE_SIZE},
+    enums::{MemoryRegionType, PageSize},
+    error::MappingError,
+};
+use libk::alloc::VirtualAddressMapping;
+use x86::{
+    instructions::{cpuid::ExtendedCpuFeatures, tables, tlb},
+    memory_map::{MemoryMap, MemoryRegion},
+    structures::{
+        global_descriptor_table::GlobalDescriptorTableLong,
+        paging::{PageEntryFlags, PageTable},
+    },
+};
+
+/// The size of the physical memory that the bootloader maps at the
+/// start of the direct map.
+const BOOT_MAPPED_SIZE: u64 = BIG_PAGE_SIZE as u64;
+
+/// Map every range of RAM in the memory map at its place in the direct
+/// map of the physical memory.
+///
+/// The bootloader only maps the first 2MiB, so this must run before
+/// anything touches the memory above it. Each range is mapped with the
+/// largest pages that fit, and 1GiB pages are used only if the cpu
+/// supports them. The tables come from the heap, so the bump allocator
+/// hands them out of the low memory that is already mapped.
+pub fn map_physical_memory(mmap: &MemoryMap) -> Result<(), MappingError> {
+    let page_sizes: &[PageSize] =
+        if ExtendedCpuFeatures::default().has_page1gb() {
+            &[PageSize::Huge, PageSize::Big, PageSize::Regular]
+        } else {
+            &[PageSize::Big, PageSize::Regular]
+        };
+
+    let regions = mmap.regions.read();
+    let mut pending: Option<(u64, u64)> = None;
+
+    // Adjacent ranges of RAM are mapped together, so they can share
+    // large pages.
+    for region in regions.iter().filter(|r| is_ram(r)) {
+        match &mut pending {
+            Some((_, end)) if *end == region.base_address => {
+                *end = region.end();
+            }
+            _ => {
+                if let Some((start, end)) =
+                    pending.replace((region.base_address, region.end()))
+                {
+                    map_range(start, end, page_sizes)?;
+                }
+            }
+        }
+    }
+
+    if let Some((start, end)) = pending {
+        map_range(start, end, page_sizes)?;
+    }
+
+    Ok(())
+}
+
+/// Forbid running code from the direct map of every range of RAM,
+/// including the memory that the bootloader mapped.
+///
+/// The kernel only runs from its own mapping, so the direct map needs no
+/// executable pages. Each page that holds RAM is changed whole.
+///
+/// # Safety
+/// The execute disable bit must be enabled in EFER.
+pub unsafe fn disable_execution(
+    mmap: &MemoryMap,
+) -> Result<(), MappingError> {
+    let regions = mmap.regions.read();
+
+    for region in regions.iter().filter(|r| is_ram(r)) {
+        let mut address = (region.base_address as usize)
+            .next_multiple_of(REGULAR_PAGE_SIZE);
+        let end = region.end() as usize & !(REGULAR_PAGE_SIZE - 1);
+
+        while address < end {
+            let virt = PhysicalAddress::from(address).translate();
+            let (page_size, _) = virt.mapping_path()?;
+
+            unsafe { virt.set_executable(false)? };
+            address = (address + 1).next_multiple_of(page_size.size());
+        }
+    }
+
+    Ok(())
+}
+
+/// Map the pages of physical memory that hold `address..address + length`
+/// at their place in the direct map, unless they are already mapped.
+///
+/// This is for memory that is not RAM, such as the registers of devices,
+/// which [`map_physical_memory`] does not map.
+pub fn map_device_memory(
+    address: PhysicalAddress,
+    length: usize,
+    flags: PageEntryFlags,
+) -> Result<(), MappingError> {
+    let start = address.as_usize() & !(REGULAR_PAGE_SIZE - 1);
+    let end =
+        (address.as_usize() + length).next_multiple_of(REGULAR_PAGE_SIZE);
+
+    for page in (start..end).step_by(REGULAR_PAGE_SIZE) {
+        let physical = PhysicalAddress::from(page);
+        let virt = physical.translate();
+
+        if virt.translate().is_err() {
+            virt.map(physical, Some(flags), PageSize::Regular)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the identity mapping of the low memory that the bootloader set
+/// up, so the lower half of the address space is left for user space.
+///
+/// # Safety
+/// Nothing may use a low address anymore, including the current stack.
+pub unsafe fn unmap_identity() {
+    // The GDT is still in the second stage, so it is loaded again
+    // through the direct map.
+    let gdt = core::ptr::from_ref(unsafe { tables::sgdt() });
+    let gdt = PhysicalAddress::from(gdt.addr())
+        .translate()
+        .as_non_null::<GlobalDescriptorTableLong>();
+    unsafe { gdt.as_ref().load() };
+
+    let pml4 = unsafe { PageTable::current_table().as_mut() };
+    pml4.entries[0]
+        .unmap()
+        .expect("The low memory is not identity mapped");
+
+    tlb::flash_all();
+}
+
+/// Returns true if the region is RAM that the kernel may access.
+fn is_ram(region: &MemoryRegion) -> bool {
+    matches!(
+        region.region_type,
+        MemoryRegionType::Usable
+            | MemoryRegionType::Reclaimable
+            | MemoryRegionType::ACPINVS
+            | MemoryRegionType::UserEnterd
+    )
+}
+
+/// Map the whole pages of physical memory in `start..end` in the direct
+/// map, skipping the part that the bootloader already mapped.
+fn map_range(
+    start: u64,
+    end: u64,
+    page_sizes: &[PageSize],
+) -> Result<(), MappingError> {
+    let mut address = (start.max(BOOT_MAPPED_SIZE) as usize)
+        .next_multiple_of(REGULAR_PAGE_SIZE);
+    let end = end as usize & !(REGULAR_PAGE_SIZE - 1);
+
+    while address < end {
+        let physical = PhysicalAddress::from(address);
+
+        let page_size = page_sizes
+            .iter()
+            .copied()
+            .find(|size| {
+                physical.is_aligned(size.alignment())
+                    && end - address >= size.size()
+            })
+            .expect("A regular page always fits");
+
+        physical.translate().map(physical, None, page_size)?;
+        address += page_size.size();
+    }
+
+    Ok(())
+}
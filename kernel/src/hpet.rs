@@ -0,0 +1,109 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use common::{
+    address_types::Address,
+    enums::interrupts::Interrupt,
+    error::{AcpiError, HpetError},
+    late_init::LateInit,
+};
+use libk::time::{self, Duration};
+use x86::{
+    acpi::{HpetTable, MpsIntiFlags},
+    apic::InterruptSource,
+    hpet::Hpet,
+    instructions::interrupts,
+    structures::{
+        interrupt_descriptor_table::InterruptStackFrame,
+        paging::PageEntryFlags,
+    },
+};
+
+use crate::{IO_APICS, LOCAL_APIC, acpi, apic, direct_map};
+
+/// The size of the registers of the HPET.
+const HPET_REGISTERS_SIZE: usize = 0x400;
+
+/// When [`interrupt_arrives`] fires the comparator, in nanoseconds.
+const TEST_DELAY_NS: u64 = 1_000_000;
+
+/// How long [`interrupt_arrives`] waits for the interrupt.
+const TEST_TIMEOUT: Duration = Duration::from_millis(10);
+
+pub static HPET: LateInit<Hpet> = LateInit::uninit();
+
+/// Set when the interrupt of [`interrupt_arrives`] is handled.
+static TEST_FIRED: AtomicBool = AtomicBool::new(false);
+
+/// Map the registers of the HPET that the ACPI tables describe, and start
+/// its main counter.
+pub fn init() -> Result<(), AcpiError> {
+    let table = acpi::find_table::<HpetTable>()?;
+    direct_map::map_device_memory(
+        table.address(),
+        HPET_REGISTERS_SIZE,
+        PageEntryFlags::regular_io_page_flags(),
+    )?;
+
+    HPET.init(unsafe {
+        Hpet::new(table.address().translate().as_non_null())
+    });
+    Ok(())
+}
+
+/// Route the interrupt of the comparator to `vector` on this cpu.
+///
+/// The comparators raise edge triggered interrupts, which are active
+/// high.
+///
+/// # Safety
+/// The vector must have a handler, which signals the end of the interrupt
+/// to [`LOCAL_APIC`].
+pub unsafe fn route(
+    timer: u8,
+    vector: Interrupt,
+) -> Result<(), HpetError> {
+    let source = InterruptSource {
+        gsi: HPET.route(timer)?,
+        flags: MpsIntiFlags(0),
+    };
+    unsafe { apic::route(source, vector)? };
+    Ok(())
+}
+
+/// Fire the first comparator once through the I/O APIC, and returns true
+/// if its interrupt arrives.
+///
+/// Interrupts must be enabled, and the [`CLOCK`](time::CLOCK) must be
+/// initialized.
+pub fn interrupt_arrives() -> Result<bool, HpetError> {
+    TEST_FIRED.store(false, Ordering::Relaxed);
+    unsafe {
+        route(0, Interrupt::Hpet)?;
+        HPET.start_one_shot(
+            0,
+            HPET.counter() + HPET.ns_to_counts(TEST_DELAY_NS),
+        )?;
+    }
+    let fired = time::wait_until(TEST_TIMEOUT, || {
+        TEST_FIRED.load(Ordering::Relaxed)
+    })
+    .is_ok();
+
+    HPET.stop(0)?;
+    IO_APICS.lock().mask(HPET.route(0)?)?;
+    Ok(fired)
+}
+
+/// Handles the interrupt of [`interrupt_arrives`].
+pub extern "x86-interrupt" fn hpet_handler(
+    _stack_frame: InterruptStackFrame,
+) {
+    unsafe {
+        interrupts::disable();
+    }
+    TEST_FIRED.store(true, Ordering::Relaxed);
+    LOCAL_APIC.end_of_interrupt();
+    unsafe {
+        interrupts::enable();
+    }
+}
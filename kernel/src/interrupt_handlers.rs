@@ -1,24 +1,37 @@
+use core::ptr;
+
 use common::{
-    address_types::{Address, PhysicalAddress, VirtualAddress},
+    address_types::{Address, VirtualAddress},
+    constants::REGULAR_PAGE_SIZE,
     enums::{
-        PageSize, ProtectionLevel,
-        interrupts::{Interrupt, InterruptType},
+        ProtectionLevel,
+        interrupts::{
+            Interrupt, InterruptStackTable, InterruptType,
+            PageFaultErrorCode,
+        },
     },
+    error::CowError,
 };
 use keyboard::keyboard_handler;
-use libk::alloc::VirtualAddressMapping;
 use vga_display::println;
 use x86::{
     registers::cr2,
-    structures::{
-        interrupt_descriptor_table::{
-            InterruptDescriptorTable, InterruptStackFrame,
-        },
-        paging::PageEntryFlags,
+    structures::interrupt_descriptor_table::{
+        InterruptDescriptorTable, InterruptStackFrame,
     },
 };
 
-use crate::timer::timer_handler;
+use crate::{
+    cow,
+    demand_paging::{self, LAZY_REGIONS},
+    hpet::hpet_handler,
+    sections, stack,
+    timer::{apic_timer_handler, timer_handler},
+};
+
+/// The interrupt stack table entry of the double fault stack.
+pub const DOUBLE_FAULT_IST: InterruptStackTable =
+    InterruptStackTable::IST1;
 
 pub extern "x86-interrupt" fn division_error_handler(
     stack_frame: InterruptStackFrame,
@@ -119,13 +132,30 @@ pub extern "x86-interrupt" fn virtualization_handler(
     panic!("Stack frame: {:#?}", stack_frame);
 }
 
+/// Runs on its own stack, so a fault that could not be delivered on an
+/// overflowed kernel stack can still be reported.
 pub extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    panic!("Interrupt: DoubleFault");
-    panic!("Stack frame: {:#?}", stack_frame);
-    panic!("Error code: {:#x}", error_code);
+    let address =
+        unsafe { VirtualAddress::new_unchecked(cr2::read() as usize) };
+
+    if let Some(task) = stack::overflowed_stack(address)
+        .or_else(|| stack::overflowed_stack(stack_frame.stack_pointer))
+    {
+        panic!(
+            "Kernel stack overflow in {} at rip {:#x}\nStack frame: {:#?}",
+            task,
+            stack_frame.instruction_pointer.as_usize(),
+            stack_frame
+        );
+    }
+
+    panic!(
+        "Interrupt: DoubleFault\nError code: {:#x}\nStack frame: {:#?}",
+        error_code, stack_frame
+    );
 }
 
 pub extern "x86-interrupt" fn invalid_tss_handler(
@@ -165,22 +195,77 @@ pub extern "x86-interrupt" fn general_protection_handler(
 }
 
 pub extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    let faulting_address =
+    let address =
         unsafe { VirtualAddress::new_unchecked(cr2::read() as usize) };
+    let code = PageFaultErrorCode::from(error_code as u16);
+
+    let region = LAZY_REGIONS.lock().find(address);
+
+    if let Some(region) = region
+        && code.is_not_present()
+    {
+        if let Err(e) = demand_paging::back_page(&region, address) {
+            panic!(
+                "Page fault: could not back {:#x} in `{}`: {}",
+                address.as_usize(),
+                region.name,
+                e
+            );
+        }
+        return;
+    }
 
-    let identity_map =
-        unsafe { PhysicalAddress::new_unchecked(cr2::read() as usize) };
+    if let Some(task) = stack::overflowed_stack(address) {
+        panic!(
+            "Kernel stack overflow in {} at rip {:#x}\nStack frame: {:#?}",
+            task,
+            stack_frame.instruction_pointer.as_usize(),
+            stack_frame
+        );
+    }
+
+    if !code.is_not_present() && code.is_write() {
+        match cow::copy_on_write(address) {
+            Ok(()) => return,
+            Err(CowError::NotCopyOnWrite) => {}
+            Err(e) => panic!(
+                "Page fault: could not copy {:#x}: {}",
+                address.as_usize(),
+                e
+            ),
+        }
+    }
+
+    let recovery = sections::write_fault_recovery(address, &code);
+
+    if let Some(recovery) = recovery {
+        // The frame is the one the cpu returns through, so the write must
+        // not be optimized away.
+        unsafe {
+            ptr::write_volatile(
+                &mut stack_frame.instruction_pointer,
+                recovery,
+            )
+        };
+        return;
+    }
 
-    faulting_address
-        .map(
-            identity_map,
-            Some(PageEntryFlags::regular_page_flags()),
-            PageSize::Regular,
-        )
-        .expect("Cannot map address");
+    panic!(
+        "Page fault: {} {:#x}{} at rip {:#x}\n{:#?}\nStack frame: {:#?}",
+        code.access(),
+        address.as_usize(),
+        if address.as_usize() < REGULAR_PAGE_SIZE {
+            " (null pointer dereference)"
+        } else {
+            ""
+        },
+        stack_frame.instruction_pointer.as_usize(),
+        code,
+        stack_frame
+    );
 }
 
 pub extern "x86-interrupt" fn alignment_check_handler(
@@ -201,6 +286,13 @@ pub extern "x86-interrupt" fn control_protection_handler(
     panic!("Error code: {:#x}", error_code);
 }
 
+/// The local APIC raises this when an interrupt goes away before it is
+/// delivered, and it must not be acknowledged.
+pub extern "x86-interrupt" fn spurious_handler(
+    _stack_frame: InterruptStackFrame,
+) {
+}
+
 #[extend::ext]
 pub impl InterruptDescriptorTable {
     fn init_handlers(&mut self) {
@@ -277,13 +369,14 @@ pub impl InterruptDescriptorTable {
                 InterruptType::Fault,
             );
 
-            self.set_interrupt_handler(
+            self.set_interrupt_handler_with_stack(
                 Interrupt::DoubleFault,
                 VirtualAddress::new_unchecked(
                     double_fault_handler as *const () as usize,
                 ),
                 ProtectionLevel::Ring0,
                 InterruptType::Fault,
+                DOUBLE_FAULT_IST,
             );
 
             self.set_interrupt_handler(
@@ -404,6 +497,22 @@ pub impl InterruptDescriptorTable {
                 ProtectionLevel::Ring0,
                 InterruptType::Trap,
             );
+            self.set_interrupt_handler(
+                Interrupt::ApicTimer,
+                VirtualAddress::new_unchecked(
+                    apic_timer_handler as *const () as usize,
+                ),
+                ProtectionLevel::Ring0,
+                InterruptType::Trap,
+            );
+            self.set_interrupt_handler(
+                Interrupt::Hpet,
+                VirtualAddress::new_unchecked(
+                    hpet_handler as *const () as usize,
+                ),
+                ProtectionLevel::Ring0,
+                InterruptType::Trap,
+            );
             self.set_interrupt_handler(
                 Interrupt::Keyboard,
                 VirtualAddress::new_unchecked(
@@ -412,6 +521,14 @@ pub impl InterruptDescriptorTable {
                 ProtectionLevel::Ring0,
                 InterruptType::Trap,
             );
+            self.set_interrupt_handler(
+                Interrupt::Spurious,
+                VirtualAddress::new_unchecked(
+                    spurious_handler as *const () as usize,
+                ),
+                ProtectionLevel::Ring0,
+                InterruptType::Trap,
+            );
             // self.set_interrupt_handler(
             //     Interrupt::Ahci,
             //     VirtualAddress::new_unchecked(
@@ -4,9 +4,10 @@
 #![feature(abi_x86_interrupt)]
 #![feature(const_default)]
 #![feature(const_trait_impl)]
+#![feature(const_convert)]
 extern crate alloc;
 
-use core::panic::PanicInfo;
+use core::{alloc::Layout, arch::naked_asm, panic::PanicInfo};
 
 use alloc::boxed::Box;
 use buddy::BuddyAllocator;
@@ -14,14 +15,20 @@ use bump::BumpAllocator;
 use common::{
     address_types::{Address, PhysicalAddress, VirtualAddress},
     constants::{
-        MEMORY_MAP_LENGTH, MEMORY_MAP_OFFSET, MiB, PARSED_MEMORY_MAP,
-        PHYSICAL_MEMORY_OFFSET, REGULAR_PAGE_SIZE,
+        IDENTITY_PAGE_TABLE_L2_OFFSET, IDENTITY_PAGE_TABLE_L4_OFFSET,
+        KERNEL_VIRTUAL_BASE, MEMORY_MAP_LENGTH, MEMORY_MAP_OFFSET, MiB,
+        PARSED_MEMORY_MAP, PHYSICAL_MEMORY_OFFSET, REGULAR_PAGE_SIZE,
     },
-    enums::{PS2ScanCode, PageSize},
+    enums::{
+        IsaIrq, PS2ScanCode, PageSize, PageTableLevel,
+        interrupts::Interrupt,
+    },
+    error::AcpiError,
     late_init::LateInit,
 };
 use keyboard::ps2_keyboard::Keyboard;
 use page::{Page, arena::PageMap};
+use slab::{SLAB_ALLOCATOR, vmalloc};
 use vga_display::{
     SCREEN,
     advanced_writer::AdvancedWriter,
@@ -31,29 +38,51 @@ use vga_display::{
     writer::SimpleWriter,
 };
 use x86::{
-    instructions::interrupts::{self, hlt},
+    acpi::Madt,
+    apic::{IoApics, LocalApic},
+    instructions::{
+        interrupts::{self, hlt},
+        tlb,
+    },
     memory_map::{MemoryMap, MemoryRegion, MemoryRegionExtended},
     pic8259::CascadedPIC,
-    structures::interrupt_descriptor_table::InterruptDescriptorTable,
+    registers::{self, model_specific},
+    structures::{
+        interrupt_descriptor_table::{InterruptDescriptorTable, TSS},
+        paging::{PageEntryFlags, PageTable},
+    },
 };
 
 use libk::{
-    alloc::{GlobalAllocator, VirtualAddressMapping},
+    alloc::{GlobalAllocator, TableAllocator, VirtualAddressMapping},
     print, println,
+    time::{self, Clock, ClockSource},
 };
 
 use sync::{mutex::SpinMutex, spsc::SpscRingBuffer};
 
-use crate::interrupt_handlers::InterruptDescriptorTableExt;
+use crate::{
+    interrupt_handlers::{DOUBLE_FAULT_IST, InterruptDescriptorTableExt},
+    stack::{DOUBLE_FAULT_STACK_SIZE, KERNEL_STACK_SIZE, KernelStack},
+};
 
+mod acpi;
+mod apic;
+mod cow;
+mod demand_paging;
+mod direct_map;
+mod hpet;
 mod interrupt_handlers;
+mod sections;
+mod stack;
 mod timer;
 
 static MMAP: LateInit<MemoryMap> = LateInit::uninit();
 
 #[unsafe(no_mangle)]
-static PIC: SpinMutex<CascadedPIC> =
-    SpinMutex::new(CascadedPIC::default());
+static LOCAL_APIC: LateInit<LocalApic> = LateInit::uninit();
+
+static IO_APICS: SpinMutex<IoApics> = SpinMutex::new(IoApics::new());
 
 static IDT: LateInit<SpinMutex<Box<InterruptDescriptorTable>>> =
     LateInit::uninit();
@@ -79,28 +108,83 @@ pub static BUMP_ALLOCATOR: LateInit<BumpAllocator> = LateInit::uninit();
 #[global_allocator]
 static mut GLOBAL_ALLOCATOR: GlobalAllocator = GlobalAllocator::uninit();
 
+#[unsafe(no_mangle)]
 pub static BUDDY_ALLOCATOR: LateInit<BuddyAllocator<PageMap, Page>> =
     LateInit::uninit();
 
+/// The table of the top 512GiB of the address space, which maps the
+/// kernel at [`KERNEL_VIRTUAL_BASE`].
+static mut KERNEL_PDPT: PageTable = PageTable::empty();
+
+/// How often the screen is redrawn, in milliseconds.
+const SCREEN_UPDATE_MS: u64 = 50;
+
+const KERNEL_BASE: VirtualAddress =
+    VirtualAddress::from(KERNEL_VIRTUAL_BASE);
+
+/// The entry point, which runs from the identity mapping of the low
+/// memory.
+///
+/// The kernel is loaded at
+/// [`KERNEL_OFFSET`](common::constants::KERNEL_OFFSET) and linked at the
+/// top 2GiB, so this code must be position independent. Relative addresses
+/// are physical until the jump, and the first 2MiB of the physical memory,
+/// which hold the kernel, are mapped at [`KERNEL_VIRTUAL_BASE`] through
+/// the identity L2 table of the bootloader.
+#[unsafe(naked)]
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".start")]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn _start() -> ! {
+    naked_asm!(
+        "lea rax, [rip + {pdpt}]",
+        "mov qword ptr [rax + {pdpt_index} * 8], {l2} | {flags}",
+        "or rax, {flags}",
+        "mov qword ptr [{l4} + {pml4_index} * 8], rax",
+        "movabs rax, offset {entry}",
+        "jmp rax",
+        pdpt = sym KERNEL_PDPT,
+        pdpt_index = const KERNEL_BASE.index_of(PageTableLevel::PDPT),
+        pml4_index = const KERNEL_BASE.index_of(PageTableLevel::PML4),
+        l2 = const IDENTITY_PAGE_TABLE_L2_OFFSET,
+        l4 = const IDENTITY_PAGE_TABLE_L4_OFFSET,
+        flags = const u16::from(PageEntryFlags::table_flags()),
+        entry = sym kernel_start,
+    )
+}
+
+/// The first code that runs in the higher half.
+unsafe extern "C" fn kernel_start() -> ! {
     vga_init();
 
     okprintln!("Entered Protected Mode");
     okprintln!("Enabled Paging");
     okprintln!("Entered Long Mode");
-    let len = unsafe { *(MEMORY_MAP_LENGTH as *const u32) as usize };
+    okprintln!("Jumped to the higher half");
+
+    // The bootloader left the memory map in the low memory, which is
+    // mapped at the start of the direct map.
+    let len = unsafe {
+        *PhysicalAddress::from(MEMORY_MAP_LENGTH)
+            .translate()
+            .as_non_null::<u32>()
+            .as_ptr() as usize
+    };
     let raw = unsafe {
         core::slice::from_raw_parts_mut(
-            MEMORY_MAP_OFFSET as *mut MemoryRegionExtended,
+            PhysicalAddress::from(MEMORY_MAP_OFFSET)
+                .translate()
+                .as_non_null::<MemoryRegionExtended>()
+                .as_ptr(),
             len,
         )
     };
     let buf = unsafe {
         core::slice::from_raw_parts_mut(
-            PARSED_MEMORY_MAP as *mut MemoryRegion,
+            PhysicalAddress::from(PARSED_MEMORY_MAP)
+                .translate()
+                .as_non_null::<MemoryRegion>()
+                .as_ptr(),
             REGULAR_PAGE_SIZE / size_of::<MemoryRegion>(),
         )
     };
@@ -114,6 +198,11 @@ pub unsafe extern "C" fn _start() -> ! {
         GLOBAL_ALLOCATOR.set(BUMP_ALLOCATOR.assume_init_ref());
     }
 
+    direct_map::map_physical_memory(MMAP.assume_init_ref())
+        .expect("Could not map the physical memory");
+
+    okprintln!("Mapped the physical memory");
+
     BUDDY_ALLOCATOR.init(BuddyAllocator::<PageMap, Page>::new(
         MMAP.assume_init_ref(),
     ));
@@ -123,38 +212,160 @@ pub unsafe extern "C" fn _start() -> ! {
         MMAP.assume_init_ref(),
     );
 
-    #[allow(static_mut_refs)]
-    GLOBAL_ALLOCATOR.set(BUDDY_ALLOCATOR.assume_init_ref());
+    BUDDY_ALLOCATOR.set_oom_hook(buddy_oom_hook);
 
     okprintln!("Initialized Buddy Allocator");
+
+    SLAB_ALLOCATOR.init();
+    vmalloc::init();
+    BUDDY_ALLOCATOR.set_pressure_hook(|| SLAB_ALLOCATOR.shrink());
+
+    #[allow(static_mut_refs)]
+    GLOBAL_ALLOCATOR.set(&SLAB_ALLOCATOR);
+
+    libk::alloc::set_table_allocator(TableAllocator {
+        alloc: slab::alloc_page_table,
+        free: slab::free_page_table,
+    });
+
+    okprintln!("Initialized Slab Allocator");
+
+    if tlb::enable_pcid() {
+        okprintln!("Enabled Process Context Identifiers");
+    }
+
+    let nx = model_specific::enable_nx();
+    if nx {
+        okprintln!("Enabled No-Execute pages");
+        unsafe { direct_map::disable_execution(MMAP.assume_init_ref()) }
+            .expect("Could not protect the direct map");
+        okprintln!("Disabled execution from the direct map");
+    }
+
+    // Without it, the kernel writes through read-only mappings without
+    // faulting, so neither W^X nor copy-on-write would be enforced.
+    registers::enable_write_protect();
+    okprintln!("Enabled write protection");
+
     unsafe {
         interrupts::disable();
         InterruptDescriptorTable::init(&IDT);
         okprintln!("Initialized interrupt descriptor table");
         IDT.lock().init_handlers();
         okprintln!("Initialized interrupts handlers");
-        PIC.lock().init();
-        okprintln!("Initialized Programmable Interrupt Controller");
+        let double_fault_stack =
+            KernelStack::new("double fault", DOUBLE_FAULT_STACK_SIZE)
+                .expect("Could not allocate the double fault stack");
+        TSS.lock().set_interrupt_stack(
+            DOUBLE_FAULT_IST,
+            double_fault_stack.top(),
+        );
+        okprintln!("Initialized double fault stack");
+        acpi::init().expect("Could not find the ACPI tables");
+        let madt =
+            acpi::find_table::<Madt>().expect("Could not find the MADT");
+        apic::map_registers(madt)
+            .expect("Could not map the registers of the APICs");
+        LOCAL_APIC.init(
+            LocalApic::enable(Interrupt::Spurious)
+                .expect("Could not enable the local APIC"),
+        );
+        okprintln!("Enabled the local APIC");
+        IO_APICS
+            .lock()
+            .init(madt)
+            .expect("Could not initialize the I/O APICs");
+        okprintln!("Initialized the I/O APICs");
+        if madt.has_legacy_pics() {
+            let mut pic = CascadedPIC::default();
+            pic.init();
+            pic.disable();
+            okprintln!("Masked the 8259 PICs");
+        }
+        apic::route_isa_irq(IsaIrq::Timer, Interrupt::Timer)
+            .expect("Could not route the timer interrupt");
+        apic::route_isa_irq(IsaIrq::Keyboard, Interrupt::Keyboard)
+            .expect("Could not route the keyboard interrupt");
         let buffer = Box::new([0u8; 4096]);
         KEYBOARD_BUFFER.init(SpscRingBuffer::new(buffer));
 
         KEYBOARD.init(Keyboard::new(&KEYBOARD_BUFFER));
         okprintln!("Initialized Keyboard");
-        interrupts::enable();
+        timer::init();
+        okprintln!("Started the PIT and the local APIC timer");
+    }
+
+    let has_hpet = match hpet::init() {
+        Ok(()) => true,
+        Err(AcpiError::TableNotFound(_)) => false,
+        Err(error) => panic!("Could not start the HPET: {}", error),
+    };
+    if has_hpet {
+        okprintln!("Started the HPET");
+    }
+
+    // The TSC is only measured here, and the timers count their deadlines
+    // on this clock.
+    time::CLOCK.init(Clock::new(ClockSource::select(
+        has_hpet.then(|| &*hpet::HPET),
+        &timer::PIT,
+    )));
+    let clock_source = time::CLOCK.source();
+    okprintln!("Using {} as the monotonic clock", clock_source);
+
+    timer::add_timer(0, Some(SCREEN_UPDATE_MS), update_screen)
+        .expect("Could not add the screen update timer");
+    unsafe { interrupts::enable() };
+
+    sections::protect_kernel(nx)
+        .expect("Could not protect the kernel sections");
+    okprintln!("Protected the kernel sections");
+    assert!(sections::text_is_read_only(), "The kernel code is writable");
+    okprintln!("Verified that the kernel code is read-only");
+    assert!(
+        cow::writes_are_private()
+            .expect("Could not share a page copy-on-write"),
+        "A copy-on-write page was written in place"
+    );
+    okprintln!("Verified that copy-on-write pages are copied on write");
+
+    if has_hpet {
+        assert!(
+            hpet::interrupt_arrives().expect("Could not fire the HPET"),
+            "The HPET interrupt did not arrive"
+        );
+        okprintln!("Verified the HPET interrupt");
     }
-    let w = ADVANCED_WRITER.leak();
+
+    let stack = KernelStack::new("kernel", KERNEL_STACK_SIZE)
+        .expect("Could not allocate the kernel stack");
+    okprintln!("Switching to the kernel stack");
+    unsafe { stack.switch_to(kernel_main) }
+}
+
+/// The rest of the kernel, which runs on a stack with a guard page.
+extern "C" fn kernel_main() -> ! {
+    let w = unsafe { ADVANCED_WRITER.leak() };
     w.init(AdvancedWriter::default());
     WRITER.lock().set_writer(w.assume_init_mut());
     okprintln!("Set advanced writer");
+
+    unsafe { direct_map::unmap_identity() };
+    okprintln!("Unmapped the low memory");
+    let boot_time = timer::uptime();
+    okprintln!("Booted in {:?}", boot_time);
     // Wait for the next update.
     unsafe {
         hlt();
     }
 
     println!("{}", MMAP.assume_init_ref());
-    BUDDY_ALLOCATOR.print_allocated_regions();
-    // unsafe { SLAB_ALLOCATOR.init() }
-    // okprintln!("Initialized slab allocator");
+    println!("{}", BUDDY_ALLOCATOR.stats());
+    #[cfg(feature = "alloc-tracking")]
+    #[allow(static_mut_refs)]
+    unsafe {
+        GLOBAL_ALLOCATOR.dump_leaks()
+    }
     // panic!("")
     // let mut pci_devices = pci::scan_pci();
     // println!("Press ENTER to enumerate PCI devices!");
@@ -205,16 +416,15 @@ pub unsafe extern "C" fn _start() -> ! {
     //         let _ = hba.probe_init();
     //         let p = &mut hba.ports[0];
 
-    //         let buf =
-    //             unsafe { alloc_pages!(1) as *mut IdentityPacketData
-    // };
+    //         let buf = dma::alloc(
+    //             size_of::<IdentityPacketData>(),
+    //             DmaConstraints::new().below_4gib(),
+    //         )
+    //         .unwrap();
 
-    //         p.identity_packet(buf);
+    //         p.identity_packet(&buf);
     //         let id = unsafe {
-    //             core::ptr::read_volatile(
-    //                 (buf as usize + PHYSICAL_MEMORY_OFFSET)
-    //                     as *mut IdentityPacketData,
-    //             )
+    //             buf.as_non_null::<IdentityPacketData>().read_volatile()
     //         };
 
     //         println!("{:?}", id);
@@ -252,6 +462,25 @@ pub unsafe extern "C" fn _start() -> ! {
     }
 }
 
+/// This function is called when a heap allocation runs out of memory,
+/// right before the allocation error is raised.
+fn buddy_oom_hook(layout: Layout) {
+    println!("Out of memory while allocating {:?}", layout);
+    println!("{}", BUDDY_ALLOCATOR.stats());
+    #[cfg(feature = "alloc-tracking")]
+    #[allow(static_mut_refs)]
+    unsafe {
+        GLOBAL_ALLOCATOR.dump_leaks()
+    }
+}
+
+/// This function is called by the timer every [`SCREEN_UPDATE_MS`].
+fn update_screen() {
+    if let Some(mut writer) = WRITER.try_lock() {
+        writer.inner.update();
+    }
+}
+
 /// This function is called on panic.
 #[panic_handler]
 unsafe fn panic(_info: &PanicInfo) -> ! {
@@ -0,0 +1,146 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use common::{
+    address_types::{Address, VirtualAddress},
+    constants::REGULAR_PAGE_SIZE,
+    enums::{PageSize, interrupts::PageFaultErrorCode},
+    error::MappingError,
+};
+use libk::alloc::VirtualAddressMapping;
+use x86::structures::paging::PageEntryFlags;
+
+unsafe extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+}
+
+/// The address to continue from when a write to the kernel code faults,
+/// or zero if such a fault is not expected.
+static WRITE_FAULT_RECOVERY: AtomicUsize = AtomicUsize::new(0);
+
+/// A group of sections of the kernel image that share their permissions.
+struct Section {
+    start: *const u8,
+    end: *const u8,
+    flags: PageEntryFlags,
+    executable: bool,
+}
+
+/// Map the code of the kernel as read-only, its constants as read-only
+/// and not executable, and its data as writable and not executable.
+///
+/// The kernel is also reachable through the direct map, where its code
+/// and constants are made read-only too, so no alias of the kernel is
+/// both writable and executable.
+///
+/// The kernel is loaded in a big page, which is split down to regular
+/// pages, so tables must be allocatable. The execute disable bit is set
+/// only if `nx` is true, since it is reserved until it is enabled in
+/// EFER.
+pub fn protect_kernel(nx: bool) -> Result<(), MappingError> {
+    let read_only = PageEntryFlags::new().present(true);
+
+    let sections = [
+        Section {
+            start: &raw const __text_start,
+            end: &raw const __text_end,
+            flags: read_only,
+            executable: true,
+        },
+        Section {
+            start: &raw const __rodata_start,
+            end: &raw const __rodata_end,
+            flags: read_only,
+            executable: false,
+        },
+        Section {
+            start: &raw const __data_start,
+            end: &raw const __data_end,
+            flags: PageEntryFlags::regular_page_flags(),
+            executable: false,
+        },
+    ];
+
+    for section in sections {
+        for page in (section.start.addr()..section.end.addr())
+            .step_by(REGULAR_PAGE_SIZE)
+        {
+            let page = VirtualAddress::from(page);
+            let alias = page.translate()?.translate();
+
+            while page.split()? != PageSize::Regular {}
+            while alias.split()? != PageSize::Regular {}
+
+            page.protect(section.flags)?;
+            alias.protect(section.flags)?;
+            if nx {
+                unsafe {
+                    page.set_executable(section.executable)?;
+                    alias.set_executable(false)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write to the kernel code, and return true if the write faulted.
+///
+/// The byte is written back with its own value, so nothing changes if
+/// the code is writable.
+pub fn text_is_read_only() -> bool {
+    let faulted: u32;
+
+    unsafe {
+        asm!(
+            "lea {tmp}, [rip + 2f]",
+            "mov qword ptr [{recovery}], {tmp}",
+            "mov {tmp:l}, byte ptr [{target}]",
+            "mov byte ptr [{target}], {tmp:l}",
+            "xor {faulted:e}, {faulted:e}",
+            "jmp 3f",
+            // The page fault handler continues from here.
+            "2:",
+            "mov {faulted:e}, 1",
+            "3:",
+            "mov qword ptr [{recovery}], 0",
+            recovery = in(reg) WRITE_FAULT_RECOVERY.as_ptr(),
+            target = in(reg) &raw const __text_start,
+            tmp = out(reg) _,
+            faulted = out(reg) faulted,
+            options(nostack),
+        )
+    }
+
+    faulted != 0
+}
+
+/// Returns the address to continue from if the page fault is the
+/// expected write to the kernel code of [`text_is_read_only`].
+pub fn write_fault_recovery(
+    address: VirtualAddress,
+    code: &PageFaultErrorCode,
+) -> Option<VirtualAddress> {
+    let text =
+        (&raw const __text_start).addr()..(&raw const __text_end).addr();
+
+    if code.is_not_present()
+        || !code.is_write()
+        || !text.contains(&address.as_usize())
+    {
+        return None;
+    }
+
+    match WRITE_FAULT_RECOVERY.swap(0, Ordering::Relaxed) {
+        0 => None,
+        recovery => Some(VirtualAddress::from(recovery)),
+    }
+}
@@ -0,0 +1,176 @@
+use core::arch::asm;
+
+use common::{
+    address_types::{Address, VirtualAddress},
+    constants::{KERNEL_STACKS_OFFSET, KiB, REGULAR_PAGE_SIZE},
+    enums::{BuddyOrder, PageSize},
+    error::StackError,
+};
+use libk::alloc::VirtualAddressMapping;
+use sync::mutex::SpinMutex;
+use x86::structures::paging::PageEntryFlags;
+
+use crate::BUDDY_ALLOCATOR;
+
+pub const KERNEL_STACK_SIZE: usize = 64 * KiB;
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 16 * KiB;
+
+/// The virtual space of each kernel stack, including its guard page.
+const STACK_SLOT_SIZE: usize = 32 * REGULAR_PAGE_SIZE;
+
+/// The maximum number of kernel stacks.
+const MAX_KERNEL_STACKS: usize = 64;
+
+/// The name of the task that owns each stack slot.
+static KERNEL_STACKS: SpinMutex<
+    [Option<&'static str>; MAX_KERNEL_STACKS],
+> = SpinMutex::new([None; MAX_KERNEL_STACKS]);
+
+/// A kernel stack with an unmapped guard page below it, so an overflow
+/// faults instead of corrupting the memory below the stack.
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: usize,
+    size: usize,
+}
+
+impl KernelStack {
+    /// Allocate a stack of `size` bytes, rounded up to whole pages, for
+    /// the task with the given name.
+    pub fn new(
+        name: &'static str,
+        size: usize,
+    ) -> Result<KernelStack, StackError> {
+        let size = size.next_multiple_of(REGULAR_PAGE_SIZE);
+        if size > STACK_SLOT_SIZE - REGULAR_PAGE_SIZE {
+            return Err(StackError::TooLarge(size));
+        }
+
+        let slot = {
+            let mut stacks = KERNEL_STACKS.lock();
+            let slot = stacks
+                .iter()
+                .position(|s| s.is_none())
+                .ok_or(StackError::Full)?;
+            stacks[slot] = Some(name);
+            slot
+        };
+
+        let stack = KernelStack { slot, size };
+
+        for page in (stack.bottom().as_usize()..stack.top().as_usize())
+            .step_by(REGULAR_PAGE_SIZE)
+        {
+            if let Err(e) = map_page(VirtualAddress::from(page)) {
+                unsafe { stack.free() };
+                return Err(e);
+            }
+        }
+
+        Ok(stack)
+    }
+
+    /// The unmapped page below the stack.
+    pub fn guard(&self) -> VirtualAddress {
+        VirtualAddress::from(
+            KERNEL_STACKS_OFFSET + self.slot * STACK_SLOT_SIZE,
+        )
+    }
+
+    /// The lowest address of the stack.
+    pub fn bottom(&self) -> VirtualAddress {
+        VirtualAddress::from(self.guard().as_usize() + REGULAR_PAGE_SIZE)
+    }
+
+    /// The address right above the stack, which is the initial stack
+    /// pointer.
+    pub fn top(&self) -> VirtualAddress {
+        VirtualAddress::from(self.bottom().as_usize() + self.size)
+    }
+
+    /// Continue the execution on this stack by calling `entry`.
+    ///
+    /// # Safety
+    /// Nothing on the current stack is accessible after the switch, and
+    /// this stack must never be freed.
+    pub unsafe fn switch_to(&self, entry: extern "C" fn() -> !) -> ! {
+        unsafe {
+            asm!(
+                "mov rsp, {top}",
+                "xor rbp, rbp",
+                "call {entry}",
+                "ud2",
+                top = in(reg) self.top().as_usize(),
+                entry = in(reg) entry,
+                options(noreturn)
+            )
+        }
+    }
+
+    /// Unmap the stack, and return the pages that are not shared anymore
+    /// to the buddy allocator.
+    ///
+    /// # Safety
+    /// Nothing may run on the stack anymore.
+    pub unsafe fn free(self) {
+        for page in (self.bottom().as_usize()..self.top().as_usize())
+            .step_by(REGULAR_PAGE_SIZE)
+        {
+            let Ok((physical, _)) = VirtualAddress::from(page).unmap()
+            else {
+                continue;
+            };
+
+            let block = BUDDY_ALLOCATOR
+                .block_at(physical)
+                .expect("Stack page is not on the page map");
+
+            // The page is still mapped copy-on-write somewhere else.
+            let mapping = unsafe { &block.as_ref().mapping };
+            if !mapping.try_unshare() {
+                mapping.release();
+                continue;
+            }
+
+            unsafe { BUDDY_ALLOCATOR.free_pages(block) };
+        }
+
+        KERNEL_STACKS.lock()[self.slot] = None;
+    }
+}
+
+/// Returns the name of the task whose stack overflowed, if `address` is
+/// inside the guard page of a kernel stack.
+///
+/// This is called from fault handlers, so it never waits for the lock.
+pub fn overflowed_stack(address: VirtualAddress) -> Option<&'static str> {
+    let offset = address.as_usize().checked_sub(KERNEL_STACKS_OFFSET)?;
+    let slot = offset / STACK_SLOT_SIZE;
+
+    if slot >= MAX_KERNEL_STACKS
+        || offset % STACK_SLOT_SIZE >= REGULAR_PAGE_SIZE
+    {
+        return None;
+    }
+
+    KERNEL_STACKS.try_lock()?[slot]
+}
+
+fn map_page(page: VirtualAddress) -> Result<(), StackError> {
+    let block = BUDDY_ALLOCATOR
+        .try_alloc_pages(BuddyOrder::Order0)
+        .map_err(|_| StackError::OutOfMemory)?;
+
+    let physical = BUDDY_ALLOCATOR.address_of(block);
+
+    if let Err(e) = page.map(
+        physical,
+        Some(PageEntryFlags::regular_page_flags()),
+        PageSize::Regular,
+    ) {
+        unsafe { BUDDY_ALLOCATOR.free_pages(block) };
+        return Err(e.into());
+    }
+
+    Ok(())
+}
@@ -1,10 +1,165 @@
-use common::enums::CascadedPicInterruptLine;
+use common::{
+    enums::interrupts::Interrupt, error::TimerError, late_init::LateInit,
+};
+use libk::time::{self, Duration, Instant};
+use sync::mutex::SpinMutex;
 use x86::{
-    instructions::interrupts,
+    apic::ApicTimer, instructions::interrupts, pit::Pit,
     structures::interrupt_descriptor_table::InterruptStackFrame,
 };
 
-use crate::{PIC, WRITER};
+use crate::LOCAL_APIC;
+
+/// The amount of timers that may be pending at once.
+const MAX_TIMERS: usize = 16;
+
+/// Program the timer to the earliest pending deadline, instead of waking
+/// up at a fixed rate.
+const TICKLESS: bool = !cfg!(feature = "periodic-timer");
+
+/// The rate of the timer when it is not [`TICKLESS`].
+const PERIODIC_HZ: u64 = 100;
+
+/// The rate of the PIT interrupts, which count the uptime.
+const PIT_HZ: u32 = 1000;
+
+pub static PIT: LateInit<Pit> = LateInit::uninit();
+
+static APIC_TIMER: LateInit<ApicTimer> = LateInit::uninit();
+
+static TIMERS: SpinMutex<TimerQueue> = SpinMutex::new(TimerQueue::new());
+
+/// A callback that runs from the timer interrupt once the
+/// [`CLOCK`](time::CLOCK) reaches its deadline.
+#[derive(Clone, Copy)]
+struct Timer {
+    deadline: Instant,
+    /// The time until it runs again, if it repeats.
+    period: Option<Duration>,
+    callback: fn(),
+}
+
+struct TimerQueue {
+    timers: [Option<Timer>; MAX_TIMERS],
+}
+
+impl TimerQueue {
+    const fn new() -> Self {
+        Self {
+            timers: [None; MAX_TIMERS],
+        }
+    }
+
+    fn push(&mut self, timer: Timer) -> Result<(), TimerError> {
+        let slot = self
+            .timers
+            .iter_mut()
+            .find(|t| t.is_none())
+            .ok_or(TimerError::QueueFull)?;
+        *slot = Some(timer);
+        Ok(())
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.timers.iter().flatten().map(|t| t.deadline).min()
+    }
+
+    /// Returns the callbacks of the timers that expired at `now`, and
+    /// removes them from the queue unless they repeat.
+    fn expire(&mut self, now: Instant) -> [Option<fn()>; MAX_TIMERS] {
+        let mut expired = [None; MAX_TIMERS];
+        for (slot, callback) in self.timers.iter_mut().zip(&mut expired) {
+            let Some(timer) = slot else { continue };
+            if timer.deadline > now {
+                continue;
+            }
+
+            *callback = Some(timer.callback);
+            match timer.period {
+                Some(period) => {
+                    timer.deadline = timer.deadline + period;
+                    // Skip the periods that were missed, instead of
+                    // firing once for each of them.
+                    if timer.deadline <= now {
+                        timer.deadline = now + period;
+                    }
+                }
+                None => *slot = None,
+            }
+        }
+        expired
+    }
+}
+
+/// Start the PIT, and measure the frequency of the local APIC timer and
+/// start it.
+///
+/// # Safety
+/// [`LOCAL_APIC`] must be enabled, [`Interrupt::Timer`] must be handled
+/// by [`timer_handler`] and [`Interrupt::ApicTimer`] by
+/// [`apic_timer_handler`]. Interrupts should be disabled.
+pub unsafe fn init() {
+    PIT.init(unsafe { Pit::start(PIT_HZ) });
+    APIC_TIMER
+        .init(ApicTimer::calibrate(&LOCAL_APIC, Interrupt::ApicTimer));
+    if !TICKLESS {
+        unsafe { APIC_TIMER.start_periodic(1_000_000 / PERIODIC_HZ) };
+    }
+}
+
+/// Run `callback` from the timer interrupt after `delay_ms` milliseconds,
+/// and then every `period_ms` milliseconds if it is given.
+///
+/// The [`CLOCK`](time::CLOCK) must be initialized.
+pub fn add_timer(
+    delay_ms: u64,
+    period_ms: Option<u64>,
+    callback: fn(),
+) -> Result<(), TimerError> {
+    let timer = Timer {
+        deadline: Instant::now() + Duration::from_millis(delay_ms),
+        period: period_ms.map(Duration::from_millis),
+        callback,
+    };
+
+    interrupts::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        timers.push(timer)?;
+        rearm(&timers);
+        Ok(())
+    })
+}
+
+/// In tickless mode, program the timer to the earliest deadline, or stop
+/// it if nothing is pending. Otherwise the queue is checked on every tick.
+fn rearm(timers: &TimerQueue) {
+    if TICKLESS {
+        match timers.next_deadline() {
+            Some(deadline) => arm(deadline),
+            None => APIC_TIMER.stop(),
+        }
+    }
+}
+
+/// Program the timer to fire at `deadline`.
+///
+/// When the clock counts the TSC, its deadline is programmed directly if
+/// the timer supports it. Otherwise the time until the deadline is counted
+/// down, so the timer is measured against the clock only once.
+fn arm(deadline: Instant) {
+    match time::CLOCK.tsc_at(deadline) {
+        Some(tsc) if APIC_TIMER.has_tsc_deadline() => unsafe {
+            APIC_TIMER.start_tsc_deadline(tsc)
+        },
+        _ => unsafe {
+            APIC_TIMER
+                .start_one_shot(deadline.duration_since(Instant::now()))
+        },
+    }
+}
+
+/// Returns the time since the PIT was started.
+pub fn uptime() -> Duration { PIT.uptime() }
 
 pub extern "x86-interrupt" fn timer_handler(
     _stack_frame: InterruptStackFrame,
@@ -12,10 +167,26 @@ pub extern "x86-interrupt" fn timer_handler(
     unsafe {
         interrupts::disable();
     }
-    if let Some(mut writer) = WRITER.try_lock() {
-        writer.inner.update();
+    PIT.tick();
+    LOCAL_APIC.end_of_interrupt();
+    unsafe {
+        interrupts::enable();
+    }
+}
+
+pub extern "x86-interrupt" fn apic_timer_handler(
+    _stack_frame: InterruptStackFrame,
+) {
+    unsafe {
+        interrupts::disable();
+    }
+    // The callbacks run without the lock, so they may add timers.
+    let expired = TIMERS.lock().expire(Instant::now());
+    for callback in expired.into_iter().flatten() {
+        callback();
     }
-    PIC.lock().end_of_interrupt(CascadedPicInterruptLine::Timer);
+    rearm(&TIMERS.lock());
+    LOCAL_APIC.end_of_interrupt();
     unsafe {
         interrupts::enable();
     }
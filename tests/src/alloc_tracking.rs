@@ -0,0 +1,114 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+
+use libk::alloc::{
+    GlobalAllocator,
+    tracking::{AllocationTracker, MAX_TRACKED_ALLOCATIONS},
+};
+
+fn system_allocator() -> GlobalAllocator<'static> {
+    let mut allocator = GlobalAllocator::uninit();
+    allocator.set(&System);
+    allocator
+}
+
+#[test]
+fn test_tracking_alloc_free() {
+    let allocator = system_allocator();
+    let layout = Layout::from_size_align(48, 8).unwrap();
+
+    let a = unsafe { allocator.alloc(layout) };
+    let b = unsafe { allocator.alloc_zeroed(layout) };
+    let summary = allocator.allocation_summary();
+    assert_eq!(summary.live_allocations, 2);
+    assert_eq!(summary.live_bytes, 96);
+
+    let b = unsafe { allocator.realloc(b, layout, 100) };
+    let summary = allocator.allocation_summary();
+    assert_eq!(summary.live_allocations, 2);
+    assert_eq!(summary.live_bytes, 148);
+    assert_eq!(summary.peak_bytes, 148);
+
+    unsafe {
+        allocator.dealloc(a, layout);
+        allocator.dealloc(b, Layout::from_size_align(100, 8).unwrap());
+    }
+    let summary = allocator.allocation_summary();
+    assert_eq!(summary.live_allocations, 0);
+    assert_eq!(summary.live_bytes, 0);
+    assert_eq!(summary.peak_bytes, 148);
+    assert_eq!(summary.total_allocations, 3);
+    assert_eq!(summary.total_frees, 3);
+}
+
+#[test]
+fn test_tracking_kmalloc_call_sites() {
+    let allocator = system_allocator();
+    let layout = Layout::new::<u64>();
+
+    let first = allocator.kmalloc(layout).unwrap();
+    let second = allocator.kzalloc(layout).unwrap();
+
+    // Each call is counted for its own line, not for the allocator.
+    let sites: Vec<_> =
+        allocator.call_sites().into_iter().flatten().collect();
+    assert_eq!(sites.len(), 2);
+    assert!(sites.iter().all(|site| site.caller.file() == file!()));
+    assert!(sites.iter().all(|site| site.live_allocations() == 1));
+    assert_ne!(sites[0].caller.line(), sites[1].caller.line());
+
+    unsafe {
+        allocator.kfree(first, layout);
+        allocator.kfree(second, layout);
+    }
+    let sites: Vec<_> =
+        allocator.call_sites().into_iter().flatten().collect();
+    assert!(sites.iter().all(|site| site.live_allocations() == 0));
+}
+
+#[test]
+fn test_tracking_call_sites() {
+    let mut tracker = AllocationTracker::new();
+    let layout = Layout::new::<u64>();
+    let leaking = std::panic::Location::caller();
+    let freeing = std::panic::Location::caller();
+
+    for address in (0x1000..0x1100).step_by(8) {
+        tracker.track(address, layout, leaking);
+    }
+    for address in (0x2000..0x2100).step_by(8) {
+        tracker.track(address, layout, freeing);
+        tracker.untrack(address, layout);
+    }
+
+    let sites: Vec<_> = tracker.sites().copied().collect();
+    assert_eq!(sites.len(), 2);
+    assert_eq!(sites[0].live_allocations(), 32);
+    assert_eq!(sites[0].live_bytes, 256);
+    assert_eq!(sites[1].allocations, 32);
+    assert_eq!(sites[1].live_allocations(), 0);
+    assert_eq!(tracker.allocations().count(), 32);
+}
+
+#[test]
+fn test_tracking_full_table() {
+    let mut tracker = AllocationTracker::new();
+    let layout = Layout::new::<u64>();
+    let caller = std::panic::Location::caller();
+
+    for address in 0..MAX_TRACKED_ALLOCATIONS + 4 {
+        tracker.track(address * 8, layout, caller);
+    }
+
+    let summary = tracker.summary();
+    assert_eq!(summary.live_allocations, MAX_TRACKED_ALLOCATIONS + 4);
+    assert_eq!(summary.untracked_allocations, 4);
+
+    for address in 0..MAX_TRACKED_ALLOCATIONS + 4 {
+        tracker.untrack(address * 8, layout);
+    }
+
+    let summary = tracker.summary();
+    assert_eq!(summary.live_allocations, 0);
+    assert_eq!(summary.untracked_allocations, 0);
+    assert_eq!(tracker.allocations().count(), 0);
+}
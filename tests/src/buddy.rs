@@ -1,76 +1,488 @@
 #![allow(static_mut_refs)]
 
-use buddy::{BuddyAllocator, meta::BuddyArena};
-use common::enums::MemoryRegionType;
-use page::Page;
-use page::arena::PageMap;
-use sync::mutex::SpinMutex;
-use x86::{
-    memory_map::{MemoryMap, MemoryRegion, MemoryRegionExtended},
-    registers::al,
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use buddy::BuddyAllocator;
+use common::{
+    address_types::{Address, PhysicalAddress, VirtualAddress},
+    alloc::{Allocation, BumpAllocations},
+    constants::{MiB, REGULAR_PAGE_SIZE},
+    enums::{BuddyOrder, MemoryRegionType, Zone},
+};
+use page::{Page, host::HostPageMap};
+use x86::memory_map::{
+    ATTRIBUTE_ENABLED, MemoryMap, MemoryRegion, MemoryRegionExtended,
 };
 
 pub static mut MOCK_UNPARSED_MEMORY_MAP: [MemoryRegionExtended; 7] = [
     MemoryRegionExtended {
         base_address: 0x0000_0000,
         length: 0x0009_FC00,
-        region_type: MemoryRegionType::Usable,
-        extended_attributes: 0,
+        region_type: MemoryRegionType::Usable as u32,
+        extended_attributes: ATTRIBUTE_ENABLED,
     },
     MemoryRegionExtended {
         base_address: 0x0009_FC00,
         length: 0x0000_0400,
-        region_type: MemoryRegionType::Reserved,
-        extended_attributes: 0,
+        region_type: MemoryRegionType::Reserved as u32,
+        extended_attributes: ATTRIBUTE_ENABLED,
     },
     MemoryRegionExtended {
         base_address: 0x000F_0000,
         length: 0x0001_0000,
-        region_type: MemoryRegionType::Reserved,
-        extended_attributes: 0,
+        region_type: MemoryRegionType::Reserved as u32,
+        extended_attributes: ATTRIBUTE_ENABLED,
     },
     MemoryRegionExtended {
         base_address: 0x0010_0000,
         length: 0x07EE_0000,
-        region_type: MemoryRegionType::Usable,
-        extended_attributes: 0,
+        region_type: MemoryRegionType::Usable as u32,
+        extended_attributes: ATTRIBUTE_ENABLED,
     },
     MemoryRegionExtended {
         base_address: 0x07FE_0000,
         length: 0x0000_3000,
-        region_type: MemoryRegionType::Reclaimable,
-        extended_attributes: 0,
+        region_type: MemoryRegionType::Reclaimable as u32,
+        extended_attributes: ATTRIBUTE_ENABLED,
     },
     MemoryRegionExtended {
         base_address: 0x07FE_4000,
         length: 0x0001_C000,
-        region_type: MemoryRegionType::Reserved,
-        extended_attributes: 0,
+        region_type: MemoryRegionType::Reserved as u32,
+        extended_attributes: ATTRIBUTE_ENABLED,
     },
     MemoryRegionExtended {
         base_address: 0x0800_0000,
         length: 0x0100_0000,
-        region_type: MemoryRegionType::UserEnterd,
-        extended_attributes: 0,
+        region_type: MemoryRegionType::UserEnterd as u32,
+        extended_attributes: ATTRIBUTE_ENABLED,
     },
 ];
 
-static mut MOCK_MEMORY_MAP: [MemoryRegion; 32] =
-    [MemoryRegion::default(); 32];
+/// Parse the mock memory map.
+pub fn mock_memory_map() -> MemoryMap {
+    let raw = Box::leak(Box::new(unsafe { MOCK_UNPARSED_MEMORY_MAP }));
+    let buf = Box::leak(Box::new([MemoryRegion::default(); 32]));
+
+    MemoryMap::parse_map(raw, buf).unwrap()
+}
+
+/// Build an initialized allocator over the mock memory map.
+///
+/// The allocator is boxed because the freelist heads are stored inline,
+/// and the allocator must not move after it was initialized.
+fn mock_allocator() -> Box<BuddyAllocator<HostPageMap, Page>> {
+    let mmap = mock_memory_map();
+
+    let allocator = Box::new(BuddyAllocator::new(&mmap));
+    allocator.initialize(&BumpAllocations::default(), &mmap);
+
+    allocator
+}
+
+/// A small xorshift generator, so the random sequences are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
 
 #[test]
 fn test_buddy_allocator() {
-    let mmap = unsafe {
-        MemoryMap::parse_map(
-            &mut MOCK_UNPARSED_MEMORY_MAP,
-            &mut MOCK_MEMORY_MAP,
-        )
-        .unwrap()
+    let allocator = mock_allocator();
+
+    let before = allocator.stats();
+    let block = allocator.try_alloc_pages(BuddyOrder::Order1).unwrap();
+    let after = allocator.stats();
+
+    assert_eq!(after.free_pages + 2, before.free_pages);
+
+    unsafe { allocator.free_pages(block) };
+
+    assert_eq!(allocator.stats(), before);
+}
+
+#[test]
+fn test_buddy_absorb_bump_allocations() {
+    let mmap = mock_memory_map();
+
+    // Two adjacent allocations, one that overlaps both of them, one that
+    // is not page aligned, and one far away from the rest.
+    let ranges = [
+        (0x0020_0000, 0x3000),
+        (0x0020_3000, 0x1000),
+        (0x0020_2000, 0x2000),
+        (0x0040_0800, 0x1000),
+        (0x0300_0000, 0x1_0000),
+    ];
+    let mut allocations = BumpAllocations::default();
+    for (base, size) in ranges {
+        allocations.write(Allocation {
+            layout: Layout::from_size_align(size, 8).unwrap(),
+            base: VirtualAddress::from(base),
+        });
+    }
+
+    let empty = mock_allocator();
+    let allocator =
+        Box::new(BuddyAllocator::<HostPageMap, Page>::new(&mmap));
+    allocator.initialize(&allocations, &mmap);
+
+    // 4 pages of the adjacent ones, 2 of the unaligned one and 16 of the
+    // last one.
+    assert_eq!(
+        allocator.stats().free_pages + 22,
+        empty.stats().free_pages
+    );
+
+    while let Ok(block) = allocator.try_alloc_pages(BuddyOrder::Order0) {
+        let address = allocator.address_of(block).as_usize();
+        for (base, size) in ranges {
+            let start = base & !(REGULAR_PAGE_SIZE - 1);
+            assert!(
+                !(start..base + size).contains(&address),
+                "Page {:#x} of a bump allocation is free",
+                address
+            );
+        }
+    }
+}
+
+#[test]
+fn test_buddy_stats() {
+    let allocator = mock_allocator();
+    let before = allocator.stats();
+
+    assert_eq!(before.free_pages + before.used_pages, before.total_pages);
+    assert_eq!(before.largest_free_block, Some(BuddyOrder::MAX));
+    assert_eq!(before.fragmentation_index(BuddyOrder::Order0), 0);
+
+    let block = allocator.try_alloc_pages(BuddyOrder::Order1).unwrap();
+    let after = allocator.stats();
+    assert_eq!(after.used_pages, before.used_pages + 2);
+    assert_eq!(after.total_pages, before.total_pages);
+    unsafe { allocator.free_pages(block) };
+
+    // Once every block of the largest order is taken, only smaller blocks
+    // are left, and none of the free memory can hold the largest order.
+    let mut blocks = Vec::new();
+    while let Ok(block) = allocator.try_alloc_pages(BuddyOrder::MAX) {
+        blocks.push(block);
+    }
+    let drained = allocator.stats();
+    assert!(drained.free_pages > 0);
+    assert!(drained.largest_free_block < Some(BuddyOrder::MAX));
+    assert_eq!(drained.fragmentation_index(BuddyOrder::MAX), 100);
+    assert_eq!(drained.fragmentation_index(BuddyOrder::Order0), 0);
+
+    for block in blocks {
+        unsafe { allocator.free_pages(block) };
+    }
+    assert_eq!(allocator.stats(), before);
+}
+
+#[test]
+fn test_buddy_random_alloc_free() {
+    for seed in [0x2545_f491_4f6c_dd1d, 0x9e37_79b9_7f4a_7c15, 0xdead_beef]
+    {
+        let allocator = mock_allocator();
+        let initial = allocator.stats();
+        let mut rng = Rng(seed);
+        let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+
+        for step in 0..4000 {
+            if live.is_empty() || rng.below(3) != 0 {
+                let order = rng.below(BuddyOrder::MAX as usize + 1);
+                let layout = Layout::from_size_align(
+                    REGULAR_PAGE_SIZE << order,
+                    REGULAR_PAGE_SIZE,
+                )
+                .unwrap();
+
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    continue;
+                }
+
+                let (start, end) =
+                    (ptr.addr(), ptr.addr() + layout.size());
+                for (other, other_layout, _) in &live {
+                    let other_end = other.addr() + other_layout.size();
+                    assert!(
+                        end <= other.addr() || other_end <= start,
+                        "Block {:x?} overlaps {:x?}",
+                        start..end,
+                        other.addr()..other_end
+                    );
+                }
+
+                let tag = step as u8;
+                unsafe { ptr.write_bytes(tag, layout.size()) };
+                live.push((ptr, layout, tag));
+            } else {
+                let (ptr, layout, tag) =
+                    live.swap_remove(rng.below(live.len()));
+
+                let memory = unsafe {
+                    std::slice::from_raw_parts(ptr, layout.size())
+                };
+                assert!(memory.iter().all(|&b| b == tag));
+
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+
+            let live_pages: usize = live
+                .iter()
+                .map(|(_, layout, _)| layout.size() / REGULAR_PAGE_SIZE)
+                .sum();
+            let stats = allocator.stats();
+            assert_eq!(stats.free_pages + live_pages, initial.free_pages);
+            assert_eq!(stats.total_pages, initial.total_pages);
+        }
+
+        for (ptr, layout, _) in live.drain(..) {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+
+        assert_eq!(allocator.stats(), initial);
+    }
+}
+
+#[test]
+fn test_buddy_alloc_below() {
+    let allocator = mock_allocator();
+    let limit = PhysicalAddress::from(0x0020_0000usize);
+
+    let before = allocator.stats();
+    let blocks: Vec<_> = (0..8)
+        .map(|_| {
+            allocator
+                .try_alloc_pages_below(BuddyOrder::Order4, limit)
+                .unwrap()
+        })
+        .collect();
+
+    for block in blocks.iter() {
+        let end = allocator.address_of(*block).as_usize()
+            + (REGULAR_PAGE_SIZE << BuddyOrder::Order4 as usize);
+        assert!(end <= limit.as_usize());
+    }
+
+    for block in blocks {
+        unsafe { allocator.free_pages(block) };
+    }
+
+    assert_eq!(allocator.stats(), before);
+}
+
+#[test]
+fn test_buddy_alloc_zones() {
+    let allocator = mock_allocator();
+    let before = allocator.stats();
+
+    // The mock memory map ends below 4GiB, so there is no normal memory.
+    let dma = before.zones[Zone::Dma as usize];
+    assert_eq!(dma.total_pages, 16 * MiB / REGULAR_PAGE_SIZE);
+    assert_eq!(before.zones[Zone::Normal as usize].total_pages, 0);
+    assert_eq!(
+        before.zones.iter().map(|z| z.free_pages).sum::<usize>(),
+        before.free_pages
+    );
+
+    // A normal allocation falls back to the highest zone with memory.
+    let block = allocator.try_alloc_pages(BuddyOrder::Order0).unwrap();
+    assert_eq!(allocator.zone_of(block), Zone::Dma32);
+
+    let mut blocks = vec![block];
+    while let Ok(block) =
+        allocator.try_alloc_pages_in(BuddyOrder::Order0, Zone::Dma)
+    {
+        assert!(allocator.address_of(block).as_usize() < 16 * MiB);
+        blocks.push(block);
+    }
+
+    // The DMA zone never falls back to a higher zone.
+    assert_eq!(blocks.len(), dma.free_pages + 1);
+    assert_eq!(allocator.stats().zones[Zone::Dma as usize].free_pages, 0);
+
+    let block = allocator.try_alloc_pages(BuddyOrder::Order3).unwrap();
+    assert_eq!(allocator.zone_of(block), Zone::Dma32);
+    blocks.push(block);
+
+    for block in blocks {
+        unsafe { allocator.free_pages(block) };
+    }
+
+    assert_eq!(allocator.stats(), before);
+}
+
+#[test]
+fn test_buddy_release_acpi_memory() {
+    let mmap = mock_memory_map();
+
+    let allocator =
+        Box::new(BuddyAllocator::<HostPageMap, Page>::new(&mmap));
+    allocator.initialize(&BumpAllocations::default(), &mmap);
+
+    let before = allocator.stats();
+    let released = unsafe { allocator.release_acpi_memory(&mmap) };
+
+    // The mock map has three pages of ACPI reclaimable memory.
+    assert_eq!(released, 3);
+    assert_eq!(allocator.stats().free_pages, before.free_pages + 3);
+    assert!(
+        mmap.regions
+            .read()
+            .iter()
+            .all(|r| r.region_type != MemoryRegionType::Reclaimable)
+    );
+}
+
+/// The size of the last layout that reached [`record_oom`].
+static OOM_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+fn record_oom(layout: Layout) {
+    OOM_SIZE.store(layout.size(), Ordering::Relaxed);
+}
+
+#[test]
+fn test_buddy_oom_hook() {
+    let allocator = mock_allocator();
+    allocator.set_oom_hook(record_oom);
+
+    let mut blocks = Vec::new();
+    while let Ok(block) = allocator.try_alloc_pages(BuddyOrder::MAX) {
+        blocks.push(block);
+    }
+
+    // A failed page allocation is returned to the caller, which may
+    // handle it.
+    assert_eq!(OOM_SIZE.load(Ordering::Relaxed), 0);
+
+    let layout = Layout::from_size_align(
+        REGULAR_PAGE_SIZE << BuddyOrder::MAX as usize,
+        REGULAR_PAGE_SIZE,
+    )
+    .unwrap();
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+    assert_eq!(OOM_SIZE.load(Ordering::Relaxed), layout.size());
+
+    for block in blocks {
+        unsafe { allocator.free_pages(block) };
+    }
+}
+
+/// The allocator of [`test_buddy_pressure_hook`], and a block that its
+/// pressure hook frees.
+static PRESSURE_ALLOCATOR: AtomicPtr<BuddyAllocator<HostPageMap, Page>> =
+    AtomicPtr::new(ptr::null_mut());
+static PRESSURE_BLOCK: AtomicPtr<Page> = AtomicPtr::new(ptr::null_mut());
+
+fn release_block() -> usize {
+    let Some(block) = NonNull::new(
+        PRESSURE_BLOCK.swap(ptr::null_mut(), Ordering::Relaxed),
+    ) else {
+        return 0;
     };
 
-    println!("{}", mmap);
-    let mut allocator = BuddyAllocator::<PageMap, Page>::new(&mmap);
+    let allocator = PRESSURE_ALLOCATOR.load(Ordering::Relaxed);
+    unsafe { (*allocator).free_pages(block) };
+    1
+}
+
+#[test]
+fn test_buddy_pressure_hook() {
+    let mut allocator = mock_allocator();
+    PRESSURE_ALLOCATOR.store(&mut *allocator, Ordering::Relaxed);
+    allocator.set_pressure_hook(release_block);
+
+    let stashed = allocator.try_alloc_pages(BuddyOrder::Order0).unwrap();
+
+    let mut blocks = Vec::new();
+    for order in (0..=BuddyOrder::MAX as u8).rev() {
+        while let Ok(block) =
+            allocator.try_alloc_pages(BuddyOrder::from(order))
+        {
+            blocks.push(block);
+        }
+    }
+
+    // The hook is asked to release memory before an allocation fails,
+    // and the allocation is retried with the memory it released.
+    PRESSURE_BLOCK.store(stashed.as_ptr(), Ordering::Relaxed);
+
+    let block = allocator.try_alloc_pages(BuddyOrder::Order0).unwrap();
+    assert_eq!(block, stashed);
+    assert!(allocator.try_alloc_pages(BuddyOrder::Order0).is_err());
+
+    blocks.push(block);
+    for block in blocks {
+        unsafe { allocator.free_pages(block) };
+    }
+}
+
+#[test]
+fn test_page_mapping_count() {
+    let allocator = mock_allocator();
+    let block = allocator.try_alloc_pages(BuddyOrder::Order0).unwrap();
+    let mapping = unsafe { &block.as_ref().mapping };
+
+    assert_eq!(mapping.count(), 0);
+    assert!(mapping.try_unshare());
+
+    // The original mapping and two copy-on-write mappings.
+    mapping.share();
+    mapping.share();
+    assert_eq!(mapping.count(), 3);
+
+    assert!(!mapping.try_unshare());
+    mapping.release();
+    assert!(!mapping.try_unshare());
+    mapping.release();
+
+    assert!(mapping.try_unshare());
+    assert_eq!(mapping.count(), 0);
+
+    unsafe { allocator.free_pages(block) };
+}
+
+#[test]
+#[should_panic(expected = "Double free")]
+fn test_buddy_double_free() {
+    let allocator = mock_allocator();
+    let block = allocator.try_alloc_pages(BuddyOrder::Order0).unwrap();
+
+    unsafe {
+        allocator.free_pages(block);
+        allocator.free_pages(block);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Use after free")]
+fn test_buddy_use_after_free() {
+    let allocator = mock_allocator();
+    let block = allocator.try_alloc_pages(BuddyOrder::Order0).unwrap();
+    let ptr = allocator.pointer_of(block);
+
+    unsafe {
+        allocator.free_pages(block);
+        ptr.write(0);
+    }
 
-    let p = allocator.alloc_pages(2);
-    println!("{:?}", p);
+    // The freed block is merged back, and handed out again.
+    let _ = allocator.try_alloc_pages(BuddyOrder::Order0);
 }
@@ -0,0 +1,79 @@
+use std::ptr::NonNull;
+
+use common::error::HpetError;
+use x86::hpet::{Hpet, HpetTimerConfiguration};
+
+/// A period of 10ns, which is a 100MHz main counter.
+const PERIOD_FS: u64 = 10_000_000;
+
+/// Returns the registers of an HPET with three comparators, where only
+/// the first two are periodic and can be routed to the I/O APIC.
+fn registers() -> NonNull<u64> {
+    let registers = Box::leak(Box::new([0u64; 128]));
+    registers[0] = PERIOD_FS << 32 | 2 << 8;
+    // Periodic, and routable to the inputs 20 and 21.
+    registers[0x100 / 8] = 0x30_0000 << 32 | 1 << 4;
+    registers[0x120 / 8] = 0x30_0000 << 32 | 1 << 4;
+    NonNull::from(registers).cast()
+}
+
+fn read(registers: NonNull<u64>, offset: usize) -> u64 {
+    unsafe { registers.byte_add(offset).read_volatile() }
+}
+
+#[test]
+fn test_hpet_capabilities() {
+    let registers = registers();
+    unsafe { registers.byte_add(0x10).write_volatile(0b11) };
+    let hpet = unsafe { Hpet::new(registers) };
+
+    assert_eq!(hpet.timers(), 3);
+    assert_eq!(hpet.frequency(), 100_000_000);
+    assert_eq!(hpet.counts_to_ns(1_000), 10_000);
+    assert_eq!(hpet.ns_to_counts(1_000_000), 100_000);
+    // The legacy route is disabled and the main counter is started.
+    assert_eq!(read(registers, 0x10), 0b01);
+}
+
+#[test]
+fn test_hpet_periodic() {
+    let registers = registers();
+    let hpet = unsafe { Hpet::new(registers) };
+
+    unsafe { hpet.start_periodic(1, 1_000_000).unwrap() };
+    let configuration =
+        HpetTimerConfiguration::from(read(registers, 0x120));
+    assert!(configuration.is_interrupt_enable());
+    assert!(configuration.is_periodic());
+    assert!(!configuration.is_level_triggered());
+    assert_eq!(configuration.get_io_apic_route(), 20);
+    // The last write to the comparator is the period.
+    assert_eq!(read(registers, 0x128), 100_000);
+
+    hpet.stop(1).unwrap();
+    let configuration =
+        HpetTimerConfiguration::from(read(registers, 0x120));
+    assert!(!configuration.is_interrupt_enable());
+    assert!(!configuration.is_periodic());
+}
+
+#[test]
+fn test_hpet_timer_errors() {
+    let registers = registers();
+    let hpet = unsafe { Hpet::new(registers) };
+
+    assert!(matches!(
+        unsafe { hpet.start_periodic(2, 1_000) },
+        Err(HpetError::Unroutable(2))
+    ));
+    assert!(matches!(
+        unsafe { hpet.start_one_shot(3, 1_000) },
+        Err(HpetError::UnknownTimer(3))
+    ));
+
+    unsafe { hpet.start_one_shot(0, 1_000).unwrap() };
+    let configuration =
+        HpetTimerConfiguration::from(read(registers, 0x100));
+    assert!(!configuration.is_periodic());
+    assert_eq!(read(registers, 0x108), 1_000);
+}
@@ -2,13 +2,25 @@
 #![feature(const_trait_impl)]
 #![feature(const_convert)]
 #![feature(const_result_trait_fn)]
+#![feature(allocator_api)]
+#![feature(specialization)]
+#![allow(incomplete_features)]
 
 use macros::bitfields;
 
+mod alloc_tracking;
 mod buddy;
+mod hpet;
+mod memory_map;
+mod slab;
 mod test;
+mod time;
 use test::{Nested, Test};
 
+/// Route the kernel print macros to the standard output.
+#[unsafe(no_mangle)]
+pub fn kprint(args: std::fmt::Arguments<'_>) { print!("{}", args) }
+
 fn main() {
     println!("Hello, world!");
 
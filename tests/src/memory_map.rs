@@ -0,0 +1,235 @@
+use common::{constants::INIT_AREA_SIZE_BYTES, enums::MemoryRegionType};
+use x86::memory_map::{
+    ATTRIBUTE_ENABLED, ATTRIBUTE_NON_VOLATILE, MemoryMap, MemoryMapError,
+    MemoryRegion, MemoryRegionExtended,
+};
+
+use MemoryRegionType::*;
+
+/// An enabled entry of the given E820 type.
+fn entry(
+    base_address: u64,
+    length: u64,
+    e820: u32,
+) -> MemoryRegionExtended {
+    MemoryRegionExtended {
+        base_address,
+        length,
+        region_type: e820,
+        extended_attributes: ATTRIBUTE_ENABLED,
+    }
+}
+
+fn region(
+    base_address: u64,
+    end: u64,
+    region_type: MemoryRegionType,
+) -> MemoryRegion {
+    MemoryRegion {
+        base_address,
+        length: end - base_address,
+        region_type,
+    }
+}
+
+fn parse(
+    raw: &[MemoryRegionExtended],
+) -> Result<Vec<MemoryRegion>, MemoryMapError> {
+    let raw = Box::leak(raw.to_vec().into_boxed_slice());
+    let buf = Box::leak(Box::new([MemoryRegion::default(); 32]));
+
+    let mmap = MemoryMap::parse_map(raw, buf)?;
+    Ok(mmap.regions.read().to_vec())
+}
+
+/// The regions that the init area is carved from the first usable region
+/// at address zero.
+fn low_memory(end: u64) -> [MemoryRegion; 2] {
+    [
+        region(0, INIT_AREA_SIZE_BYTES, UserEnterd),
+        region(INIT_AREA_SIZE_BYTES, end, Usable),
+    ]
+}
+
+#[test]
+fn test_parse_sorted_map() {
+    let regions = parse(&[
+        entry(0x0000_0000, 0x0009_FC00, 1),
+        entry(0x0009_FC00, 0x0000_0400, 2),
+        entry(0x000F_0000, 0x0001_0000, 2),
+        entry(0x0010_0000, 0x07EE_0000, 1),
+        entry(0x07FE_0000, 0x0002_0000, 2),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        regions,
+        [
+            &low_memory(0x0009_FC00)[..],
+            &[
+                region(0x0009_FC00, 0x000A_0000, Reserved),
+                region(0x000A_0000, 0x000F_0000, Filler),
+                region(0x000F_0000, 0x0010_0000, Reserved),
+                region(0x0010_0000, 0x07FE_0000, Usable),
+                region(0x07FE_0000, 0x0800_0000, Reserved),
+            ],
+        ]
+        .concat()
+    );
+}
+
+#[test]
+fn test_parse_unsorted_map() {
+    let regions = parse(&[
+        entry(0x0010_0000, 0x07EE_0000, 1),
+        entry(0x000F_0000, 0x0001_0000, 2),
+        entry(0x07FE_0000, 0x0002_0000, 3),
+        entry(0x0000_0000, 0x0009_FC00, 1),
+    ])
+    .unwrap();
+
+    assert!(regions.is_sorted_by_key(|r| r.base_address));
+    assert_eq!(
+        regions.last(),
+        Some(&region(0x07FE_0000, 0x0800_0000, Reclaimable))
+    );
+}
+
+#[test]
+fn test_parse_overlapping_map() {
+    // A usable entry that runs into the EBDA, and a reserved hole that
+    // some firmwares report inside of a usable entry.
+    let regions = parse(&[
+        entry(0x0000_0000, 0x000A_0000, 1),
+        entry(0x0009_F000, 0x0000_1000, 2),
+        entry(0x0010_0000, 0x3FF0_0000, 1),
+        entry(0x0100_0000, 0x0010_0000, 2),
+        entry(0x0200_0000, 0x0010_0000, 3),
+        entry(0x0200_0000, 0x0010_0000, 5),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        regions,
+        [
+            &low_memory(0x0009_F000)[..],
+            &[
+                region(0x0009_F000, 0x000A_0000, Reserved),
+                region(0x000A_0000, 0x0010_0000, Filler),
+                region(0x0010_0000, 0x0100_0000, Usable),
+                region(0x0100_0000, 0x0110_0000, Reserved),
+                region(0x0110_0000, 0x0200_0000, Usable),
+                region(0x0200_0000, 0x0210_0000, BadMemory),
+                region(0x0210_0000, 0x4000_0000, Usable),
+            ],
+        ]
+        .concat()
+    );
+}
+
+#[test]
+fn test_parse_merges_adjacent_regions() {
+    let regions = parse(&[
+        entry(0x0000_0000, 0x0008_0000, 1),
+        entry(0x0008_0000, 0x0001_F000, 1),
+        entry(0x0008_0000, 0x0001_F000, 1),
+        entry(0x0010_0000, 0x0010_0000, 2),
+        entry(0x0020_0000, 0x0010_0000, 2),
+        entry(0x0010_0000, 0x0000_0000, 5),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        regions,
+        [
+            &low_memory(0x0009_F000)[..],
+            &[
+                region(0x0009_F000, 0x0010_0000, Filler),
+                region(0x0010_0000, 0x0030_0000, Reserved),
+            ],
+        ]
+        .concat()
+    );
+}
+
+#[test]
+fn test_parse_extended_attributes() {
+    let mut disabled = entry(0x0010_0000, 0x0010_0000, 2);
+    disabled.extended_attributes = 0;
+
+    let mut persistent = entry(0x0020_0000, 0x0010_0000, 1);
+    persistent.extended_attributes |= ATTRIBUTE_NON_VOLATILE;
+
+    let regions = parse(&[
+        entry(0x0000_0000, 0x0010_0000, 1),
+        disabled,
+        persistent,
+        // Persistent memory and an ACPI 6 type the kernel does not know.
+        entry(0x0030_0000, 0x0010_0000, 7),
+        entry(0x0040_0000, 0x0010_0000, 12),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        regions,
+        [
+            &low_memory(0x0010_0000)[..],
+            &[region(0x0010_0000, 0x0020_0000, Filler)],
+            &[region(0x0020_0000, 0x0050_0000, Reserved)],
+        ]
+        .concat()
+    );
+}
+
+#[test]
+fn test_parse_end_of_address_space() {
+    let regions = parse(&[
+        entry(0x0000_0000, 0x0010_0000, 1),
+        entry(0xFFFF_FFFF_FFFF_0000, 0x0010_0000, 2),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        regions.last(),
+        Some(&region(0xFFFF_FFFF_FFFF_0000, u64::MAX, Reserved))
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(matches!(parse(&[]), Err(MemoryMapError::Empty)));
+    assert!(matches!(
+        parse(&[entry(0, 0x0010_0000, 2)]),
+        Err(MemoryMapError::Empty)
+    ));
+
+    let fragmented: Vec<_> = (0..64)
+        .map(|n| entry(n * 0x0010_0000, 0x0010_0000, 1 + n as u32 % 2))
+        .collect();
+    assert!(matches!(parse(&fragmented), Err(MemoryMapError::Overflow)));
+}
+
+#[test]
+fn test_reclaim_acpi() {
+    let raw = Box::leak(Box::new([
+        entry(0x0000_0000, 0x0010_0000, 1),
+        entry(0x0010_0000, 0x0010_0000, 3),
+        entry(0x0020_0000, 0x0010_0000, 1),
+        entry(0x0030_0000, 0x0000_1000, 4),
+    ]));
+    let buf = Box::leak(Box::new([MemoryRegion::default(); 32]));
+    let mmap = MemoryMap::parse_map(raw, buf).unwrap();
+
+    let mut released = Vec::new();
+    mmap.reclaim_acpi(|region| released.push(*region));
+
+    assert_eq!(released, [region(0x0010_0000, 0x0020_0000, Reclaimable)]);
+    assert_eq!(
+        mmap.regions.read().to_vec(),
+        [
+            &low_memory(0x0030_0000)[..],
+            &[region(0x0030_0000, 0x0030_1000, ACPINVS)],
+        ]
+        .concat()
+    );
+}
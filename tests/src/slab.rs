@@ -0,0 +1,357 @@
+use std::{
+    alloc::{Allocator, Layout},
+    collections::HashSet,
+    ptr::NonNull,
+    sync::{
+        Mutex, MutexGuard, Once, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use buddy::BuddyAllocator;
+use common::{
+    alloc::BumpAllocations, constants::REGULAR_PAGE_SIZE,
+    enums::BuddyOrder, late_init::LateInit,
+};
+use page::{Page, host::HostPageMap};
+use slab::{
+    Generic64, Generic512, SLAB_ALLOCATOR,
+    cache::SlabCache,
+    descriptor::PreallocatedObject,
+    traits::{Slab, SlabCacheConstructor, SlabPosition},
+};
+use x86::structures::paging::PageTable;
+
+use crate::buddy::mock_memory_map;
+
+/// The buddy allocator that the slabs take their pages from.
+#[unsafe(no_mangle)]
+static BUDDY_ALLOCATOR: LateInit<BuddyAllocator<HostPageMap, Page>> =
+    LateInit::uninit();
+
+/// Serializes the tests that use the global allocators.
+static GLOBAL_ALLOCATORS: Mutex<()> = Mutex::new(());
+
+/// Initialize the global allocators once, and lock them for the test.
+pub fn global_allocators() -> MutexGuard<'static, ()> {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        let mmap = mock_memory_map();
+        BUDDY_ALLOCATOR.init(BuddyAllocator::new(&mmap));
+        BUDDY_ALLOCATOR.initialize(&BumpAllocations::default(), &mmap);
+        SLAB_ALLOCATOR.init();
+    });
+
+    GLOBAL_ALLOCATORS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the amount of objects on a slab of the cache.
+pub fn objects_per_slab<T: Slab>(cache: &SlabCache<T>) -> usize {
+    (REGULAR_PAGE_SIZE << cache.buddy_order)
+        / size_of::<PreallocatedObject<T>>()
+}
+
+/// Returns the amount of slabs on a list of the cache.
+pub fn slabs_on<T: Slab>(
+    list: Option<NonNull<slab::descriptor::SlabDescriptor<T>>>,
+) -> usize {
+    let mut count = 0;
+    let mut next = list;
+    while let Some(slab) = next {
+        count += 1;
+        next = unsafe { slab.as_ref().next };
+    }
+    count
+}
+
+#[test]
+fn test_slab_alloc_free() {
+    let _guard = global_allocators();
+
+    let a = SLAB_ALLOCATOR.kmalloc::<Generic64>().unwrap();
+    let b = SLAB_ALLOCATOR.kmalloc::<Generic64>().unwrap();
+    assert_ne!(a, b);
+    assert!(a.is_aligned() && b.is_aligned());
+
+    unsafe {
+        a.write(Generic64([1; 8]));
+        b.write(Generic64([2; 8]));
+        assert_eq!(a.read().0, [1; 8]);
+        assert_eq!(b.read().0, [2; 8]);
+
+        SLAB_ALLOCATOR.kfree(b);
+    }
+
+    // The last freed object is the first one that is handed out again.
+    let c = SLAB_ALLOCATOR.kmalloc::<Generic64>().unwrap();
+    assert_eq!(c, b);
+
+    unsafe {
+        SLAB_ALLOCATOR.kfree(a);
+        SLAB_ALLOCATOR.kfree(c);
+    }
+}
+
+#[test]
+fn test_slab_growth() {
+    let _guard = global_allocators();
+
+    // The cache is boxed, because its slabs point back to it.
+    let mut cache = Box::new(SlabCache::<Generic512>::new(0));
+    let per_slab = objects_per_slab(&cache);
+
+    let objects: Vec<_> =
+        (0..per_slab).map(|_| cache.alloc().unwrap()).collect();
+    assert_eq!(slabs_on(cache.full), 1);
+    assert_eq!(slabs_on(cache.partial), 0);
+
+    // A full cache grows by a slab.
+    let extra = cache.alloc().unwrap();
+    assert_eq!(slabs_on(cache.full), 1);
+    assert_eq!(slabs_on(cache.partial), 1);
+
+    let blocks: HashSet<_> = objects
+        .iter()
+        .map(|object| BUDDY_ALLOCATOR.block_of(object.cast()).unwrap())
+        .collect();
+    assert_eq!(blocks.len(), 1);
+    assert!(
+        !blocks.contains(&BUDDY_ALLOCATOR.block_of(extra.cast()).unwrap())
+    );
+
+    for object in objects.into_iter().chain([extra]) {
+        unsafe { cache.dealloc(object) };
+    }
+    assert_eq!(slabs_on(cache.full), 0);
+    assert_eq!(slabs_on(cache.partial), 0);
+    assert_eq!(slabs_on(cache.free), 2);
+}
+
+#[test]
+fn test_slab_reap_watermarks() {
+    let _guard = global_allocators();
+
+    let mut cache = Box::new(SlabCache::<Generic512>::new(0));
+    cache.set_watermarks(1, 2);
+    let per_slab = objects_per_slab(&cache);
+
+    let objects: Vec<_> =
+        (0..per_slab * 4).map(|_| cache.alloc().unwrap()).collect();
+    assert_eq!(slabs_on(cache.full), 4);
+
+    let before = BUDDY_ALLOCATOR.stats();
+
+    // Once a third slab is free, the cache is reaped down to a single
+    // free slab, and the fourth slab is kept as well.
+    for object in objects {
+        unsafe { cache.dealloc(object) };
+    }
+    assert_eq!(cache.free_slabs, 2);
+    assert_eq!(slabs_on(cache.free), 2);
+    assert_eq!(BUDDY_ALLOCATOR.stats().free_pages, before.free_pages + 2);
+
+    assert_eq!(cache.shrink(), 2);
+    assert_eq!(cache.free_slabs, 0);
+    assert_eq!(slabs_on(cache.free), 0);
+    assert_eq!(BUDDY_ALLOCATOR.stats().free_pages, before.free_pages + 4);
+}
+
+/// The amount of pages that the slabs released under memory pressure.
+static RELEASED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+fn shrink_slabs() -> usize {
+    let released = SLAB_ALLOCATOR.shrink();
+    RELEASED_PAGES.fetch_add(released, Ordering::Relaxed);
+    released
+}
+
+#[test]
+fn test_slab_shrink_under_pressure() {
+    let _guard = global_allocators();
+    BUDDY_ALLOCATOR.set_pressure_hook(shrink_slabs);
+
+    // The freed objects leave free slabs in the cache, which are below
+    // its high watermark.
+    let objects: Vec<_> = (0..8)
+        .map(|_| SLAB_ALLOCATOR.kmalloc::<Generic512>().unwrap())
+        .collect();
+    for object in objects {
+        unsafe { SLAB_ALLOCATOR.kfree(object) };
+    }
+
+    // Once the buddy allocator runs out of blocks, it takes the free
+    // slabs back from the caches.
+    let mut blocks = Vec::new();
+    for order in (0..=BuddyOrder::MAX as u8).rev() {
+        while let Ok(block) =
+            BUDDY_ALLOCATOR.try_alloc_pages(BuddyOrder::from(order))
+        {
+            blocks.push(block);
+        }
+    }
+    assert!(RELEASED_PAGES.load(Ordering::Relaxed) > 0);
+    assert_eq!(BUDDY_ALLOCATOR.stats().free_pages, 0);
+    assert_eq!(SLAB_ALLOCATOR.shrink(), 0);
+
+    for block in blocks {
+        unsafe { BUDDY_ALLOCATOR.free_pages(block) };
+    }
+}
+
+#[test]
+fn test_kmalloc_size_classes() {
+    let _guard = global_allocators();
+
+    for (size, align, class) in [
+        (1, 1, 8),
+        (8, 8, 8),
+        (9, 1, 16),
+        (33, 8, 64),
+        (8, 64, 64),
+        (65, 8, 96),
+        (100, 4, 128),
+        (200, 8, 256),
+        (3000, 8, 4096),
+        (5000, 8, 8192),
+        (8192, 4096, 8192),
+    ] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let allocation = SLAB_ALLOCATOR.allocate(layout).unwrap();
+
+        assert_eq!(allocation.len(), class, "Size class of {:?}", layout);
+        assert!(allocation.cast::<u8>().as_ptr().addr() % align == 0);
+
+        unsafe { SLAB_ALLOCATOR.deallocate(allocation.cast(), layout) };
+    }
+
+    let layout = Layout::from_size_align(8193, 8).unwrap();
+    assert!(SLAB_ALLOCATOR.allocate(layout).is_err());
+
+    // The redzones only keep the alignment of the cache type.
+    let layout = Layout::from_size_align(8192, 8192).unwrap();
+    assert!(SLAB_ALLOCATOR.allocate(layout).is_err());
+}
+
+/// An object of a cache with hooks that count how many times they run.
+///
+/// A free object holds the index of the next free object in its first
+/// bytes, so the constructed state is kept after them.
+#[repr(C)]
+struct Counted {
+    link: u64,
+    state: u64,
+}
+
+const CONSTRUCTED_STATE: u64 = 0xc0ff_ee00_c0ff_ee00;
+
+static CONSTRUCTS: AtomicUsize = AtomicUsize::new(0);
+static DESTRUCTS: AtomicUsize = AtomicUsize::new(0);
+
+impl SlabPosition for Counted {
+    const SLAB_POSITION: usize = usize::MAX - 1;
+}
+
+impl Slab for Counted {}
+
+impl SlabCacheConstructor<Counted> for SlabCache<Counted> {
+    const CONSTRUCTED: bool = true;
+
+    fn construct(object: NonNull<Counted>) {
+        CONSTRUCTS.fetch_add(1, Ordering::Relaxed);
+        unsafe {
+            object.write(Counted {
+                link: 0,
+                state: CONSTRUCTED_STATE,
+            })
+        };
+    }
+
+    fn destruct(object: NonNull<Counted>) {
+        assert_eq!(unsafe { object.as_ref() }.state, CONSTRUCTED_STATE);
+        DESTRUCTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_slab_hooks_run_once_per_object() {
+    let _guard = global_allocators();
+
+    let mut cache = Box::new(SlabCache::<Counted>::new(0));
+    let per_slab = objects_per_slab(&cache);
+
+    // Every object of a new slab is constructed before the first one is
+    // handed out.
+    let first = cache.alloc().unwrap();
+    assert_eq!(CONSTRUCTS.load(Ordering::Relaxed), per_slab);
+
+    let objects: Vec<_> = [first]
+        .into_iter()
+        .chain((1..per_slab).map(|_| cache.alloc().unwrap()))
+        .collect();
+    assert_eq!(slabs_on(cache.full), 1);
+
+    for _ in 0..2 {
+        for object in &objects {
+            let object = unsafe { object.as_ref() };
+            assert_eq!(object.link, 0);
+            assert_eq!(object.state, CONSTRUCTED_STATE);
+        }
+        for &object in &objects {
+            unsafe { cache.dealloc(object) };
+        }
+
+        // Freed objects keep their state, and are handed out again
+        // without being constructed or destructed.
+        for (object, again) in objects
+            .iter()
+            .rev()
+            .zip((0..per_slab).map(|_| cache.alloc().unwrap()))
+        {
+            assert_eq!(*object, again);
+        }
+    }
+    assert_eq!(CONSTRUCTS.load(Ordering::Relaxed), per_slab);
+    assert_eq!(DESTRUCTS.load(Ordering::Relaxed), 0);
+
+    for object in objects {
+        unsafe { cache.dealloc(object) };
+    }
+
+    // The objects are destructed when their slab is released.
+    assert_eq!(cache.shrink(), 1);
+    assert_eq!(CONSTRUCTS.load(Ordering::Relaxed), per_slab);
+    assert_eq!(DESTRUCTS.load(Ordering::Relaxed), per_slab);
+}
+
+#[test]
+fn test_page_table_cache() {
+    let _guard = global_allocators();
+
+    let is_empty = |table: NonNull<PageTable>| unsafe {
+        std::slice::from_raw_parts(
+            table.cast::<u8>().as_ptr(),
+            size_of::<PageTable>(),
+        )
+        .iter()
+        .all(|&byte| byte == 0)
+    };
+
+    let table = slab::alloc_page_table().unwrap();
+    assert!(table.cast::<u8>().as_ptr().addr() % REGULAR_PAGE_SIZE == 0);
+    assert!(is_empty(table));
+
+    // A table that still has entries is cleared when it is freed.
+    unsafe {
+        table.cast::<u8>().write_bytes(0xff, size_of::<PageTable>());
+        slab::free_page_table(table);
+    }
+
+    let again = slab::alloc_page_table().unwrap();
+    assert_eq!(again, table);
+    assert!(is_empty(again));
+
+    unsafe { slab::free_page_table(again) };
+}
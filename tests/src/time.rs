@@ -0,0 +1,85 @@
+use std::ptr::NonNull;
+
+use common::error::TimerError;
+use libk::time::{
+    CLOCK, Clock, ClockSource, Duration, Instant, wait_until,
+};
+use x86::hpet::Hpet;
+
+/// The offset of the main counter of the HPET.
+const MAIN_COUNTER: usize = 0xf0;
+
+/// Returns the registers of an HPET with a 100MHz 64 bit main counter.
+fn hpet_registers() -> NonNull<u64> {
+    let registers = Box::leak(Box::new([0u64; 128]));
+    registers[0] = 10_000_000 << 32 | 1 << 13;
+    NonNull::from(registers).cast()
+}
+
+#[test]
+fn test_instant_arithmetic() {
+    let earlier = Instant::from_nanos(1_000);
+    let later = earlier + Duration::from_micros(2);
+
+    assert_eq!(later.as_nanos(), 3_000);
+    assert_eq!(later - earlier, Duration::from_nanos(2_000));
+    assert_eq!(earlier.duration_since(later), Duration::ZERO);
+    assert!(earlier < later);
+    assert_eq!(
+        Instant::from_nanos(u64::MAX).checked_add(Duration::from_nanos(1)),
+        None
+    );
+    assert_eq!(earlier.checked_add(Duration::MAX), None);
+}
+
+#[test]
+fn test_hpet_clock() {
+    let registers = hpet_registers();
+    let hpet = Box::leak(Box::new(unsafe { Hpet::new(registers) }));
+    CLOCK.init(Clock::new(ClockSource::Hpet(hpet)));
+    let advance = |counts: u64| unsafe {
+        let counter = registers.byte_add(MAIN_COUNTER);
+        counter.write_volatile(counter.read_volatile() + counts);
+    };
+
+    let start = Instant::now();
+    advance(150);
+    assert_eq!(start.elapsed(), Duration::from_nanos(1_500));
+
+    // The condition moves the clock by a millisecond each time it is
+    // checked.
+    let mut checks = 0;
+    let result = wait_until(Duration::from_millis(5), || {
+        advance(100_000);
+        checks += 1;
+        false
+    });
+    assert!(matches!(result, Err(TimerError::TimedOut(_))));
+    assert_eq!(checks, 5);
+
+    let mut checks = 0;
+    wait_until(Duration::from_millis(5), || {
+        advance(100_000);
+        checks += 1;
+        checks == 3
+    })
+    .unwrap();
+    assert_eq!(checks, 3);
+}
+
+#[test]
+fn test_clock_tsc_at() {
+    let clock = Clock::new(ClockSource::Tsc {
+        cycles_per_ms: 2_500_000,
+    });
+
+    // The deadline is rounded up, so the clock reads the instant once the
+    // TSC reaches it.
+    let instant = Instant::from_nanos(1_000_001);
+    assert_eq!(clock.tsc_at(instant), Some(2_500_003));
+    assert_eq!(clock.tsc_at(Instant::from_nanos(1)), Some(3));
+
+    let hpet = Box::leak(Box::new(unsafe { Hpet::new(hpet_registers()) }));
+    let clock = Clock::new(ClockSource::Hpet(hpet));
+    assert_eq!(clock.tsc_at(instant), None);
+}